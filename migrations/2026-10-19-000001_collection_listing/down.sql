@@ -0,0 +1,7 @@
+DROP INDEX collection_versions_semver_idx;
+ALTER TABLE collection_versions
+  DROP COLUMN version_major,
+  DROP COLUMN version_minor,
+  DROP COLUMN version_patch,
+  DROP COLUMN version_prerelease;
+ALTER TABLE collections DROP COLUMN deprecated;
@@ -0,0 +1,17 @@
+ALTER TABLE collections ADD COLUMN deprecated BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE collection_versions
+  ADD COLUMN version_major INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN version_minor INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN version_patch INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN version_prerelease VARCHAR NOT NULL DEFAULT '';
+
+UPDATE collection_versions SET
+  version_major = COALESCE(substring(version from '^(\d+)')::INTEGER, 0),
+  version_minor = COALESCE(substring(version from '^\d+\.(\d+)')::INTEGER, 0),
+  version_patch = COALESCE(substring(version from '^\d+\.\d+\.(\d+)')::INTEGER, 0),
+  version_prerelease = COALESCE(substring(version from '^\d+\.\d+\.\d+-([^+]+)'), '');
+
+CREATE INDEX collection_versions_semver_idx ON collection_versions (
+  collection_id, version_major, version_minor, version_patch, version_prerelease
+);
@@ -0,0 +1,4 @@
+DROP INDEX collection_versions_search_idx;
+ALTER TABLE collection_versions DROP COLUMN search_vector;
+ALTER TABLE collection_versions DROP COLUMN readme;
+DROP TABLE roles;
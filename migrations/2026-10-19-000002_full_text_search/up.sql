@@ -0,0 +1,26 @@
+CREATE TABLE roles (
+  id SERIAL PRIMARY KEY,
+  namespace VARCHAR NOT NULL,
+  name VARCHAR NOT NULL,
+  description TEXT NOT NULL DEFAULT '',
+  tags JSON NOT NULL DEFAULT '[]',
+  readme TEXT NOT NULL DEFAULT '',
+  metadata JSON NOT NULL DEFAULT '{}',
+  search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', namespace || ' ' || name), 'A') ||
+    setweight(to_tsvector('simple', tags::text), 'B') ||
+    setweight(to_tsvector('simple', description), 'C') ||
+    setweight(to_tsvector('simple', readme), 'D')
+  ) STORED,
+  UNIQUE (namespace, name)
+);
+CREATE INDEX roles_search_idx ON roles USING GIN (search_vector);
+
+ALTER TABLE collection_versions ADD COLUMN readme TEXT NOT NULL DEFAULT '';
+ALTER TABLE collection_versions ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+  setweight(to_tsvector('simple', coalesce(metadata->>'namespace', '') || ' ' || coalesce(metadata->>'name', '')), 'A') ||
+  setweight(to_tsvector('simple', coalesce(metadata->>'tags', '')), 'B') ||
+  setweight(to_tsvector('simple', coalesce(metadata->>'description', '')), 'C') ||
+  setweight(to_tsvector('simple', readme), 'D')
+) STORED;
+CREATE INDEX collection_versions_search_idx ON collection_versions USING GIN (search_vector);
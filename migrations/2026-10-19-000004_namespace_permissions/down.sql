@@ -0,0 +1,7 @@
+ALTER TABLE collection_versions
+  DROP COLUMN uploaded_by,
+  DROP COLUMN created_at;
+DROP TABLE namespace_owners;
+DROP TABLE group_members;
+DROP TABLE groups;
+DROP TABLE namespaces;
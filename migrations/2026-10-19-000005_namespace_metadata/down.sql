@@ -0,0 +1,7 @@
+ALTER TABLE namespaces
+  DROP COLUMN company,
+  DROP COLUMN email,
+  DROP COLUMN avatar_url,
+  DROP COLUMN description,
+  DROP COLUMN resources,
+  DROP COLUMN links;
@@ -0,0 +1,13 @@
+ALTER TABLE namespaces
+  ADD COLUMN company VARCHAR NOT NULL DEFAULT '',
+  ADD COLUMN email VARCHAR NOT NULL DEFAULT '',
+  ADD COLUMN avatar_url VARCHAR NOT NULL DEFAULT '',
+  ADD COLUMN description VARCHAR NOT NULL DEFAULT '',
+  ADD COLUMN resources TEXT NOT NULL DEFAULT '',
+  ADD COLUMN links JSON NOT NULL DEFAULT '[]';
+
+INSERT INTO namespaces (name)
+  SELECT namespace FROM collections
+  UNION
+  SELECT namespace FROM roles
+ON CONFLICT (name) DO NOTHING;
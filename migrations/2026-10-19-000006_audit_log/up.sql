@@ -0,0 +1,9 @@
+CREATE TABLE audit_log (
+  id SERIAL PRIMARY KEY,
+  action VARCHAR NOT NULL,
+  target VARCHAR NOT NULL,
+  username VARCHAR NOT NULL,
+  details JSON NOT NULL DEFAULT '{}',
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at);
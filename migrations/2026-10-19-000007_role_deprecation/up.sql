@@ -0,0 +1 @@
+ALTER TABLE roles ADD COLUMN deprecated BOOLEAN NOT NULL DEFAULT false;
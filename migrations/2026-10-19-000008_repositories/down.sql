@@ -0,0 +1,2 @@
+DROP TABLE repository_content;
+DROP TABLE repositories;
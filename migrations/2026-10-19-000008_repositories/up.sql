@@ -0,0 +1,24 @@
+CREATE TABLE repositories (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  description TEXT NOT NULL DEFAULT ''
+);
+
+INSERT INTO repositories (name, description) VALUES
+  ('staging', 'Uploads waiting for approval'),
+  ('published', 'Content served to clients'),
+  ('rejected', 'Uploads that failed approval');
+
+CREATE TABLE repository_content (
+  repository_id INTEGER NOT NULL,
+  collection_version_id INTEGER NOT NULL,
+  PRIMARY KEY (repository_id, collection_version_id),
+  FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE CASCADE,
+  FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE CASCADE
+);
+CREATE INDEX repository_content_version_idx ON repository_content (collection_version_id);
+
+INSERT INTO repository_content (repository_id, collection_version_id)
+  SELECT repositories.id, collection_versions.id
+  FROM repositories, collection_versions
+  WHERE repositories.name = 'published';
@@ -0,0 +1,2 @@
+DROP TABLE repository_version_content;
+DROP TABLE repository_versions;
@@ -0,0 +1,26 @@
+CREATE TABLE repository_versions (
+  id SERIAL PRIMARY KEY,
+  repository_id INTEGER NOT NULL,
+  number INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  UNIQUE (repository_id, number),
+  FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE CASCADE
+);
+
+CREATE TABLE repository_version_content (
+  repository_version_id INTEGER NOT NULL,
+  collection_version_id INTEGER NOT NULL,
+  PRIMARY KEY (repository_version_id, collection_version_id),
+  FOREIGN KEY (repository_version_id) REFERENCES repository_versions(id) ON DELETE CASCADE,
+  FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE CASCADE
+);
+CREATE INDEX repository_version_content_version_idx ON repository_version_content (collection_version_id);
+
+-- Version 0 of each repository is its content as of this migration.
+INSERT INTO repository_versions (repository_id, number)
+  SELECT id, 0 FROM repositories;
+
+INSERT INTO repository_version_content (repository_version_id, collection_version_id)
+  SELECT repository_versions.id, repository_content.collection_version_id
+  FROM repository_versions
+  JOIN repository_content ON repository_content.repository_id = repository_versions.repository_id;
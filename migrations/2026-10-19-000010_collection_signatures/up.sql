@@ -0,0 +1,10 @@
+CREATE TABLE collection_signatures (
+  id SERIAL PRIMARY KEY,
+  collection_version_id INTEGER NOT NULL,
+  signature TEXT NOT NULL,
+  pubkey_fingerprint VARCHAR NOT NULL,
+  signing_service VARCHAR,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  UNIQUE (collection_version_id, pubkey_fingerprint),
+  FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+CREATE TABLE task_states (
+  key VARCHAR PRIMARY KEY,
+  value TEXT NOT NULL,
+  expires_at TIMESTAMP
+);
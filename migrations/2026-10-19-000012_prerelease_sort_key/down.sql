@@ -0,0 +1,9 @@
+ALTER TABLE collection_versions ADD COLUMN version_prerelease VARCHAR NOT NULL DEFAULT '';
+UPDATE collection_versions SET
+  version_prerelease = COALESCE(substring(version from '^\d+\.\d+\.\d+-([^+]+)'), '');
+
+DROP INDEX collection_versions_semver_idx;
+ALTER TABLE collection_versions DROP COLUMN version_prerelease_key;
+CREATE INDEX collection_versions_semver_idx ON collection_versions (
+  collection_id, version_major, version_minor, version_patch, version_prerelease
+);
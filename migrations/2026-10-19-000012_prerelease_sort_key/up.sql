@@ -0,0 +1,20 @@
+-- Prerelease identifiers as `versions::prerelease_key` encodes them, so
+-- that `rc.10` sorts above `rc.9`. The "C" collation compares bytes.
+ALTER TABLE collection_versions
+  ADD COLUMN version_prerelease_key VARCHAR COLLATE "C" NOT NULL DEFAULT '';
+
+UPDATE collection_versions SET version_prerelease_key = (
+  SELECT string_agg(
+    CASE WHEN part ~ '^\d+$' THEN '0' || lpad(part, greatest(length(part), 20), '0')
+    ELSE '1' || part END,
+    ' ' ORDER BY n
+  )
+  FROM unnest(string_to_array(version_prerelease, '.')) WITH ORDINALITY AS p(part, n)
+)
+WHERE version_prerelease <> '';
+
+DROP INDEX collection_versions_semver_idx;
+ALTER TABLE collection_versions DROP COLUMN version_prerelease;
+CREATE INDEX collection_versions_semver_idx ON collection_versions (
+  collection_id, version_major, version_minor, version_patch, version_prerelease_key
+);
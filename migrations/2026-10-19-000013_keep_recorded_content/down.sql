@@ -0,0 +1,4 @@
+ALTER TABLE repository_version_content
+  DROP CONSTRAINT repository_version_content_collection_version_id_fkey,
+  ADD CONSTRAINT repository_version_content_collection_version_id_fkey
+    FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE CASCADE;
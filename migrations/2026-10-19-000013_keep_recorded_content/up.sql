@@ -0,0 +1,6 @@
+-- Repository versions keep what they recorded: a collection version can only
+-- be deleted once it's explicitly taken out of them.
+ALTER TABLE repository_version_content
+  DROP CONSTRAINT repository_version_content_collection_version_id_fkey,
+  ADD CONSTRAINT repository_version_content_collection_version_id_fkey
+    FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE RESTRICT;
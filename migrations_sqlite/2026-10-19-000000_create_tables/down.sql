@@ -0,0 +1,16 @@
+DROP TABLE task_states;
+DROP TABLE collection_signatures;
+DROP TABLE repository_version_content;
+DROP TABLE repository_versions;
+DROP TABLE repository_content;
+DROP TABLE repositories;
+DROP TABLE audit_log;
+DROP TABLE namespace_owners;
+DROP TABLE group_members;
+DROP TABLE groups;
+DROP TABLE namespaces;
+DROP TABLE tokens;
+DROP TABLE users;
+DROP TABLE roles;
+DROP TABLE collection_versions;
+DROP TABLE collections;
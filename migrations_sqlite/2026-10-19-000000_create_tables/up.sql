@@ -0,0 +1,160 @@
+-- The schema `migrations/` builds on Postgres, as of its task_states
+-- migration. Later schema changes need a migration in both directories.
+-- JSON columns hold text, and there is no search_vector: keyword search
+-- matches the same fields with instr() instead.
+CREATE TABLE collections (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  namespace VARCHAR NOT NULL,
+  name VARCHAR NOT NULL,
+  deprecated BOOLEAN NOT NULL DEFAULT FALSE,
+  UNIQUE (namespace, name)
+);
+
+CREATE TABLE collection_versions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  collection_id INTEGER NOT NULL,
+  artifact JSON NOT NULL,
+  version VARCHAR NOT NULL,
+  metadata JSON NOT NULL,
+  version_major INTEGER NOT NULL DEFAULT 0,
+  version_minor INTEGER NOT NULL DEFAULT 0,
+  version_patch INTEGER NOT NULL DEFAULT 0,
+  version_prerelease VARCHAR NOT NULL DEFAULT '',
+  readme TEXT NOT NULL DEFAULT '',
+  uploaded_by VARCHAR,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY (collection_id) REFERENCES collections(id),
+  UNIQUE (collection_id, version)
+);
+CREATE INDEX collection_versions_semver_idx ON collection_versions (
+  collection_id, version_major, version_minor, version_patch, version_prerelease
+);
+
+CREATE TABLE roles (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  namespace VARCHAR NOT NULL,
+  name VARCHAR NOT NULL,
+  description TEXT NOT NULL DEFAULT '',
+  tags JSON NOT NULL DEFAULT '[]',
+  readme TEXT NOT NULL DEFAULT '',
+  metadata JSON NOT NULL DEFAULT '{}',
+  deprecated BOOLEAN NOT NULL DEFAULT FALSE,
+  UNIQUE (namespace, name)
+);
+
+CREATE TABLE users (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  username VARCHAR NOT NULL UNIQUE,
+  is_superuser BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE tokens (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  key_hash VARCHAR NOT NULL UNIQUE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_used_at TIMESTAMP,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE namespaces (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name VARCHAR NOT NULL UNIQUE,
+  company VARCHAR NOT NULL DEFAULT '',
+  email VARCHAR NOT NULL DEFAULT '',
+  avatar_url VARCHAR NOT NULL DEFAULT '',
+  description VARCHAR NOT NULL DEFAULT '',
+  resources TEXT NOT NULL DEFAULT '',
+  links JSON NOT NULL DEFAULT '[]'
+);
+
+CREATE TABLE groups (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name VARCHAR NOT NULL UNIQUE
+);
+
+CREATE TABLE group_members (
+  group_id INTEGER NOT NULL,
+  user_id INTEGER NOT NULL,
+  PRIMARY KEY (group_id, user_id),
+  FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE namespace_owners (
+  namespace_id INTEGER NOT NULL,
+  group_id INTEGER NOT NULL,
+  PRIMARY KEY (namespace_id, group_id),
+  FOREIGN KEY (namespace_id) REFERENCES namespaces(id) ON DELETE CASCADE,
+  FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE
+);
+
+CREATE TABLE audit_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  action VARCHAR NOT NULL,
+  target VARCHAR NOT NULL,
+  username VARCHAR NOT NULL,
+  details JSON NOT NULL DEFAULT '{}',
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at);
+
+CREATE TABLE repositories (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name VARCHAR NOT NULL UNIQUE,
+  description TEXT NOT NULL DEFAULT ''
+);
+
+INSERT INTO repositories (name, description) VALUES
+  ('staging', 'Uploads waiting for approval'),
+  ('published', 'Content served to clients'),
+  ('rejected', 'Uploads that failed approval');
+
+CREATE TABLE repository_content (
+  repository_id INTEGER NOT NULL,
+  collection_version_id INTEGER NOT NULL,
+  PRIMARY KEY (repository_id, collection_version_id),
+  FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE CASCADE,
+  FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE CASCADE
+);
+CREATE INDEX repository_content_version_idx ON repository_content (collection_version_id);
+
+CREATE TABLE repository_versions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  repository_id INTEGER NOT NULL,
+  number INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (repository_id, number),
+  FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE CASCADE
+);
+
+CREATE TABLE repository_version_content (
+  repository_version_id INTEGER NOT NULL,
+  collection_version_id INTEGER NOT NULL,
+  PRIMARY KEY (repository_version_id, collection_version_id),
+  FOREIGN KEY (repository_version_id) REFERENCES repository_versions(id) ON DELETE CASCADE,
+  FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE CASCADE
+);
+CREATE INDEX repository_version_content_version_idx ON repository_version_content (collection_version_id);
+
+-- Version 0 of each repository is its (empty) starting content.
+INSERT INTO repository_versions (repository_id, number)
+  SELECT id, 0 FROM repositories;
+
+CREATE TABLE collection_signatures (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  collection_version_id INTEGER NOT NULL,
+  signature TEXT NOT NULL,
+  pubkey_fingerprint VARCHAR NOT NULL,
+  signing_service VARCHAR,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (collection_version_id, pubkey_fingerprint),
+  FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE CASCADE
+);
+
+CREATE TABLE task_states (
+  key VARCHAR PRIMARY KEY,
+  value TEXT NOT NULL,
+  expires_at TIMESTAMP
+);
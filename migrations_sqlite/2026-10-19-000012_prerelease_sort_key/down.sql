@@ -0,0 +1,12 @@
+ALTER TABLE collection_versions ADD COLUMN version_prerelease VARCHAR NOT NULL DEFAULT '';
+UPDATE collection_versions SET version_prerelease = CASE
+  WHEN instr(version, '-') = 0 THEN ''
+  WHEN instr(version, '+') = 0 THEN substr(version, instr(version, '-') + 1)
+  ELSE substr(version, instr(version, '-') + 1, instr(version, '+') - instr(version, '-') - 1)
+END;
+
+DROP INDEX collection_versions_semver_idx;
+ALTER TABLE collection_versions DROP COLUMN version_prerelease_key;
+CREATE INDEX collection_versions_semver_idx ON collection_versions (
+  collection_id, version_major, version_minor, version_patch, version_prerelease
+);
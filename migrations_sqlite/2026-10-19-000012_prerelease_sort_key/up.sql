@@ -0,0 +1,27 @@
+-- Prerelease identifiers as `versions::prerelease_key` encodes them, so
+-- that `rc.10` sorts above `rc.9`.
+ALTER TABLE collection_versions
+  ADD COLUMN version_prerelease_key VARCHAR NOT NULL DEFAULT '';
+
+UPDATE collection_versions SET version_prerelease_key = (
+  WITH RECURSIVE split(n, part, rest) AS (
+    SELECT 0, '', collection_versions.version_prerelease || '.'
+    UNION ALL
+    SELECT n + 1, substr(rest, 1, instr(rest, '.') - 1), substr(rest, instr(rest, '.') + 1)
+    FROM split WHERE rest <> ''
+  )
+  SELECT group_concat(
+    CASE WHEN part NOT GLOB '*[^0-9]*'
+    THEN '0' || substr('00000000000000000000', length(part) + 1) || part
+    ELSE '1' || part END,
+    ' '
+  )
+  FROM (SELECT part FROM split WHERE n > 0 ORDER BY n)
+)
+WHERE version_prerelease <> '';
+
+DROP INDEX collection_versions_semver_idx;
+ALTER TABLE collection_versions DROP COLUMN version_prerelease;
+CREATE INDEX collection_versions_semver_idx ON collection_versions (
+  collection_id, version_major, version_minor, version_patch, version_prerelease_key
+);
@@ -0,0 +1,11 @@
+CREATE TABLE repository_version_content_new (
+  repository_version_id INTEGER NOT NULL,
+  collection_version_id INTEGER NOT NULL,
+  PRIMARY KEY (repository_version_id, collection_version_id),
+  FOREIGN KEY (repository_version_id) REFERENCES repository_versions(id) ON DELETE CASCADE,
+  FOREIGN KEY (collection_version_id) REFERENCES collection_versions(id) ON DELETE CASCADE
+);
+INSERT INTO repository_version_content_new SELECT * FROM repository_version_content;
+DROP TABLE repository_version_content;
+ALTER TABLE repository_version_content_new RENAME TO repository_version_content;
+CREATE INDEX repository_version_content_version_idx ON repository_version_content (collection_version_id);
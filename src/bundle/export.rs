@@ -0,0 +1,382 @@
+use super::{checksum, FileEntry, Index, Manifest, EXPORT_DIR, FORMAT, MANIFEST};
+use crate::db_utils::DbConnection;
+use crate::models::{Collection, CollectionSignature, CollectionVersion, Namespace, Role};
+use crate::repository;
+use crate::tasks::Tasks;
+use actix_web::web;
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// What goes into an export. Content is selected by `namespace` or
+/// `namespace.name`; `None` selects everything.
+#[derive(Default)]
+pub struct ExportOptions {
+    pub collections: Option<Vec<String>>,
+    pub roles: Option<Vec<String>>,
+    /// Name of an earlier export whose artifacts are left out.
+    pub since: Option<String>,
+    /// Split the bundle into files of at most this many bytes.
+    pub chunk_size: Option<u64>,
+}
+
+fn selected(specs: &Option<Vec<String>>, namespace: &str, name: &str) -> bool {
+    match specs {
+        None => true,
+        Some(specs) => specs
+            .iter()
+            .any(|spec| spec == namespace || *spec == format!("{namespace}.{name}")),
+    }
+}
+
+/// Writes an export of the published collections and the roles selected by
+/// `options` into `dir`, returning its index.
+pub fn export(conn: &mut DbConnection, options: &ExportOptions, dir: &Path) -> Result<Index> {
+    use crate::schema::{collection_signatures, collection_versions, collections};
+    use crate::schema::{namespaces, repository_content, roles};
+    if options.chunk_size == Some(0) {
+        return Err(anyhow!("The chunk size must be at least one byte"));
+    }
+    let previous = match &options.since {
+        Some(since) => Index::read(dir, since)?.contents,
+        None => BTreeMap::new(),
+    };
+
+    let published = repository::find(conn, repository::PUBLISHED)?
+        .ok_or_else(|| anyhow!("The published repository is missing"))?;
+    let versions: Vec<(Collection, CollectionVersion)> = collection_versions::table
+        .inner_join(collections::table)
+        .filter(
+            collection_versions::id.eq_any(
+                repository_content::table
+                    .filter(repository_content::repository_id.eq(published))
+                    .select(repository_content::collection_version_id),
+            ),
+        )
+        .select((Collection::as_select(), CollectionVersion::as_select()))
+        .order((
+            collections::namespace.asc(),
+            collections::name.asc(),
+            collection_versions::id.asc(),
+        ))
+        .load(conn)?;
+    let versions: Vec<_> = versions
+        .into_iter()
+        .filter(|(c, _)| selected(&options.collections, &c.namespace, &c.name))
+        .collect();
+    let version_ids: Vec<i32> = versions.iter().map(|(_, v)| v.id).collect();
+    let mut signatures: HashMap<i32, Vec<CollectionSignature>> = HashMap::new();
+    for signature in collection_signatures::table
+        .filter(collection_signatures::collection_version_id.eq_any(&version_ids))
+        .select(CollectionSignature::as_select())
+        .order(collection_signatures::id.asc())
+        .load::<CollectionSignature>(conn)?
+    {
+        signatures
+            .entry(signature.collection_version_id)
+            .or_default()
+            .push(signature);
+    }
+    let roles: Vec<Role> = roles::table
+        .select(Role::as_select())
+        .order((roles::namespace.asc(), roles::name.asc()))
+        .load::<Role>(conn)?
+        .into_iter()
+        .filter(|role| selected(&options.roles, &role.namespace, &role.name))
+        .collect();
+    let names: BTreeSet<&str> = versions
+        .iter()
+        .map(|(c, _)| c.namespace.as_str())
+        .chain(roles.iter().map(|role| role.namespace.as_str()))
+        .collect();
+    let owners: Vec<Namespace> = namespaces::table
+        .filter(namespaces::name.eq_any(&names))
+        .select(Namespace::as_select())
+        .order(namespaces::name.asc())
+        .load(conn)?;
+
+    // Collections with their versions nested, each with its signatures.
+    let mut collections: Vec<Value> = Vec::new();
+    let mut artifacts: Vec<String> = Vec::new();
+    for (collection, version) in &versions {
+        if collections
+            .last()
+            .is_none_or(|c| c["id"] != json!(collection.id))
+        {
+            let mut record = serde_json::to_value(collection)?;
+            record["versions"] = json!([]);
+            collections.push(record);
+        }
+        let mut record = serde_json::to_value(version)?;
+        record["signatures"] =
+            serde_json::to_value(signatures.remove(&version.id).unwrap_or_default())?;
+        collections.last_mut().unwrap()["versions"]
+            .as_array_mut()
+            .unwrap()
+            .push(record);
+        if let Some(filename) = version.artifact["filename"].as_str() {
+            artifacts.push(format!(
+                "content/collections/{}/{}/versions/{}/{filename}",
+                collection.namespace, collection.name, version.version
+            ));
+        }
+    }
+    for role in &roles {
+        artifacts.extend(role_archives(&role.namespace, &role.name));
+    }
+
+    let mut contents = previous;
+    let mut files = Vec::new();
+    let mut included = Vec::new();
+    for path in artifacts {
+        let Ok((size, sha256)) = checksum(Path::new(&path)) else {
+            warn!("Leaving {path} out of the export: it can't be read");
+            continue;
+        };
+        if contents.get(&path) == Some(&sha256) {
+            continue;
+        }
+        contents.insert(path.clone(), sha256.clone());
+        files.push(FileEntry {
+            path: path.clone(),
+            size,
+            sha256,
+        });
+        included.push(path);
+    }
+    let metadata: Vec<(&str, Vec<u8>)> = vec![
+        ("namespaces.json", serde_json::to_vec_pretty(&owners)?),
+        ("collections.json", serde_json::to_vec_pretty(&collections)?),
+        ("roles.json", serde_json::to_vec_pretty(&roles)?),
+    ];
+    for (path, data) in metadata.iter().rev() {
+        files.insert(
+            0,
+            FileEntry {
+                path: path.to_string(),
+                size: data.len() as u64,
+                sha256: format!("{:x}", Sha256::digest(data)),
+            },
+        );
+    }
+    let (created_at, name, out) = create_dir(dir)?;
+    let manifest = Manifest {
+        format: FORMAT,
+        name: name.clone(),
+        created_at,
+        since: options.since.clone(),
+        files,
+    };
+
+    let written = (|| {
+        let mut builder = tar::Builder::new(ChunkWriter::new(&out, &name, options.chunk_size));
+        let mtime = created_at.and_utc().timestamp() as u64;
+        append(
+            &mut builder,
+            MANIFEST,
+            &serde_json::to_vec_pretty(&manifest)?,
+            mtime,
+        )?;
+        for (path, data) in &metadata {
+            append(&mut builder, path, data, mtime)?;
+        }
+        for path in &included {
+            builder
+                .append_path_with_name(path, path)
+                .with_context(|| format!("Failed to add {path}"))?;
+        }
+        let chunks = builder.into_inner()?.finish()?;
+        let index = Index {
+            name: name.clone(),
+            created_at,
+            since: options.since.clone(),
+            chunks,
+            contents,
+        };
+        std::fs::write(
+            out.join(format!("{name}.json")),
+            serde_json::to_vec_pretty(&index)?,
+        )?;
+        Result::<Index>::Ok(index)
+    })();
+    match written {
+        Ok(index) => {
+            info!(
+                "Exported {} collection versions and {} roles to {}",
+                versions.len(),
+                roles.len(),
+                out.display()
+            );
+            Ok(index)
+        }
+        Err(err) => {
+            let _ = std::fs::remove_dir_all(&out);
+            Err(err.context(format!("Failed to write {}", out.display())))
+        }
+    }
+}
+
+/// Creates the directory of a new export under `dir`, named after when it
+/// was created. Exports started in the same microsecond take the next one.
+fn create_dir(dir: &Path) -> Result<(NaiveDateTime, String, PathBuf)> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    loop {
+        let created_at = Utc::now().naive_utc();
+        let name = format!("groot-export-{}", created_at.format("%Y%m%d%H%M%S%6f"));
+        let out = dir.join(&name);
+        match std::fs::create_dir(&out) {
+            Ok(()) => return Ok((created_at, name, out)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to create {}", out.display()))
+            }
+        }
+    }
+}
+
+/// Runs `export` into `EXPORT_DIR` as the task `task_uuid`.
+pub async fn export_task(
+    task_uuid: &str,
+    options: ExportOptions,
+    dpool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    tasks: web::Data<Tasks>,
+) -> Result<Index> {
+    tasks
+        .set_state(task_uuid, "running")
+        .expect("Error setting task state");
+
+    // Hashing and copying every artifact takes a while, keep it off the workers.
+    let result = web::block(move || {
+        let mut conn = dpool.get()?;
+        export(&mut conn, &options, Path::new(EXPORT_DIR))
+    })
+    .await
+    .map_err(|err| anyhow!("{err}"))
+    .and_then(|result| result);
+    match &result {
+        Ok(_) => tasks
+            .set_state(task_uuid, "completed")
+            .expect("Error setting task state"),
+        Err(err) => {
+            error!("Export {task_uuid} failed: {err:#}");
+            tasks
+                .fail(task_uuid, &[format!("{err:#}")])
+                .expect("Error setting task state")
+        }
+    };
+    result
+}
+
+/// The archives stored for each version of a role, branch included.
+fn role_archives(namespace: &str, name: &str) -> Vec<String> {
+    let versions = format!("content/roles/{namespace}/{name}/versions");
+    let Ok(entries) = std::fs::read_dir(&versions) else {
+        return Vec::new();
+    };
+    let mut archives: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|version| !version.starts_with('.'))
+        .flat_map(|version| {
+            let dir = format!("{versions}/{version}");
+            std::fs::read_dir(&dir)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .map(move |file| format!("{dir}/{file}"))
+        })
+        .collect();
+    archives.sort();
+    archives
+}
+
+fn append<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+    mtime: u64,
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)
+}
+
+/// Writes `<name>.tar`, or `<name>.tar.000`, `.001`, ... of `chunk_size`
+/// bytes each, hashing every file as it goes.
+struct ChunkWriter {
+    dir: PathBuf,
+    name: String,
+    chunk_size: Option<u64>,
+    current: Option<(File, Sha256, u64, String)>,
+    chunks: Vec<FileEntry>,
+}
+
+impl ChunkWriter {
+    fn new(dir: &Path, name: &str, chunk_size: Option<u64>) -> Self {
+        ChunkWriter {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            chunk_size,
+            current: None,
+            chunks: Vec::new(),
+        }
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        if let Some((mut file, hasher, size, path)) = self.current.take() {
+            file.flush()?;
+            self.chunks.push(FileEntry {
+                path,
+                size,
+                sha256: format!("{:x}", hasher.finalize()),
+            });
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> std::io::Result<Vec<FileEntry>> {
+        self.close()?;
+        Ok(self.chunks)
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.current.is_none() {
+            let path = match self.chunk_size {
+                Some(_) => format!("{}.tar.{:03}", self.name, self.chunks.len()),
+                None => format!("{}.tar", self.name),
+            };
+            let file = File::create(self.dir.join(&path))?;
+            self.current = Some((file, Sha256::new(), 0, path));
+        }
+        let (file, hasher, size, _) = self.current.as_mut().unwrap();
+        let room = self
+            .chunk_size
+            .map_or(buf.len(), |chunk_size| (chunk_size - *size) as usize);
+        let written = file.write(&buf[..buf.len().min(room)])?;
+        hasher.update(&buf[..written]);
+        *size += written as u64;
+        if Some(*size) == self.chunk_size {
+            self.close()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.current {
+            Some((file, ..)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
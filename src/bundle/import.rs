@@ -0,0 +1,397 @@
+use super::{checksum, FileEntry, Index, Manifest, FORMAT, MANIFEST};
+use crate::db_utils::{write_transaction, DbConnection};
+use crate::models::{
+    CollectionNew, CollectionSignatureNew, CollectionVersionNew, NamespaceData, Role, RoleNew,
+};
+use crate::repository;
+use crate::sync::save_namespaces;
+use crate::tasks::Tasks;
+use actix_web::web;
+use anyhow::{anyhow, Context, Result};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::upsert::excluded;
+use log::{error, info};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+
+/// What an import added; artifacts already in place count as `unchanged`.
+pub struct ImportSummary {
+    pub name: String,
+    pub collection_versions: usize,
+    pub roles: usize,
+    pub placed: usize,
+    pub unchanged: usize,
+}
+
+/// Imports the export in the directory `dir`, as written by `export`.
+/// Importing the same export again changes nothing.
+pub fn import(conn: &mut DbConnection, dir: &Path) -> Result<ImportSummary> {
+    let index = Index::find(dir)?;
+    for chunk in &index.chunks {
+        let path = dir.join(&chunk.path);
+        let (size, sha256) =
+            checksum(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        if size != chunk.size || sha256 != chunk.sha256 {
+            return Err(anyhow!("{} is corrupted or incomplete", path.display()));
+        }
+    }
+    let mut reader: Box<dyn Read> = Box::new(std::io::empty());
+    for chunk in &index.chunks {
+        reader = Box::new(reader.chain(File::open(dir.join(&chunk.path))?));
+    }
+
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = archive.entries()?;
+    let mut first = entries
+        .next()
+        .ok_or_else(|| anyhow!("The bundle is empty"))??;
+    if first.path()?.as_os_str() != MANIFEST {
+        return Err(anyhow!("The bundle doesn't start with {MANIFEST}"));
+    }
+    let mut manifest = Vec::new();
+    first.read_to_end(&mut manifest)?;
+    let manifest: Manifest =
+        serde_json::from_slice(&manifest).with_context(|| format!("Failed to parse {MANIFEST}"))?;
+    if manifest.format != FORMAT {
+        return Err(anyhow!("Unsupported bundle format {}", manifest.format));
+    }
+    if manifest.name != index.name {
+        return Err(anyhow!("The bundle belongs to {}", manifest.name));
+    }
+    let expected: HashMap<&str, &FileEntry> = manifest
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+
+    // The metadata comes before the artifacts, so it is checked before any
+    // of them is written. Artifacts are staged next to their targets and only
+    // moved into place once everything checked out and the rows are saved.
+    let mut metadata: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut parsed = None;
+    let mut branches = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut staged = Staged::default();
+    let mut unchanged = 0;
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let file = expected
+            .get(path.as_str())
+            .ok_or_else(|| anyhow!("{path} is not in the manifest"))?;
+        if !seen.insert(path.clone()) {
+            return Err(anyhow!("{path} is in the bundle twice"));
+        }
+        if !path.starts_with("content/") {
+            if parsed.is_some() {
+                return Err(anyhow!("{path} comes after the artifacts"));
+            }
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            if format!("{:x}", Sha256::digest(&data)) != file.sha256 {
+                return Err(anyhow!("{path} doesn't match its checksum"));
+            }
+            metadata.insert(path, data);
+            continue;
+        }
+        if !is_content(&path) {
+            return Err(anyhow!("{path} is outside the content directories"));
+        }
+        if parsed.is_none() {
+            let (owners, collections, roles) = read_metadata(&metadata, &manifest)?;
+            branches = branch_snapshots(&roles);
+            parsed = Some((owners, collections, roles));
+        }
+        if let Ok(found) = checksum(Path::new(&path)) {
+            if found == (file.size, file.sha256.clone()) {
+                unchanged += 1;
+                continue;
+            }
+            // Only role branch snapshots move on; anything else that differs
+            // was published with other content under the same name.
+            if !branches.contains(&path) {
+                return Err(anyhow!("{path} is already here with different content"));
+            }
+        }
+        staged.stage(&mut entry, &path, &file.sha256)?;
+    }
+    if let Some(missing) = expected.keys().find(|path| !seen.contains(**path)) {
+        return Err(anyhow!("{missing} is missing from the bundle"));
+    }
+    let (owners, collections, roles) = match parsed {
+        Some(parsed) => parsed,
+        None => read_metadata(&metadata, &manifest)?,
+    };
+
+    let placed = staged.0.len();
+    let collection_versions = write_transaction(conn, |conn| {
+        let owners: Vec<NamespaceData> = owners
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|owner| NamespaceData::from_json(owner["name"].as_str().unwrap_or(""), owner))
+            .collect();
+        save_namespaces(conn, &owners, true)?;
+        let version_ids = save_collections(conn, &collections)?;
+        repository::add_new_versions(conn, repository::PUBLISHED, &version_ids)?;
+        repository::snapshot(conn, repository::PUBLISHED)?;
+        save_roles(conn, &roles)?;
+        // Last, so a failed move still rolls the rows back.
+        staged.place()?;
+        anyhow::Ok(version_ids.len())
+    })?;
+    info!(
+        "Imported {}: {collection_versions} collection versions, {} roles, {placed} new artifacts",
+        index.name,
+        roles.len()
+    );
+    Ok(ImportSummary {
+        name: index.name,
+        collection_versions,
+        roles: roles.len(),
+        placed,
+        unchanged,
+    })
+}
+
+/// Runs `import` on `dir` as the task `task_uuid`.
+pub async fn import_bundle_task(
+    task_uuid: &str,
+    dir: PathBuf,
+    dpool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    tasks: web::Data<Tasks>,
+) -> Result<ImportSummary> {
+    tasks
+        .set_state(task_uuid, "running")
+        .expect("Error setting task state");
+
+    let result = web::block(move || {
+        let mut conn = dpool.get()?;
+        import(&mut conn, &dir)
+    })
+    .await
+    .map_err(|err| anyhow!("{err}"))
+    .and_then(|result| result);
+    match &result {
+        Ok(_) => tasks
+            .set_state(task_uuid, "completed")
+            .expect("Error setting task state"),
+        Err(err) => {
+            error!("Bundle import {task_uuid} failed: {err:#}");
+            tasks
+                .fail(task_uuid, &[format!("{err:#}")])
+                .expect("Error setting task state")
+        }
+    };
+    result
+}
+
+/// Parses the namespaces, collections and roles of a bundle, making sure the
+/// artifact of every collection version is either in it or already here.
+fn read_metadata(
+    metadata: &HashMap<String, Vec<u8>>,
+    manifest: &Manifest,
+) -> Result<(Value, Value, Vec<Role>)> {
+    let json = |path: &str| -> Result<Value> {
+        let data = metadata
+            .get(path)
+            .ok_or_else(|| anyhow!("{path} is missing from the bundle"))?;
+        serde_json::from_slice(data).with_context(|| format!("Failed to parse {path}"))
+    };
+    let owners = json("namespaces.json")?;
+    let collections = json("collections.json")?;
+    let roles: Vec<Role> =
+        serde_json::from_value(json("roles.json")?).context("Failed to parse roles.json")?;
+
+    // Incremental exports leave out the artifacts earlier ones brought.
+    for collection in collections.as_array().into_iter().flatten() {
+        for version in collection["versions"].as_array().into_iter().flatten() {
+            let path = format!(
+                "content/collections/{}/{}/versions/{}/{}",
+                collection["namespace"].as_str().unwrap_or(""),
+                collection["name"].as_str().unwrap_or(""),
+                version["version"].as_str().unwrap_or(""),
+                version["artifact"]["filename"].as_str().unwrap_or("")
+            );
+            let bundled = manifest.files.iter().any(|file| file.path == path);
+            if !bundled && !Path::new(&path).is_file() {
+                return Err(anyhow!(
+                    "{path} is missing, import {} first",
+                    manifest
+                        .since
+                        .as_deref()
+                        .unwrap_or("the export it came from")
+                ));
+            }
+        }
+    }
+    Ok((owners, collections, roles))
+}
+
+/// Upserts the collections and their versions and signatures, returning the
+/// version ids.
+fn save_collections(conn: &mut DbConnection, collections: &Value) -> QueryResult<Vec<i32>> {
+    use crate::schema::{collection_signatures, collection_versions, collections};
+    let mut version_ids = Vec::new();
+    for collection in collections.as_array().into_iter().flatten() {
+        let collection_id: i32 = diesel::insert_into(collections::table)
+            .values(&CollectionNew {
+                namespace: collection["namespace"].as_str().unwrap_or(""),
+                name: collection["name"].as_str().unwrap_or(""),
+                deprecated: collection["deprecated"].as_bool().unwrap_or(false),
+            })
+            .on_conflict((collections::namespace, collections::name))
+            .do_update()
+            .set(collections::deprecated.eq(excluded(collections::deprecated)))
+            .returning(collections::id)
+            .get_result(conn)?;
+        for version in collection["versions"].as_array().into_iter().flatten() {
+            let mut to_save = CollectionVersionNew::new(
+                &collection_id,
+                &version["artifact"],
+                version["version"].as_str().unwrap_or(""),
+                &version["metadata"],
+            );
+            to_save.uploaded_by = version["uploaded_by"].as_str();
+            to_save.readme = version["readme"].as_str().unwrap_or("");
+            let version_id: i32 = diesel::insert_into(collection_versions::table)
+                .values(&to_save)
+                .on_conflict((
+                    collection_versions::collection_id,
+                    collection_versions::version,
+                ))
+                .do_update()
+                .set((
+                    collection_versions::artifact.eq(excluded(collection_versions::artifact)),
+                    collection_versions::metadata.eq(excluded(collection_versions::metadata)),
+                    collection_versions::readme.eq(excluded(collection_versions::readme)),
+                ))
+                .returning(collection_versions::id)
+                .get_result(conn)?;
+            let signatures: Vec<CollectionSignatureNew> = version["signatures"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|signature| {
+                    Some(CollectionSignatureNew {
+                        collection_version_id: version_id,
+                        signature: signature["signature"].as_str()?,
+                        pubkey_fingerprint: signature["pubkey_fingerprint"].as_str()?,
+                        signing_service: signature["signing_service"].as_str(),
+                    })
+                })
+                .collect();
+            diesel::insert_into(collection_signatures::table)
+                .values(&signatures)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            version_ids.push(version_id);
+        }
+    }
+    Ok(version_ids)
+}
+
+fn save_roles(conn: &mut DbConnection, roles: &[Role]) -> QueryResult<()> {
+    use crate::schema::roles;
+    for role in roles {
+        diesel::insert_into(roles::table)
+            .values(&RoleNew {
+                namespace: &role.namespace,
+                name: &role.name,
+                description: &role.description,
+                tags: &role.tags,
+                readme: &role.readme,
+                metadata: &role.metadata,
+            })
+            .on_conflict((roles::namespace, roles::name))
+            .do_update()
+            .set((
+                roles::description.eq(excluded(roles::description)),
+                roles::tags.eq(excluded(roles::tags)),
+                roles::readme.eq(excluded(roles::readme)),
+                roles::metadata.eq(excluded(roles::metadata)),
+                roles::deprecated.eq(role.deprecated),
+            ))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Artifacts may only land in the collection and role trees.
+fn is_content(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+        && (path.starts_with("content/collections") || path.starts_with("content/roles"))
+}
+
+/// The paths of the branch snapshots of `roles`, which change whenever the
+/// branch does.
+fn branch_snapshots(roles: &[Role]) -> HashSet<String> {
+    roles
+        .iter()
+        .filter_map(|role| {
+            let branch = role.metadata["github_branch"].as_str()?;
+            Some(format!(
+                "content/roles/{}/{}/versions/{branch}/{branch}.tar.gz",
+                role.namespace, role.name
+            ))
+        })
+        .collect()
+}
+
+/// Artifacts written next to their targets, removed unless they're placed.
+#[derive(Default)]
+struct Staged(Vec<(String, String)>);
+
+impl Staged {
+    /// Writes `entry` next to `path`, keeping it only when it matches
+    /// `sha256`.
+    fn stage(&mut self, entry: &mut impl Read, path: &str, sha256: &str) -> Result<()> {
+        let target = Path::new(path);
+        std::fs::create_dir_all(target.parent().unwrap())
+            .with_context(|| format!("Failed to create the directory of {path}"))?;
+        let partial = format!("{path}.{}.import", Uuid::new_v4().simple());
+        let mut file =
+            File::create(&partial).with_context(|| format!("Failed to create {partial}"))?;
+        self.0.push((partial, path.to_string()));
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = entry.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            file.write_all(&buffer[..read])?;
+        }
+        if format!("{:x}", hasher.finalize()) != sha256 {
+            return Err(anyhow!("{path} doesn't match its checksum"));
+        }
+        Ok(())
+    }
+
+    /// Moves every staged artifact into place.
+    fn place(&mut self) -> Result<()> {
+        while let Some((partial, path)) = self.0.pop() {
+            if let Err(err) = std::fs::rename(&partial, &path) {
+                let _ = std::fs::remove_file(&partial);
+                return Err(err).with_context(|| format!("Failed to move {partial}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        for (partial, _) in &self.0 {
+            let _ = std::fs::remove_file(partial);
+        }
+    }
+}
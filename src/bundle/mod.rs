@@ -0,0 +1,115 @@
+//! Offline bundles: everything needed to recreate selected content on a
+//! groot that can't reach this one.
+//!
+//! An export named `<name>` is a directory holding `<name>.json`, the index,
+//! and the bundle itself: `<name>.tar`, or `<name>.tar.000`, `.001`, ... when
+//! split into chunks. The tar starts with `manifest.json`, followed by
+//! `namespaces.json`, `collections.json`, `roles.json` and the artifacts
+//! under the same `content/` paths groot serves them from.
+mod export;
+mod import;
+pub use export::{export, export_task, ExportOptions};
+pub use import::{import, import_bundle_task};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+/// Where the server writes exports, next to `content/`.
+pub const EXPORT_DIR: &str = "exports";
+pub const FORMAT: u32 = 1;
+pub const MANIFEST: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct FileEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// The first entry of a bundle, with the checksum of every other one.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    /// The export this one only adds to.
+    pub since: Option<String>,
+    pub files: Vec<FileEntry>,
+}
+
+/// Lists the pieces to carry over and what the receiving side has once
+/// they're imported.
+#[derive(Serialize, Deserialize)]
+pub struct Index {
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub since: Option<String>,
+    pub chunks: Vec<FileEntry>,
+    /// Checksums of the artifacts in this export and the ones it builds on,
+    /// so the next incremental export can skip them.
+    pub contents: BTreeMap<String, String>,
+}
+
+impl Index {
+    /// The index of the export `name` under `dir`. `name` has to be one of
+    /// the exports there, so it can't lead anywhere else.
+    pub fn read(dir: &Path, name: &str) -> Result<Self> {
+        if !Index::names(dir).iter().any(|export| export == name) {
+            return Err(anyhow!("Unknown export {name}"));
+        }
+        let index = Index::load(&dir.join(name).join(format!("{name}.json")))?;
+        if index.name != name {
+            return Err(anyhow!("{name} holds the export {}", index.name));
+        }
+        Ok(index)
+    }
+
+    /// The names of the exports under `dir`, finished or not.
+    pub fn names(dir: &Path) -> Vec<String> {
+        std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                if !entry.file_type().ok()?.is_dir() {
+                    return None;
+                }
+                entry.file_name().into_string().ok()
+            })
+            .collect()
+    }
+
+    /// The index in the export directory `dir`, which may have been renamed
+    /// on the way.
+    pub fn find(dir: &Path) -> Result<Self> {
+        let indexes: Vec<String> = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|file| file.ends_with(".json"))
+            .collect();
+        match indexes.as_slice() {
+            [index] => Index::load(&dir.join(index)),
+            [] => Err(anyhow!("{} has no export index", dir.display())),
+            _ => Err(anyhow!("{} has several export indexes", dir.display())),
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let index =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&index)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
+
+/// Size and SHA-256 of the file at `path`.
+fn checksum(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
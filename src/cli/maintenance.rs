@@ -0,0 +1,178 @@
+use super::Report;
+use crate::db_utils::DbConnection;
+use anyhow::Result;
+use diesel::prelude::*;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Where each collection version's tarball is stored, with what the
+/// database knows about it.
+fn collection_artifacts(conn: &mut DbConnection) -> QueryResult<Vec<(PathBuf, Value)>> {
+    use crate::schema::{collection_versions, collections};
+    let versions: Vec<(String, String, String, Value)> = collection_versions::table
+        .inner_join(collections::table)
+        .select((
+            collections::namespace,
+            collections::name,
+            collection_versions::version,
+            collection_versions::artifact,
+        ))
+        .order(collection_versions::id.asc())
+        .load(conn)?;
+    Ok(versions
+        .into_iter()
+        .map(|(namespace, name, version, artifact)| {
+            let filename = artifact["filename"].as_str().unwrap_or("").to_string();
+            let path = PathBuf::from(format!(
+                "content/collections/{namespace}/{name}/versions/{version}/{filename}"
+            ));
+            (path, artifact)
+        })
+        .collect())
+}
+
+fn files_under(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(files_under(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Removes the directories under `dir` left empty, keeping `dir` itself.
+fn remove_empty_dirs(dir: &Path) {
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            remove_empty_dirs(&path);
+            let _ = std::fs::remove_dir(&path);
+        }
+    }
+}
+
+/// Removes collection files no version refers to, the archives of deleted
+/// roles and what interrupted imports left behind. Run it while nothing is
+/// being imported, as bundle imports place files before their rows.
+pub(super) fn gc(conn: &mut DbConnection, dry_run: bool) -> Result<Report> {
+    use crate::schema::roles;
+    let keep: HashSet<PathBuf> = collection_artifacts(conn)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    let roles: HashSet<PathBuf> = roles::table
+        .select((roles::namespace, roles::name))
+        .load::<(String, String)>(conn)?
+        .into_iter()
+        .map(|(namespace, name)| Path::new("content/roles").join(namespace).join(name))
+        .collect();
+    let mut orphans: Vec<PathBuf> = files_under(Path::new("content/collections"))
+        .into_iter()
+        .filter(|path| !keep.contains(path))
+        .collect();
+    orphans.extend(
+        files_under(Path::new("content/roles"))
+            .into_iter()
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "import")
+                    || !roles.iter().any(|role| path.starts_with(role))
+            }),
+    );
+    orphans.sort();
+
+    let mut bytes = 0;
+    let mut human = String::new();
+    for path in &orphans {
+        bytes += std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+        if !dry_run {
+            std::fs::remove_file(path)?;
+        }
+        human += &format!("{}\n", path.display());
+    }
+    if !dry_run {
+        remove_empty_dirs(Path::new("content/collections"));
+        remove_empty_dirs(Path::new("content/roles"));
+    }
+    human += &format!(
+        "{} {} files, {bytes} bytes\n",
+        if dry_run { "Would remove" } else { "Removed" },
+        orphans.len()
+    );
+    Ok(Report {
+        json: json!({ "dry_run": dry_run, "removed": orphans, "bytes": bytes }),
+        human,
+        ok: true,
+    })
+}
+
+/// Checks every collection tarball against the size and SHA-256 recorded at
+/// import, and that every role has an archive.
+pub(super) fn verify(conn: &mut DbConnection) -> Result<Report> {
+    use crate::schema::roles;
+    let mut problems: Vec<(String, String)> = Vec::new();
+    let artifacts = collection_artifacts(conn)?;
+    for (path, artifact) in &artifacts {
+        let mut hasher = Sha256::new();
+        let size = match File::open(path).and_then(|mut file| std::io::copy(&mut file, &mut hasher))
+        {
+            Ok(size) => size,
+            Err(err) => {
+                problems.push((path.display().to_string(), err.to_string()));
+                continue;
+            }
+        };
+        let sha256 = format!("{:x}", hasher.finalize());
+        if artifact["sha256"]
+            .as_str()
+            .is_some_and(|expected| expected != sha256)
+        {
+            problems.push((path.display().to_string(), "checksum mismatch".to_string()));
+        } else if artifact["size"]
+            .as_u64()
+            .is_some_and(|expected| expected != size)
+        {
+            problems.push((path.display().to_string(), "size mismatch".to_string()));
+        }
+    }
+    let roles: Vec<(String, String)> = roles::table
+        .select((roles::namespace, roles::name))
+        .order((roles::namespace.asc(), roles::name.asc()))
+        .load(conn)?;
+    for (namespace, name) in &roles {
+        let versions = format!("content/roles/{namespace}/{name}/versions");
+        if files_under(Path::new(&versions)).is_empty() {
+            problems.push((versions, "no archives".to_string()));
+        }
+    }
+
+    let checked = artifacts.len() + roles.len();
+    let mut human: String = problems
+        .iter()
+        .map(|(path, problem)| format!("{path}: {problem}\n"))
+        .collect();
+    human += &format!(
+        "Checked {} collection versions and {} roles, {} problems\n",
+        artifacts.len(),
+        roles.len(),
+        problems.len()
+    );
+    Ok(Report {
+        json: json!({
+            "checked": checked,
+            "problems": problems
+                .iter()
+                .map(|(path, problem)| json!({ "path": path, "problem": problem }))
+                .collect::<Vec<_>>(),
+        }),
+        human,
+        ok: problems.is_empty(),
+    })
+}
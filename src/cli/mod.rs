@@ -0,0 +1,391 @@
+//! `groot <command>`: the server, and the jobs otherwise started over the API,
+//! run in the foreground.
+mod maintenance;
+
+use crate::bundle::{export, import, ExportOptions, Index, EXPORT_DIR};
+use crate::config::Config;
+use crate::db_utils::{establish, pool_builder, run_migrations, DbConnection};
+use crate::repository;
+use crate::sync::{import_directory, mirror_content, process_requirements};
+use crate::tasks::{Tasks, TASK_KINDS};
+use crate::web::UrlBuilder;
+use actix_web::web;
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use diesel::r2d2::{ConnectionManager, Pool};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use url::Url;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "A Galaxy server for air-gapped and private Ansible content"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// How results are printed
+    #[arg(long, global = true, value_enum, default_value_t = Format::Human)]
+    format: Format,
+    /// Configuration file, instead of GROOT_CONFIG or groot.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Human,
+    Json,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the server (the default)
+    Serve,
+    /// Apply the pending database migrations
+    Migrate,
+    /// Mirror content from the configured Galaxy
+    Sync(SyncArgs),
+    /// Import an offline bundle written by `export`, or a directory of collection tarballs
+    Import(ImportArgs),
+    /// Write an offline bundle of published collections and roles
+    Export(ExportArgs),
+    /// Remove stored files no collection version or role refers to
+    Gc(GcArgs),
+    /// Check stored artifacts against the checksums in the database
+    Verify,
+    /// Inspect the tasks the server and these commands ran
+    Tasks {
+        #[command(subcommand)]
+        command: TasksCommand,
+    },
+}
+
+#[derive(Args)]
+pub struct SyncArgs {
+    #[command(subcommand)]
+    target: SyncTarget,
+    /// Galaxy to mirror from, instead of sync.galaxy_url
+    #[arg(long, global = true)]
+    url: Option<Url>,
+}
+
+#[derive(Subcommand)]
+enum SyncTarget {
+    /// Every collection
+    Collections,
+    /// Every role
+    Roles,
+    /// The collections and roles listed in a requirements.yml
+    Requirements { file: PathBuf },
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// An export directory, or a directory of `<namespace>-<name>-<version>.tar.gz` collections
+    path: PathBuf,
+    /// Who collections from a directory are recorded as uploaded by
+    #[arg(long, default_value = "admin")]
+    uploaded_by: String,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Only export this collection, as `namespace` or `namespace.name`; repeatable
+    #[arg(long = "collection", value_name = "NAME")]
+    collections: Vec<String>,
+    /// Only export this role, as `namespace` or `namespace.name`; repeatable
+    #[arg(long = "role", value_name = "NAME")]
+    roles: Vec<String>,
+    /// Leave out collections
+    #[arg(long, conflicts_with = "collections")]
+    no_collections: bool,
+    /// Leave out roles
+    #[arg(long, conflicts_with = "roles")]
+    no_roles: bool,
+    /// Only add artifacts that changed since this earlier export
+    #[arg(long, value_name = "EXPORT")]
+    since: Option<String>,
+    /// Split the bundle into files of at most this many bytes
+    #[arg(long, value_name = "BYTES")]
+    chunk_size: Option<u64>,
+    /// Directory holding the exports
+    #[arg(long, default_value = EXPORT_DIR)]
+    output: PathBuf,
+}
+
+#[derive(Args)]
+pub struct GcArgs {
+    /// Only list what would be removed
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Subcommand)]
+pub enum TasksCommand {
+    /// List the tasks of every kind with their state
+    List,
+}
+
+/// What a command prints: `json` for `--format json`, `human` otherwise.
+/// Commands that ran but found problems, like failed files, set `ok` to
+/// false so groot exits with 1.
+struct Report {
+    json: Value,
+    human: String,
+    ok: bool,
+}
+
+/// Runs every command but `serve`, returning the exit code.
+pub async fn run(cli: Cli, config: Config) -> i32 {
+    std::env::set_var("RUST_LOG", "groot=info");
+    pretty_env_logger::init();
+    let format = cli.format;
+    let result = match cli.command {
+        None | Some(Command::Serve) => unreachable!("main starts the server"),
+        Some(command) => {
+            let applied = run_migrations(&config.database.url);
+            let config = web::Data::new(config);
+            match command {
+                Command::Migrate => Ok(migrated(applied)),
+                Command::Tasks {
+                    command: TasksCommand::List,
+                } => pools(&config).and_then(|(_, tasks)| list_tasks(&tasks)),
+                Command::Sync(args) => sync(config, args).await,
+                Command::Import(args) => run_import(&config, args),
+                Command::Export(args) => run_export(&config, args),
+                Command::Gc(args) => {
+                    connect(&config).and_then(|mut conn| maintenance::gc(&mut conn, args.dry_run))
+                }
+                Command::Verify => {
+                    connect(&config).and_then(|mut conn| maintenance::verify(&mut conn))
+                }
+                Command::Serve => unreachable!(),
+            }
+        }
+    };
+    match (result, format) {
+        (Ok(report), Format::Json) => {
+            println!("{}", serde_json::to_string_pretty(&report.json).unwrap());
+            i32::from(!report.ok)
+        }
+        (Ok(report), Format::Human) => {
+            print!("{}", report.human);
+            i32::from(!report.ok)
+        }
+        (Err(err), Format::Json) => {
+            println!("{}", json!({ "error": format!("{err:#}") }));
+            1
+        }
+        (Err(err), Format::Human) => {
+            eprintln!("Error: {err:#}");
+            1
+        }
+    }
+}
+
+fn connect(config: &Config) -> Result<DbConnection> {
+    establish(&config.database.url).context("Failed to connect to the database")
+}
+
+fn db_pool(config: &Config) -> Result<DbPool> {
+    pool_builder()
+        .max_size(config.database.pool_size)
+        .build(ConnectionManager::<DbConnection>::new(
+            config.database.url.as_str(),
+        ))
+        .context("Failed to connect to the database")
+}
+
+fn pools(config: &Config) -> Result<(web::Data<DbPool>, web::Data<Tasks>)> {
+    let db_pool = db_pool(config)?;
+    let tasks = Tasks::from_config(config, &db_pool)?;
+    Ok((web::Data::new(db_pool), web::Data::new(tasks)))
+}
+
+fn migrated(applied: Vec<String>) -> Report {
+    let human = if applied.is_empty() {
+        "No pending migrations\n".to_string()
+    } else {
+        applied
+            .iter()
+            .map(|version| format!("Applied {version}\n"))
+            .collect()
+    };
+    Report {
+        json: json!({ "applied": applied }),
+        human,
+        ok: true,
+    }
+}
+
+/// Runs a sync as a task the server lists too, so it shows up in
+/// `/api/v2/tasks/` like one started over the API.
+async fn sync(config: web::Data<Config>, args: SyncArgs) -> Result<Report> {
+    let root = match args.url {
+        Some(url) => url,
+        None => Url::parse(&config.sync.galaxy_url)?,
+    };
+    // What to sync, and the task kind the API would use for it.
+    let (kind, syncing, requirements) = match &args.target {
+        SyncTarget::Collections => ("mirror", "collections".to_string(), None),
+        SyncTarget::Roles => ("mirror", "roles".to_string(), None),
+        SyncTarget::Requirements { file } => {
+            let data = std::fs::read(file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            ("requirements", file.display().to_string(), Some(data))
+        }
+    };
+    let (db_pool, tasks) = pools(&config)?;
+    let task_uuid = tasks.create(kind, 172800)?;
+    let result = match requirements {
+        Some(data) => {
+            process_requirements(
+                &task_uuid,
+                root.clone(),
+                data,
+                db_pool,
+                tasks.clone(),
+                config,
+            )
+            .await
+        }
+        None => {
+            mirror_content(
+                &task_uuid,
+                root.clone(),
+                &syncing,
+                db_pool,
+                tasks.clone(),
+                config,
+            )
+            .await
+        }
+    };
+    if let Err(err) = result {
+        tasks.fail(&task_uuid, &[format!("{err:#}")])?;
+        return Err(err);
+    }
+    Ok(Report {
+        human: format!("Synced {syncing} from {root} as {task_uuid}\n"),
+        json: json!({ "syncing": syncing, "url": root, "task": task_uuid, "state": "completed" }),
+        ok: true,
+    })
+}
+
+fn selection(names: Vec<String>, none: bool) -> Option<Vec<String>> {
+    match (none, names.is_empty()) {
+        (true, _) => Some(Vec::new()),
+        (false, true) => None,
+        (false, false) => Some(names),
+    }
+}
+
+fn run_export(config: &Config, args: ExportArgs) -> Result<Report> {
+    let options = ExportOptions {
+        collections: selection(args.collections, args.no_collections),
+        roles: selection(args.roles, args.no_roles),
+        since: args.since,
+        chunk_size: args.chunk_size,
+    };
+    let index = export(&mut connect(config)?, &options, &args.output)?;
+    let dir = args.output.join(&index.name);
+    let mut human = format!("{}\n", index.name);
+    for chunk in &index.chunks {
+        human += &format!("  {}  {}\n", chunk.sha256, dir.join(&chunk.path).display());
+    }
+    Ok(Report {
+        json: json!({
+            "name": index.name,
+            "path": dir,
+            "since": index.since,
+            "chunks": index.chunks,
+        }),
+        human,
+        ok: true,
+    })
+}
+
+fn run_import(config: &Config, args: ImportArgs) -> Result<Report> {
+    if Index::find(&args.path).is_err() {
+        return import_collections(config, args);
+    }
+    let summary = import(&mut connect(config)?, &args.path)?;
+    Ok(Report {
+        human: format!(
+            "{}: {} collection versions, {} roles, {} artifacts placed, {} already present\n",
+            summary.name,
+            summary.collection_versions,
+            summary.roles,
+            summary.placed,
+            summary.unchanged
+        ),
+        json: json!({
+            "name": summary.name,
+            "collection_versions": summary.collection_versions,
+            "roles": summary.roles,
+            "placed": summary.placed,
+            "unchanged": summary.unchanged,
+        }),
+        ok: true,
+    })
+}
+
+fn import_collections(config: &Config, args: ImportArgs) -> Result<Report> {
+    let pool = db_pool(config)?;
+    let repository = if config.content.require_approval {
+        repository::STAGING
+    } else {
+        repository::PUBLISHED
+    };
+    let urls = UrlBuilder::from_config(config);
+    let report = import_directory(
+        &args.path,
+        &pool,
+        config,
+        urls,
+        &args.uploaded_by,
+        repository,
+    )?;
+    let mut human = String::new();
+    let mut failed = 0;
+    for file in &report {
+        if file.state == "failed" {
+            failed += 1;
+            human += &format!("failed     {}: {}\n", file.file, file.messages.join(" "));
+        } else {
+            human += &format!("{:<10} {}\n", file.state, file.file);
+        }
+    }
+    human += &format!(
+        "{} of {} collections imported into {repository}\n",
+        report.len() - failed,
+        report.len()
+    );
+    Ok(Report {
+        json: json!({ "repository": repository, "files": report }),
+        human,
+        ok: failed == 0,
+    })
+}
+
+fn list_tasks(tasks: &Tasks) -> Result<Report> {
+    let mut listed = json!({});
+    let mut human = String::new();
+    for kind in TASK_KINDS {
+        let mut of_kind = Vec::new();
+        for (id, state) in tasks.list(kind)? {
+            human += &format!("{state:<10} {id}\n");
+            of_kind.push(json!({ "uuid": id, "state": state }));
+        }
+        listed[kind] = Value::Array(of_kind);
+    }
+    Ok(Report {
+        json: json!({ "tasks": listed }),
+        human,
+        ok: true,
+    })
+}
@@ -1,22 +1,73 @@
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::{establish, pool_builder, write_transaction};
+
 use crate::diesel_migrations::MigrationHarness;
-use diesel::{connection::Connection, PgConnection};
+#[cfg(not(feature = "sqlite"))]
+use diesel::{
+    connection::Connection,
+    r2d2::{Builder, ConnectionManager, Pool},
+    ConnectionResult,
+};
 use diesel_migrations::EmbeddedMigrations;
 use std::time::Duration;
 
-pub fn run_migrations(db_url: &str) {
-    pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
-    let mut connection = PgConnection::establish(db_url);
+#[cfg(not(any(feature = "postgres", feature = "sqlite")))]
+compile_error!("groot needs the `postgres` or the `sqlite` feature");
+
+/// The connection groot runs on: Postgres, or SQLite with the `sqlite`
+/// feature.
+#[cfg(not(feature = "sqlite"))]
+pub type DbConnection = diesel::PgConnection;
+#[cfg(feature = "sqlite")]
+pub type DbConnection = diesel::SqliteConnection;
+
+#[cfg(not(feature = "sqlite"))]
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+#[cfg(feature = "sqlite")]
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+/// Opens a single connection to `db_url`.
+#[cfg(not(feature = "sqlite"))]
+pub fn establish(db_url: &str) -> ConnectionResult<DbConnection> {
+    DbConnection::establish(db_url)
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn pool_builder() -> Builder<ConnectionManager<DbConnection>> {
+    Pool::builder()
+}
+
+/// Runs `f` in a transaction that is going to write. Rows it locks with
+/// `FOR UPDATE` stay locked until it commits.
+#[cfg(not(feature = "sqlite"))]
+pub fn write_transaction<T, E, F>(conn: &mut DbConnection, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    conn.transaction(f)
+}
+
+/// Runs the pending migrations, returning the versions it applied.
+pub fn run_migrations(db_url: &str) -> Vec<String> {
+    let mut connection = establish(db_url);
     for _ in 0..5 {
         if connection.is_err() {
-            println!("Error connecting to database - Retrying...");
+            eprintln!("Error connecting to database - Retrying...");
             std::thread::sleep(Duration::from_secs(30));
-            connection = PgConnection::establish(db_url);
+            connection = establish(db_url);
         } else {
             break;
         }
     }
-    let _ = &mut connection
+    connection
         .unwrap()
         .run_pending_migrations(MIGRATIONS)
-        .expect("Error running migrations");
+        .expect("Error running migrations")
+        .iter()
+        .map(|version| version.to_string())
+        .collect()
 }
@@ -0,0 +1,46 @@
+use super::DbConnection;
+use diesel::connection::{Connection, SimpleConnection};
+use diesel::r2d2::{Builder, ConnectionManager, CustomizeConnection, Error, Pool};
+use diesel::ConnectionResult;
+
+/// Enforces foreign keys, which SQLite leaves off, and lets readers run
+/// beside the writer, waiting for its lock rather than failing.
+const PRAGMAS: &str = "
+    PRAGMA foreign_keys = ON;
+    PRAGMA busy_timeout = 5000;
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+";
+
+/// Opens a single connection to the database file `db_url`.
+pub fn establish(db_url: &str) -> ConnectionResult<DbConnection> {
+    let mut connection = DbConnection::establish(db_url)?;
+    connection
+        .batch_execute(PRAGMAS)
+        .map_err(diesel::ConnectionError::CouldntSetupConfiguration)?;
+    Ok(connection)
+}
+
+#[derive(Debug)]
+struct ConnectionOptions;
+
+impl CustomizeConnection<DbConnection, Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), Error> {
+        conn.batch_execute(PRAGMAS).map_err(Error::QueryError)
+    }
+}
+
+/// Runs `f` in a transaction that takes the database's write lock up front,
+/// so other writers wait for it instead of failing to upgrade their reads.
+pub fn write_transaction<T, E, F>(conn: &mut DbConnection, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    conn.immediate_transaction(f)
+}
+
+/// A pool builder whose connections are set up like `establish`'s.
+pub fn pool_builder() -> Builder<ConnectionManager<DbConnection>> {
+    Pool::builder().connection_customizer(Box::new(ConnectionOptions))
+}
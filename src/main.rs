@@ -1,21 +1,42 @@
+mod bundle;
+mod cli;
 mod config;
 pub mod db_utils;
 pub mod models;
+mod repository;
 pub mod schema;
+mod signatures;
 mod sync;
+mod tasks;
+mod versions;
 mod web;
 #[macro_use]
 extern crate diesel;
 #[macro_use]
 extern crate diesel_migrations;
+use clap::Parser;
+use cli::{Cli, Command};
+use config::Config;
 use db_utils::run_migrations;
 use dotenv::dotenv;
 use web::start_actix_server;
 
 #[actix_web::main]
 async fn main() {
+    let cli = Cli::parse();
     dotenv().ok();
-    let db_url = dotenv::var("DATABASE_URL").expect("DATABASE_URL");
-    run_migrations(&db_url);
-    start_actix_server().await
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid configuration: {err}");
+            std::process::exit(2);
+        }
+    };
+    match cli.command {
+        None | Some(Command::Serve) => {
+            run_migrations(&config.database.url);
+            start_actix_server(config).await
+        }
+        Some(_) => std::process::exit(cli::run(cli, config).await),
+    }
 }
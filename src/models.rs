@@ -1,4 +1,7 @@
 use crate::schema::*;
+use crate::versions::prerelease_key;
+use chrono::NaiveDateTime;
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -8,12 +11,15 @@ pub struct Collection {
     pub id: i32,
     pub namespace: String,
     pub name: String,
+    pub deprecated: bool,
 }
 #[derive(Debug, Insertable, Hash, Eq, PartialEq)]
 #[diesel(table_name = collections)]
+#[diesel(treat_none_as_default_value = false)]
 pub struct CollectionNew<'a> {
     pub namespace: &'a str,
     pub name: &'a str,
+    pub deprecated: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable, Associations)]
@@ -25,13 +31,260 @@ pub struct CollectionVersion {
     pub artifact: Value,
     pub version: String,
     pub metadata: Value,
+    pub version_major: i32,
+    pub version_minor: i32,
+    pub version_patch: i32,
+    pub version_prerelease_key: String,
+    pub readme: String,
+    pub uploaded_by: Option<String>,
+    pub created_at: NaiveDateTime,
 }
 
 #[derive(Debug, Insertable)]
 #[diesel(table_name = collection_versions)]
+#[diesel(treat_none_as_default_value = false)]
 pub struct CollectionVersionNew<'a> {
     pub collection_id: &'a i32,
     pub artifact: &'a Value,
     pub version: &'a str,
     pub metadata: &'a Value,
+    pub version_major: i32,
+    pub version_minor: i32,
+    pub version_patch: i32,
+    pub version_prerelease_key: String,
+    pub readme: &'a str,
+    pub uploaded_by: Option<&'a str>,
+}
+
+impl<'a> CollectionVersionNew<'a> {
+    /// Splits `version` into the columns used to sort versions by semver in SQL.
+    /// Versions that are not valid semver sort as `0.0.0`.
+    pub fn new(
+        collection_id: &'a i32,
+        artifact: &'a Value,
+        version: &'a str,
+        metadata: &'a Value,
+    ) -> Self {
+        let (major, minor, patch, pre) = match Version::parse(version) {
+            Ok(v) => (v.major, v.minor, v.patch, prerelease_key(&v.pre)),
+            Err(_) => (0, 0, 0, String::new()),
+        };
+        CollectionVersionNew {
+            collection_id,
+            artifact,
+            version,
+            metadata,
+            version_major: major as i32,
+            version_minor: minor as i32,
+            version_patch: patch as i32,
+            version_prerelease_key: pre,
+            readme: "",
+            uploaded_by: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = roles)]
+pub struct Role {
+    pub id: i32,
+    pub namespace: String,
+    pub name: String,
+    pub description: String,
+    pub tags: Value,
+    pub readme: String,
+    pub metadata: Value,
+    pub deprecated: bool,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = roles)]
+#[diesel(treat_none_as_default_value = false)]
+pub struct RoleNew<'a> {
+    pub namespace: &'a str,
+    pub name: &'a str,
+    pub description: &'a str,
+    pub tags: &'a Value,
+    pub readme: &'a str,
+    pub metadata: &'a Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = users)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub is_superuser: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = users)]
+pub struct UserNew<'a> {
+    pub username: &'a str,
+    pub is_superuser: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable, Associations)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = tokens)]
+pub struct Token {
+    pub id: i32,
+    pub user_id: i32,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = tokens)]
+pub struct TokenNew<'a> {
+    pub user_id: i32,
+    pub key_hash: &'a str,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = namespaces)]
+pub struct Namespace {
+    pub id: i32,
+    pub name: String,
+    pub company: String,
+    pub email: String,
+    pub avatar_url: String,
+    pub description: String,
+    pub resources: String,
+    pub links: Value,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = namespaces)]
+pub struct NamespaceNew<'a> {
+    pub name: &'a str,
+}
+
+/// Namespace metadata, as synced from upstream or edited by its owners.
+#[derive(Debug, Insertable, AsChangeset)]
+#[diesel(table_name = namespaces)]
+#[diesel(treat_none_as_default_value = false)]
+pub struct NamespaceData {
+    pub name: String,
+    pub company: String,
+    pub email: String,
+    pub avatar_url: String,
+    pub description: String,
+    pub resources: String,
+    pub links: Value,
+}
+
+impl NamespaceData {
+    /// Reads the fields Galaxy returns for a namespace, leaving missing ones empty.
+    pub fn from_json(name: &str, data: &Value) -> Self {
+        let field = |key: &str| data[key].as_str().unwrap_or("").to_string();
+        NamespaceData {
+            name: name.to_string(),
+            company: field("company"),
+            email: field("email"),
+            avatar_url: field("avatar_url"),
+            description: field("description"),
+            resources: field("resources"),
+            links: match &data["links"] {
+                Value::Array(links) => Value::Array(links.clone()),
+                _ => Value::Array(Vec::new()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = groups)]
+pub struct Group {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = groups)]
+pub struct GroupNew<'a> {
+    pub name: &'a str,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = group_members)]
+pub struct GroupMemberNew {
+    pub group_id: i32,
+    pub user_id: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = namespace_owners)]
+pub struct NamespaceOwnerNew {
+    pub namespace_id: i32,
+    pub group_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = audit_log)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub action: String,
+    pub target: String,
+    pub username: String,
+    pub details: Value,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct AuditEntryNew<'a> {
+    pub action: &'a str,
+    pub target: &'a str,
+    pub username: &'a str,
+    pub details: &'a Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = repositories)]
+pub struct Repository {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = repository_content)]
+#[diesel(treat_none_as_default_value = false)]
+pub struct RepositoryContentNew {
+    pub repository_id: i32,
+    pub collection_version_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = repository_versions)]
+pub struct RepositoryVersion {
+    pub id: i32,
+    pub repository_id: i32,
+    pub number: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = collection_signatures)]
+pub struct CollectionSignature {
+    pub id: i32,
+    pub collection_version_id: i32,
+    pub signature: String,
+    pub pubkey_fingerprint: String,
+    pub signing_service: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = collection_signatures)]
+#[diesel(treat_none_as_default_value = false)]
+pub struct CollectionSignatureNew<'a> {
+    pub collection_version_id: i32,
+    pub signature: &'a str,
+    pub pubkey_fingerprint: &'a str,
+    pub signing_service: Option<&'a str>,
 }
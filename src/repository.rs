@@ -0,0 +1,197 @@
+use crate::db_utils::DbConnection;
+use crate::models::RepositoryContentNew;
+use diesel::prelude::*;
+
+/// Where uploads wait for approval when `CONTENT.REQUIRE_APPROVAL` is set.
+pub const STAGING: &str = "staging";
+/// What clients get from the unscoped `/api/v2/collections/` endpoints.
+pub const PUBLISHED: &str = "published";
+/// Where superusers move uploads that failed approval.
+pub const REJECTED: &str = "rejected";
+
+pub fn find(conn: &mut DbConnection, name: &str) -> QueryResult<Option<i32>> {
+    use crate::schema::repositories;
+    repositories::table
+        .select(repositories::id)
+        .filter(repositories::name.eq(name))
+        .first(conn)
+        .optional()
+}
+
+/// Adds the versions that aren't in any repository yet to `repository`.
+/// Versions that were already placed, e.g. promoted or rejected, stay put.
+pub fn add_new_versions(
+    conn: &mut DbConnection,
+    repository: &str,
+    version_ids: &[i32],
+) -> QueryResult<usize> {
+    use crate::schema::repository_content;
+    let Some(repository_id) = find(conn, repository)? else {
+        return Err(diesel::result::Error::NotFound);
+    };
+    let placed: Vec<i32> = repository_content::table
+        .select(repository_content::collection_version_id)
+        .filter(repository_content::collection_version_id.eq_any(version_ids))
+        .load(conn)?;
+    let to_add: Vec<RepositoryContentNew> = version_ids
+        .iter()
+        .filter(|id| !placed.contains(id))
+        .map(|id| RepositoryContentNew {
+            repository_id,
+            collection_version_id: *id,
+        })
+        .collect();
+    diesel::insert_into(repository_content::table)
+        .values(&to_add)
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+/// Moves a version from `source` to `destination`; returns false when it
+/// isn't in `source`.
+pub fn move_version(
+    conn: &mut DbConnection,
+    version_id: i32,
+    source: i32,
+    destination: i32,
+) -> QueryResult<bool> {
+    use crate::schema::repository_content;
+    let removed = diesel::delete(
+        repository_content::table
+            .filter(repository_content::repository_id.eq(source))
+            .filter(repository_content::collection_version_id.eq(version_id)),
+    )
+    .execute(conn)?;
+    if removed == 0 {
+        return Ok(false);
+    }
+    diesel::insert_into(repository_content::table)
+        .values(&RepositoryContentNew {
+            repository_id: destination,
+            collection_version_id: version_id,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(true)
+}
+
+/// Finds `repository` and locks it against other snapshots until the
+/// transaction ends. SQLite's write transactions already hold the lock.
+#[cfg(not(feature = "sqlite"))]
+fn lock(conn: &mut DbConnection, name: &str) -> QueryResult<Option<i32>> {
+    use crate::schema::repositories;
+    repositories::table
+        .select(repositories::id)
+        .filter(repositories::name.eq(name))
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+#[cfg(feature = "sqlite")]
+fn lock(conn: &mut DbConnection, name: &str) -> QueryResult<Option<i32>> {
+    find(conn, name)
+}
+
+/// Records the current content of `repository` as its next numbered version,
+/// unless nothing changed since the latest one. Returns the new number.
+/// Run it in a `write_transaction`, so concurrent snapshots take turns.
+pub fn snapshot(conn: &mut DbConnection, repository: &str) -> QueryResult<Option<i32>> {
+    use crate::schema::{repository_content, repository_version_content, repository_versions};
+    let Some(repository_id) = lock(conn, repository)? else {
+        return Err(diesel::result::Error::NotFound);
+    };
+    let latest = latest_version(conn, repository_id)?;
+    let current: Vec<i32> = repository_content::table
+        .filter(repository_content::repository_id.eq(repository_id))
+        .select(repository_content::collection_version_id)
+        .order(repository_content::collection_version_id.asc())
+        .load(conn)?;
+    if let Some((latest_id, _)) = latest {
+        let recorded: Vec<i32> = repository_version_content::table
+            .filter(repository_version_content::repository_version_id.eq(latest_id))
+            .select(repository_version_content::collection_version_id)
+            .order(repository_version_content::collection_version_id.asc())
+            .load(conn)?;
+        if recorded == current {
+            return Ok(None);
+        }
+    }
+    let number = latest.map_or(0, |(_, number)| number + 1);
+    let version_id: i32 = diesel::insert_into(repository_versions::table)
+        .values((
+            repository_versions::repository_id.eq(repository_id),
+            repository_versions::number.eq(number),
+        ))
+        .returning(repository_versions::id)
+        .get_result(conn)?;
+    diesel::insert_into(repository_version_content::table)
+        .values(
+            repository_content::table
+                .filter(repository_content::repository_id.eq(repository_id))
+                .select((
+                    version_id.into_sql::<diesel::sql_types::Integer>(),
+                    repository_content::collection_version_id,
+                )),
+        )
+        .into_columns((
+            repository_version_content::repository_version_id,
+            repository_version_content::collection_version_id,
+        ))
+        .execute(conn)?;
+    Ok(Some(number))
+}
+
+/// The id and number of the newest version of a repository.
+pub fn latest_version(
+    conn: &mut DbConnection,
+    repository_id: i32,
+) -> QueryResult<Option<(i32, i32)>> {
+    use crate::schema::repository_versions;
+    repository_versions::table
+        .filter(repository_versions::repository_id.eq(repository_id))
+        .select((repository_versions::id, repository_versions::number))
+        .order(repository_versions::number.desc())
+        .first(conn)
+        .optional()
+}
+
+/// Resolves version `number` of `repository` to its id.
+pub fn find_version(
+    conn: &mut DbConnection,
+    repository_id: i32,
+    number: i32,
+) -> QueryResult<Option<i32>> {
+    use crate::schema::repository_versions;
+    repository_versions::table
+        .select(repository_versions::id)
+        .filter(repository_versions::repository_id.eq(repository_id))
+        .filter(repository_versions::number.eq(number))
+        .first(conn)
+        .optional()
+}
+
+/// Replaces the content of a repository with that of one of its versions.
+/// The result is recorded as a new version, so the rollback can be undone.
+pub fn rollback(conn: &mut DbConnection, repository_id: i32, version_id: i32) -> QueryResult<()> {
+    use crate::schema::{repository_content, repository_version_content};
+    diesel::delete(
+        repository_content::table.filter(repository_content::repository_id.eq(repository_id)),
+    )
+    .execute(conn)?;
+    diesel::insert_into(repository_content::table)
+        .values(
+            repository_version_content::table
+                .filter(repository_version_content::repository_version_id.eq(version_id))
+                .select((
+                    repository_id.into_sql::<diesel::sql_types::Integer>(),
+                    repository_version_content::collection_version_id,
+                )),
+        )
+        .into_columns((
+            repository_content::repository_id,
+            repository_content::collection_version_id,
+        ))
+        .execute(conn)?;
+    Ok(())
+}
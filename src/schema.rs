@@ -1,3 +1,25 @@
+table! {
+    audit_log (id) {
+        id -> Int4,
+        action -> Varchar,
+        target -> Varchar,
+        username -> Varchar,
+        details -> Json,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    collection_signatures (id) {
+        id -> Int4,
+        collection_version_id -> Int4,
+        signature -> Text,
+        pubkey_fingerprint -> Varchar,
+        signing_service -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     collection_versions (id) {
         id -> Int4,
@@ -5,6 +27,13 @@ table! {
         artifact -> Json,
         version -> Varchar,
         metadata -> Json,
+        version_major -> Int4,
+        version_minor -> Int4,
+        version_patch -> Int4,
+        readme -> Text,
+        uploaded_by -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        version_prerelease_key -> Varchar,
     }
 }
 
@@ -13,9 +42,143 @@ table! {
         id -> Int4,
         namespace -> Varchar,
         name -> Varchar,
+        deprecated -> Bool,
+    }
+}
+
+table! {
+    group_members (group_id, user_id) {
+        group_id -> Int4,
+        user_id -> Int4,
+    }
+}
+
+table! {
+    groups (id) {
+        id -> Int4,
+        name -> Varchar,
+    }
+}
+
+table! {
+    namespace_owners (namespace_id, group_id) {
+        namespace_id -> Int4,
+        group_id -> Int4,
+    }
+}
+
+table! {
+    namespaces (id) {
+        id -> Int4,
+        name -> Varchar,
+        company -> Varchar,
+        email -> Varchar,
+        avatar_url -> Varchar,
+        description -> Varchar,
+        resources -> Text,
+        links -> Json,
+    }
+}
+
+table! {
+    repositories (id) {
+        id -> Int4,
+        name -> Varchar,
+        description -> Text,
+    }
+}
+
+table! {
+    repository_content (repository_id, collection_version_id) {
+        repository_id -> Int4,
+        collection_version_id -> Int4,
+    }
+}
+
+table! {
+    repository_version_content (repository_version_id, collection_version_id) {
+        repository_version_id -> Int4,
+        collection_version_id -> Int4,
+    }
+}
+
+table! {
+    repository_versions (id) {
+        id -> Int4,
+        repository_id -> Int4,
+        number -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    roles (id) {
+        id -> Int4,
+        namespace -> Varchar,
+        name -> Varchar,
+        description -> Text,
+        tags -> Json,
+        readme -> Text,
+        metadata -> Json,
+        deprecated -> Bool,
+    }
+}
+
+table! {
+    task_states (key) {
+        key -> Varchar,
+        value -> Text,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        key_hash -> Varchar,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    users (id) {
+        id -> Int4,
+        username -> Varchar,
+        is_superuser -> Bool,
+        created_at -> Timestamp,
     }
 }
 
+joinable!(collection_signatures -> collection_versions (collection_version_id));
 joinable!(collection_versions -> collections (collection_id));
+joinable!(group_members -> groups (group_id));
+joinable!(group_members -> users (user_id));
+joinable!(namespace_owners -> groups (group_id));
+joinable!(namespace_owners -> namespaces (namespace_id));
+joinable!(repository_content -> collection_versions (collection_version_id));
+joinable!(repository_content -> repositories (repository_id));
+joinable!(repository_version_content -> collection_versions (collection_version_id));
+joinable!(repository_version_content -> repository_versions (repository_version_id));
+joinable!(repository_versions -> repositories (repository_id));
+joinable!(tokens -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(collection_versions, collections,);
+allow_tables_to_appear_in_same_query!(
+    audit_log,
+    collection_signatures,
+    collection_versions,
+    collections,
+    group_members,
+    groups,
+    namespace_owners,
+    namespaces,
+    repositories,
+    repository_content,
+    repository_version_content,
+    repository_versions,
+    roles,
+    task_states,
+    tokens,
+    users,
+);
@@ -0,0 +1,390 @@
+use crate::config::SigningConfig;
+use crate::db_utils::DbConnection;
+use crate::models::CollectionSignatureNew;
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diesel::prelude::*;
+use flate2::read::GzDecoder;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const SIGNATURE_PACKET: u8 = 2;
+const ISSUER_KEY_ID: u8 = 16;
+const ISSUER_FINGERPRINT: u8 = 33;
+
+/// Stores signatures, skipping keys that already signed the version.
+pub fn save(conn: &mut DbConnection, signatures: &[CollectionSignatureNew]) -> QueryResult<usize> {
+    use crate::schema::collection_signatures;
+    diesel::insert_into(collection_signatures::table)
+        .values(signatures)
+        .on_conflict((
+            collection_signatures::collection_version_id,
+            collection_signatures::pubkey_fingerprint,
+        ))
+        .do_nothing()
+        .execute(conn)
+}
+
+/// A signature groot made of a collection's `MANIFEST.json`.
+pub struct Signature {
+    pub signature: String,
+    pub pubkey_fingerprint: String,
+}
+
+impl Signature {
+    /// The row recording this signature for `version_id`.
+    pub fn row<'a>(
+        &'a self,
+        config: &'a SigningConfig,
+        version_id: i32,
+    ) -> CollectionSignatureNew<'a> {
+        CollectionSignatureNew {
+            collection_version_id: version_id,
+            signature: &self.signature,
+            pubkey_fingerprint: &self.pubkey_fingerprint,
+            signing_service: Some(&config.service_name),
+        }
+    }
+}
+
+/// Signs the `MANIFEST.json` of a collection tarball with the configured
+/// key. This waits on gpg, so keep it out of transactions and off the
+/// async workers.
+pub fn sign_tarball(config: &SigningConfig, tarball: &Path) -> Result<Signature> {
+    let manifest = read_manifest(tarball)?;
+    let signature = sign(config, &manifest)?;
+    let pubkey_fingerprint =
+        issuer(&signature).ok_or_else(|| anyhow!("gpg returned no signature"))?;
+    Ok(Signature {
+        signature,
+        pubkey_fingerprint,
+    })
+}
+
+/// Extracts `MANIFEST.json`, the file `ansible-galaxy` verifies signatures
+/// against, from a collection tarball.
+fn read_manifest(tarball: &Path) -> Result<Vec<u8>> {
+    let file = std::fs::File::open(tarball)
+        .with_context(|| format!("Failed to open {}", tarball.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_ref() == Path::new("MANIFEST.json") {
+            let mut manifest = Vec::new();
+            entry.read_to_end(&mut manifest)?;
+            return Ok(manifest);
+        }
+    }
+    Err(anyhow!("{} has no MANIFEST.json", tarball.display()))
+}
+
+/// Makes an ASCII-armored detached signature of `data` with gpg.
+fn sign(config: &SigningConfig, data: &[u8]) -> Result<String> {
+    let mut command = Command::new("gpg");
+    if let Some(home) = &config.gnupg_home {
+        command.arg("--homedir").arg(home);
+    }
+    let mut gpg = command
+        .args([
+            "--batch",
+            "--yes",
+            "--armor",
+            "--detach-sign",
+            "--local-user",
+        ])
+        .arg(&config.key_id)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run gpg")?;
+    gpg.stdin
+        .take()
+        .expect("gpg stdin is piped")
+        .write_all(data)?;
+    let output = gpg.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "gpg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Reads the issuer of an ASCII-armored detached OpenPGP signature: its key
+/// fingerprint, or the key id for signers that don't include one. Returns
+/// `None` when `armored` isn't a signature. The signature isn't verified;
+/// that's up to clients holding the keyring.
+pub fn issuer(armored: &str) -> Option<String> {
+    let packet = dearmor(armored)?;
+    let body = signature_body(&packet)?;
+    match body.first()? {
+        3 => body.get(7..15).map(hex),
+        4 | 5 => {
+            let hashed_len = big_endian(body.get(4..6)?);
+            let hashed = body.get(6..6 + hashed_len)?;
+            let rest = body.get(6 + hashed_len..)?;
+            let unhashed = rest.get(2..2 + big_endian(rest.get(..2)?))?;
+            let (mut fingerprint, mut key_id) = (None, None);
+            for (kind, data) in subpackets(hashed)?.into_iter().chain(subpackets(unhashed)?) {
+                match kind {
+                    // The first octet is the key version.
+                    ISSUER_FINGERPRINT if data.len() > 1 => {
+                        fingerprint = fingerprint.or_else(|| Some(hex(&data[1..])))
+                    }
+                    ISSUER_KEY_ID if !data.is_empty() => {
+                        key_id = key_id.or_else(|| Some(hex(data)))
+                    }
+                    _ => {}
+                }
+            }
+            fingerprint.or(key_id)
+        }
+        _ => None,
+    }
+    .filter(|issuer| !issuer.is_empty())
+}
+
+fn dearmor(armored: &str) -> Option<Vec<u8>> {
+    let (_, block) = armored.split_once("-----BEGIN PGP SIGNATURE-----")?;
+    let (block, _) = block.split_once("-----END PGP SIGNATURE-----")?;
+    // Skips armor headers such as `Version: ...` and the `=XXXX` checksum.
+    let is_checksum = |line: &str| line.starts_with('=') && line.len() == 5;
+    let data: String = block
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.contains(':') && !is_checksum(line))
+        .collect();
+    STANDARD.decode(data).ok()
+}
+
+/// Strips the packet header, in either the old or the new format.
+fn signature_body(packet: &[u8]) -> Option<&[u8]> {
+    let header = *packet.first()?;
+    if header & 0x80 == 0 {
+        return None;
+    }
+    let (tag, len, start) = if header & 0x40 != 0 {
+        // Partial lengths aren't valid for signature packets.
+        if (224..255).contains(packet.get(1)?) {
+            return None;
+        }
+        let (len, size) = length(packet.get(1..)?)?;
+        (header & 0x3f, len, 1 + size)
+    } else {
+        let size = match header & 0x03 {
+            0 => 1,
+            1 => 2,
+            2 => 4,
+            _ => return None,
+        };
+        (
+            (header >> 2) & 0x0f,
+            big_endian(packet.get(1..1 + size)?),
+            1 + size,
+        )
+    };
+    if tag != SIGNATURE_PACKET {
+        return None;
+    }
+    packet.get(start..start.checked_add(len)?)
+}
+
+/// Decodes a new-format (sub)packet length; returns it with its own size.
+fn length(data: &[u8]) -> Option<(usize, usize)> {
+    match *data.first()? {
+        len @ 0..=191 => Some((len as usize, 1)),
+        first @ 192..=254 => Some((
+            (((first - 192) as usize) << 8) + *data.get(1)? as usize + 192,
+            2,
+        )),
+        255 => Some((big_endian(data.get(1..5)?), 5)),
+    }
+}
+
+fn subpackets(mut data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut found = Vec::new();
+    while !data.is_empty() {
+        let (len, size) = length(data)?;
+        let end = size.checked_add(len)?;
+        let packet = data.get(size..end)?;
+        let (kind, body) = packet.split_first()?;
+        found.push((kind & 0x7f, body));
+        data = &data[end..];
+    }
+    Some(found)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | *byte as usize)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: [u8; 20] = [
+        0x59, 0x04, 0x4D, 0xA8, 0xE3, 0xDB, 0x16, 0xFD, 0xB1, 0x01, 0x5C, 0x07, 0x53, 0x50, 0x3A,
+        0x26, 0xE7, 0xE6, 0x3B, 0x3D,
+    ];
+    const KEY_ID: &str = "53503A26E7E63B3D";
+
+    fn subpacket(kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![data.len() as u8 + 1, kind];
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    /// A v4 signature body with the given subpackets and a stub MPI.
+    fn v4(hashed: &[u8], unhashed: &[u8]) -> Vec<u8> {
+        let mut body = vec![4, 0x00, 1, 8];
+        body.extend_from_slice(&(hashed.len() as u16).to_be_bytes());
+        body.extend_from_slice(hashed);
+        body.extend_from_slice(&(unhashed.len() as u16).to_be_bytes());
+        body.extend_from_slice(unhashed);
+        body.extend_from_slice(&[0xAB, 0xCD, 0x00, 0x08, 0xFF]);
+        body
+    }
+
+    fn fingerprint_body() -> Vec<u8> {
+        let mut fingerprint = vec![4];
+        fingerprint.extend_from_slice(&FINGERPRINT);
+        let mut hashed = subpacket(2, &[0x65, 0x00, 0x00, 0x00]);
+        hashed.extend(subpacket(ISSUER_FINGERPRINT, &fingerprint));
+        v4(&hashed, &subpacket(ISSUER_KEY_ID, &FINGERPRINT[12..]))
+    }
+
+    fn new_format(body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xC0 | SIGNATURE_PACKET];
+        if body.len() < 192 {
+            packet.push(body.len() as u8);
+        } else {
+            let len = body.len() - 192;
+            packet.extend_from_slice(&[(len >> 8) as u8 + 192, len as u8]);
+        }
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn old_format(body: &[u8]) -> Vec<u8> {
+        // Tag 2 with a two-octet length.
+        let mut packet = vec![0x80 | (SIGNATURE_PACKET << 2) | 1];
+        packet.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn armor(packet: &[u8]) -> String {
+        format!(
+            "-----BEGIN PGP SIGNATURE-----\nVersion: GnuPG v2\n\n{}\n=abcd\n-----END PGP SIGNATURE-----\n",
+            STANDARD.encode(packet)
+        )
+    }
+
+    #[test]
+    fn reads_the_fingerprint_with_either_header() {
+        let fingerprint = Some(hex(&FINGERPRINT));
+        assert_eq!(
+            issuer(&armor(&new_format(&fingerprint_body()))),
+            fingerprint
+        );
+        assert_eq!(
+            issuer(&armor(&old_format(&fingerprint_body()))),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn reads_two_octet_new_format_lengths() {
+        let mut hashed = subpacket(20, &[0; 150]);
+        hashed.extend(subpacket(20, &[0; 150]));
+        hashed.extend(subpacket(ISSUER_KEY_ID, &FINGERPRINT[12..]));
+        let body = v4(&hashed, &[]);
+        assert!(body.len() >= 192);
+        assert_eq!(issuer(&armor(&new_format(&body))).as_deref(), Some(KEY_ID));
+    }
+
+    #[test]
+    fn falls_back_to_the_key_id() {
+        let body = v4(&[], &subpacket(ISSUER_KEY_ID, &FINGERPRINT[12..]));
+        assert_eq!(issuer(&armor(&new_format(&body))).as_deref(), Some(KEY_ID));
+
+        let mut v3 = vec![3, 5, 0x00, 0x65, 0x00, 0x00, 0x00];
+        v3.extend_from_slice(&FINGERPRINT[12..]);
+        v3.extend_from_slice(&[1, 8, 0xAB, 0xCD]);
+        assert_eq!(issuer(&armor(&old_format(&v3))).as_deref(), Some(KEY_ID));
+    }
+
+    #[test]
+    fn missing_issuer_subpacket_is_none() {
+        let body = v4(&subpacket(2, &[0x65, 0x00, 0x00, 0x00]), &[]);
+        assert_eq!(issuer(&armor(&new_format(&body))), None);
+        // An issuer fingerprint subpacket without a fingerprint.
+        let body = v4(&subpacket(ISSUER_FINGERPRINT, &[4]), &[]);
+        assert_eq!(issuer(&armor(&new_format(&body))), None);
+    }
+
+    #[test]
+    fn truncated_packets_are_none() {
+        for packet in [
+            new_format(&fingerprint_body()),
+            old_format(&fingerprint_body()),
+        ] {
+            for len in 0..packet.len() {
+                assert_eq!(issuer(&armor(&packet[..len])), None, "{}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_packets_are_none() {
+        let body = fingerprint_body();
+        // Not a signature packet: a public key.
+        let mut public_key = new_format(&body);
+        public_key[0] = 0xC6;
+        // Lengths claiming more than there is, or partial ones.
+        let mut long = vec![0xC2, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        long.extend_from_slice(&body);
+        let mut partial = vec![0xC2, 0xE1];
+        partial.extend_from_slice(&body);
+        let mut indeterminate = vec![0x8B];
+        indeterminate.extend_from_slice(&body);
+        // A hashed area longer than the packet, and a subpacket past its area.
+        let mut hashed_len = body.clone();
+        hashed_len[4..6].copy_from_slice(&[0xFF, 0xFF]);
+        let subpacket_len = v4(&[0xFF, ISSUER_KEY_ID, 1], &[]);
+        for packet in [
+            public_key,
+            long,
+            partial,
+            indeterminate,
+            new_format(&hashed_len),
+            new_format(&subpacket_len),
+            new_format(&[9]),
+            vec![0x02, 0x01, 0x04],
+        ] {
+            assert_eq!(issuer(&armor(&packet)), None, "{:02X?}", packet);
+        }
+    }
+
+    #[test]
+    fn bad_armor_is_none() {
+        let packet = new_format(&fingerprint_body());
+        let armored = armor(&packet);
+        assert_eq!(issuer(""), None);
+        assert_eq!(issuer(&STANDARD.encode(&packet)), None);
+        assert_eq!(
+            issuer(&armored.replace("-----END PGP SIGNATURE-----", "")),
+            None
+        );
+        assert_eq!(issuer(&armored.replacen('\n', "\n!!", 3)), None);
+    }
+}
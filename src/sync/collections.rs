@@ -1,19 +1,21 @@
-use super::{get_json, request};
-use crate::models::{self, CollectionNew, CollectionVersionNew};
-use crate::schema::collection_versions;
+use super::validate::read_readme;
+use super::{get_json, request, sync_namespaces};
+use crate::config::{Config, SigningConfig};
+use crate::db_utils::{write_transaction, DbConnection};
+use crate::models::{self, CollectionNew, CollectionSignatureNew, CollectionVersionNew};
+use crate::repository;
+use crate::signatures::{self, Signature};
 use actix_web::web;
-use anyhow::{Context, Result};
-use diesel::pg::upsert::excluded;
+use anyhow::{anyhow, Context, Result};
 use diesel::prelude::*;
-use diesel::{
-    r2d2::{ConnectionManager, Pool},
-    PgConnection,
-};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::upsert::excluded;
 use futures::future::try_join_all;
 use log::info;
 use reqwest::{Client, Request};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tower::buffer::Buffer;
@@ -28,12 +30,133 @@ pub struct CollectionData {
     pub artifact: Value,
     pub version: String,
     pub metadata: Value,
+    pub deprecated: bool,
+    pub signatures: Value,
+    pub readme: String,
 }
 
+/// Pairs synced versions with their ids; `saved` is what `save_versions`
+/// returned and `collection_ids` maps `namespace.name`.
+fn with_ids<'a>(
+    versions: &'a [CollectionData],
+    collection_ids: &HashMap<String, i32>,
+    saved: &[(i32, i32, String)],
+) -> Vec<(&'a CollectionData, i32)> {
+    let version_ids: HashMap<(i32, &str), i32> = saved
+        .iter()
+        .map(|(id, collection_id, version)| ((*collection_id, version.as_str()), *id))
+        .collect();
+    versions
+        .iter()
+        .filter_map(|data| {
+            let collection_id = collection_ids[&format!("{}.{}", data.namespace, data.name)];
+            let version_id = version_ids.get(&(collection_id, data.version.as_str()))?;
+            Some((data, *version_id))
+        })
+        .collect()
+}
+
+/// Keeps the signatures upstream serves with each version.
+fn save_signatures(
+    conn: &mut DbConnection,
+    versions: &[(&CollectionData, i32)],
+) -> QueryResult<usize> {
+    let mut to_save = Vec::new();
+    for (data, version_id) in versions {
+        for signature in data.signatures.as_array().into_iter().flatten() {
+            let Some(text) = signature["signature"].as_str() else {
+                continue;
+            };
+            let Some(fingerprint) = signature["pubkey_fingerprint"]
+                .as_str()
+                .map(str::to_string)
+                .or_else(|| signatures::issuer(text))
+            else {
+                continue;
+            };
+            to_save.push((
+                *version_id,
+                text,
+                fingerprint,
+                signature["signing_service"].as_str(),
+            ));
+        }
+    }
+    let to_save: Vec<CollectionSignatureNew> = to_save
+        .iter()
+        .map(
+            |(version_id, signature, fingerprint, signing_service)| CollectionSignatureNew {
+                collection_version_id: *version_id,
+                signature,
+                pubkey_fingerprint: fingerprint,
+                signing_service: *signing_service,
+            },
+        )
+        .collect();
+    signatures::save(conn, &to_save)
+}
+
+/// Upserts collections, returning their ids by `namespace.name`.
+fn save_collections(
+    conn: &mut DbConnection,
+    to_save: &[&CollectionNew],
+) -> QueryResult<HashMap<String, i32>> {
+    use crate::schema::collections::dsl::*;
+    diesel::insert_into(collections)
+        .values(to_save.to_vec())
+        .on_conflict((namespace, name))
+        .do_update()
+        .set(deprecated.eq(excluded(deprecated)))
+        .execute(conn)?;
+    // SQLite can't return ids from a multi-row upsert, so look them up.
+    let namespaces: Vec<&str> = to_save.iter().map(|c| c.namespace).collect();
+    let names: HashSet<String> = to_save
+        .iter()
+        .map(|c| format!("{}.{}", c.namespace, c.name))
+        .collect();
+    Ok(collections
+        .filter(namespace.eq_any(namespaces))
+        .select((id, namespace, name))
+        .load::<(i32, String, String)>(conn)?
+        .into_iter()
+        .map(|(collection_id, ns, n)| (format!("{ns}.{n}"), collection_id))
+        .filter(|(key, _)| names.contains(key))
+        .collect())
+}
+
+/// Upserts versions, returning the id, collection id and version of each.
+fn save_versions(
+    conn: &mut DbConnection,
+    to_save: &[CollectionVersionNew],
+) -> QueryResult<Vec<(i32, i32, String)>> {
+    use crate::schema::collection_versions::dsl::*;
+    diesel::insert_into(collection_versions)
+        .values(to_save)
+        .on_conflict((version, collection_id))
+        .do_update()
+        .set(readme.eq(excluded(readme)))
+        .execute(conn)?;
+    let collection_ids: Vec<i32> = to_save.iter().map(|v| *v.collection_id).collect();
+    let saved: HashSet<(i32, &str)> = to_save
+        .iter()
+        .map(|v| (*v.collection_id, v.version))
+        .collect();
+    Ok(collection_versions
+        .filter(collection_id.eq_any(collection_ids))
+        .select((id, collection_id, version))
+        .load::<(i32, i32, String)>(conn)?
+        .into_iter()
+        .filter(|(_, cid, v)| saved.contains(&(*cid, v.as_str())))
+        .collect())
+}
+
+/// Fetches a version and downloads its tarball, returning the version and
+/// its README.
 pub async fn get_version(
     url: String,
     service: Buffer<ConcurrencyLimit<RateLimit<Client>>, Request>,
-) -> Result<Value> {
+) -> Result<(Value, String)> {
+    let mut readme = String::new();
     let (service, resp) = request(url, service).await;
     let status = resp.status().as_str().to_string();
     let json_response = resp.json::<Value>().await.unwrap();
@@ -61,18 +184,19 @@ pub async fn get_version(
         };
         let content = resp.bytes().await?;
         file.write_all(&content).await?;
+        readme = read_readme(&content);
     }
 
-    Ok(json_response)
+    Ok((json_response, readme))
 }
 
 pub async fn sync_collections(
-    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    pool: web::Data<Pool<ConnectionManager<DbConnection>>>,
     response: &Value,
     service: Buffer<ConcurrencyLimit<RateLimit<Client>>, Request>,
+    galaxy_url: &str,
 ) -> Result<()> {
     let results = response.as_object().unwrap()["data"].as_array().unwrap();
-    let galaxy_url = dotenv::var("GALAXY_URL").unwrap_or("https://galaxy.ansible.com/".to_string());
     let collection_version_futures: Vec<_> = results
         .iter()
         .map(|v| {
@@ -101,16 +225,34 @@ pub async fn sync_collections(
         .await
         .context("Failed to join collection versions futures")?;
 
+    let deprecated_names: HashSet<String> = results
+        .iter()
+        .filter(|v| v["is_deprecated"].as_bool().unwrap_or(false))
+        .map(|v| {
+            format!(
+                "{}.{}",
+                v["collection_version"]["namespace"].as_str().unwrap(),
+                v["collection_version"]["name"].as_str().unwrap()
+            )
+        })
+        .collect();
     let filtered: Vec<CollectionData> = cversions
         .iter()
-        .filter(|j| j["href"].as_str().is_some())
-        .map(|v| CollectionData {
+        .filter(|(j, _)| j["href"].as_str().is_some())
+        .map(|(v, readme)| CollectionData {
             namespace: v["namespace"]["name"].as_str().unwrap().to_string(),
             name: v["collection"]["name"].as_str().unwrap().to_string(),
             download_url: v["download_url"].as_str().unwrap().to_string(),
             artifact: v["artifact"].clone(),
             version: v["version"].as_str().unwrap().to_string(),
             metadata: v["metadata"].clone(),
+            signatures: v["signatures"].clone(),
+            deprecated: deprecated_names.contains(&format!(
+                "{}.{}",
+                v["namespace"]["name"].as_str().unwrap(),
+                v["collection"]["name"].as_str().unwrap()
+            )),
+            readme: readme.clone(),
         })
         .collect();
     let hashcol = filtered
@@ -118,60 +260,51 @@ pub async fn sync_collections(
         .map(|c| CollectionNew {
             namespace: c.namespace.as_str(),
             name: c.name.as_str(),
+            deprecated: c.deprecated,
         })
         .collect::<HashSet<CollectionNew>>();
 
     let to_save: Vec<&CollectionNew> = hashcol.iter().collect();
+    sync_namespaces(
+        &pool,
+        service.clone(),
+        hashcol.iter().map(|c| c.namespace).collect(),
+        galaxy_url,
+    )
+    .await?;
 
-    use crate::schema::collections::dsl::*;
     let mut conn = pool.get().expect("couldn't get db connection from pool");
     info!("Inserting collection data into the DB");
-    let cdata: Vec<(i32, String, String)> = diesel::insert_into(collections)
-        .values(to_save)
-        .on_conflict((namespace, name))
-        .do_update()
-        .set((namespace.eq(excluded(namespace)), name.eq(excluded(name))))
-        .returning((id, namespace, name))
-        .get_results(&mut conn)
-        .unwrap();
-    let mut mmap: HashMap<String, i32> = HashMap::new();
-    for v in cdata.iter() {
-        mmap.insert(format!("{}.{}", v.1.as_str(), v.2.as_str()), v.0);
-    }
+    let mmap = save_collections(&mut conn, &to_save).unwrap();
     let to_save: Vec<CollectionVersionNew> = filtered
         .iter()
         .map(|vs| CollectionVersionNew {
-            collection_id: &mmap
-                [format!("{}.{}", vs.namespace.as_str(), vs.name.as_str()).as_str()],
-            artifact: &vs.artifact,
-            version: vs.version.as_str(),
-            metadata: &vs.metadata,
+            readme: &vs.readme,
+            ..CollectionVersionNew::new(
+                &mmap[format!("{}.{}", vs.namespace.as_str(), vs.name.as_str()).as_str()],
+                &vs.artifact,
+                vs.version.as_str(),
+                &vs.metadata,
+            )
         })
         .collect();
-    diesel::insert_into(collection_versions::table)
-        .values(&to_save)
-        .on_conflict((
-            collection_versions::columns::version,
-            collection_versions::columns::collection_id,
-        ))
-        .do_update()
-        .set((
-            collection_versions::columns::collection_id
-                .eq(excluded(collection_versions::columns::collection_id)),
-            collection_versions::columns::version
-                .eq(excluded(collection_versions::columns::version)),
-        ))
-        .execute(&mut conn)
-        .unwrap();
+    let saved = save_versions(&mut conn, &to_save).unwrap();
+    save_signatures(&mut conn, &with_ids(&filtered, &mmap, &saved))
+        .context("Failed to save collection signatures")?;
+    let version_ids: Vec<i32> = saved.iter().map(|(version_id, _, _)| *version_id).collect();
+    repository::add_new_versions(&mut conn, repository::PUBLISHED, &version_ids)
+        .context("Failed to add collection versions to the published repository")?;
     Ok(())
 }
 
 pub async fn fetch_versions(
     mut service: Buffer<ConcurrencyLimit<RateLimit<Client>>, Request>,
-    url: &Value,
+    collection: &Value,
+    galaxy_url: &str,
 ) -> Result<Vec<CollectionData>> {
+    let url = &collection["versions_url"];
+    let deprecated = collection["deprecated"].as_bool().unwrap_or(false);
     let mut versions: Vec<CollectionData> = Vec::new();
-    let galaxy_url = dotenv::var("GALAXY_URL").unwrap_or("https://galaxy.ansible.com/".to_string());
     let mut versions_url = format!(
         "{}{}?limit=100",
         galaxy_url.strip_suffix('/').unwrap(),
@@ -204,13 +337,16 @@ pub async fn fetch_versions(
             .context("Failed to join collection versions futures")?;
         let cdata: Vec<CollectionData> = cversions
             .iter()
-            .map(|v| CollectionData {
+            .map(|(v, readme)| CollectionData {
                 namespace: v["namespace"]["name"].as_str().unwrap().to_string(),
                 name: v["collection"]["name"].as_str().unwrap().to_string(),
                 download_url: v["download_url"].as_str().unwrap().to_string(),
                 artifact: v["artifact"].clone(),
                 version: v["version"].as_str().unwrap().to_string(),
                 metadata: v["metadata"].clone(),
+                deprecated,
+                signatures: v["signatures"].clone(),
+                readme: readme.clone(),
             })
             .collect();
         versions.extend_from_slice(&cdata);
@@ -229,12 +365,78 @@ pub async fn fetch_versions(
     Ok(versions)
 }
 
+/// Namespace, name and version, which identify a synced version before it
+/// has an id.
+type VersionKey = (String, String, String);
+
+fn version_key(data: &CollectionData) -> VersionKey {
+    (
+        data.namespace.clone(),
+        data.name.clone(),
+        data.version.clone(),
+    )
+}
+
+/// The tarballs of the `versions` the signing service hasn't signed yet.
+fn unsigned_versions(
+    conn: &mut DbConnection,
+    signing: &SigningConfig,
+    versions: &[CollectionData],
+) -> QueryResult<Vec<(VersionKey, String)>> {
+    use crate::schema::{collection_signatures, collection_versions, collections};
+    let namespaces: HashSet<&str> = versions.iter().map(|v| v.namespace.as_str()).collect();
+    let signed: HashSet<VersionKey> = collection_signatures::table
+        .inner_join(collection_versions::table.inner_join(collections::table))
+        .filter(collection_signatures::signing_service.eq(&signing.service_name))
+        .filter(collections::namespace.eq_any(namespaces))
+        .select((
+            collections::namespace,
+            collections::name,
+            collection_versions::version,
+        ))
+        .load(conn)?
+        .into_iter()
+        .collect();
+    Ok(versions
+        .iter()
+        .map(|data| {
+            let tarball = format!(
+                "content/collections/{}/{}/versions/{}/{}",
+                data.namespace,
+                data.name,
+                data.version,
+                data.artifact["filename"].as_str().unwrap()
+            );
+            (version_key(data), tarball)
+        })
+        .filter(|(key, _)| !signed.contains(key))
+        .collect())
+}
+
+/// Signs each of `tarballs`; this waits on gpg for every one.
+fn sign_versions(
+    signing: &SigningConfig,
+    tarballs: Vec<(VersionKey, String)>,
+) -> Result<HashMap<VersionKey, Signature>> {
+    tarballs
+        .into_iter()
+        .map(|(key, tarball)| {
+            let signature = signatures::sign_tarball(signing, Path::new(&tarball))
+                .with_context(|| format!("Failed to sign {tarball}"))?;
+            Ok((key, signature))
+        })
+        .collect()
+}
+
 pub async fn process_collection_data(
-    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    pool: web::Data<Pool<ConnectionManager<DbConnection>>>,
     service: Buffer<ConcurrencyLimit<RateLimit<Client>>, Request>,
     data: Vec<Vec<CollectionData>>,
     fetch_dependencies: bool,
+    config: &Config,
 ) -> Result<()> {
+    // Only requirements syncs come through here, so only curated content is signed.
+    let signing = &config.signing;
     let mut to_process = data;
     loop {
         let mut versions: Vec<CollectionData> = Vec::new();
@@ -245,53 +447,68 @@ pub async fn process_collection_data(
             to_save.push(models::CollectionNew {
                 namespace: col.namespace.as_str(),
                 name: col.name.as_str(),
+                deprecated: col.deprecated,
             })
         }
-        use crate::schema::collections::dsl::*;
+        sync_namespaces(
+            &pool,
+            service.clone(),
+            to_save.iter().map(|c| c.namespace).collect(),
+            &config.sync.galaxy_url,
+        )
+        .await?;
         let mut conn = pool.get().expect("couldn't get db connection from pool");
         info!("Inserting collection data into the DB");
-        let cdata: Vec<(i32, String, String)> = diesel::insert_into(collections)
-            .values(&to_save)
-            .on_conflict((namespace, name))
-            .do_update()
-            .set((namespace.eq(excluded(namespace)), name.eq(excluded(name))))
-            .returning((id, namespace, name))
-            .get_results(&mut conn)
-            .unwrap();
-        let mut mmap: HashMap<String, i32> = HashMap::new();
-        for v in cdata.iter() {
-            mmap.insert(format!("{}.{}", v.1.as_str(), v.2.as_str()), v.0);
-        }
+        let mmap = save_collections(&mut conn, &to_save.iter().collect::<Vec<_>>()).unwrap();
 
         let mut to_save: Vec<models::CollectionVersionNew> = Vec::new();
         for vs in versions.iter() {
             to_save.push(models::CollectionVersionNew {
-                collection_id: &mmap
-                    [format!("{}.{}", vs.namespace.as_str(), vs.name.as_str()).as_str()],
-                artifact: &vs.artifact,
-                version: vs.version.as_str(),
-                metadata: &vs.metadata,
+                readme: &vs.readme,
+                ..models::CollectionVersionNew::new(
+                    &mmap[format!("{}.{}", vs.namespace.as_str(), vs.name.as_str()).as_str()],
+                    &vs.artifact,
+                    vs.version.as_str(),
+                    &vs.metadata,
+                )
             })
         }
-        diesel::insert_into(collection_versions::table)
-            .values(&to_save)
-            .on_conflict((
-                collection_versions::columns::version,
-                collection_versions::columns::collection_id,
-            ))
-            .do_update()
-            .set((
-                collection_versions::columns::collection_id
-                    .eq(excluded(collection_versions::columns::collection_id)),
-                collection_versions::columns::version
-                    .eq(excluded(collection_versions::columns::version)),
-            ))
-            .execute(&mut conn)
-            .unwrap();
+        // Versions are signed before they're published, and not published
+        // when signing fails. gpg runs off the workers and before the
+        // transaction, so only the rows are saved inside it.
+        let signed = match signing {
+            Some(signing) => {
+                let unsigned = unsigned_versions(&mut conn, signing, &versions)
+                    .context("Failed to read collection signatures")?;
+                let signing = signing.clone();
+                web::block(move || sign_versions(&signing, unsigned))
+                    .await
+                    .map_err(|err| anyhow!("{err}"))??
+            }
+            None => HashMap::new(),
+        };
+        write_transaction(&mut conn, |conn| {
+            let saved = save_versions(conn, &to_save)?;
+            let synced = with_ids(&versions, &mmap, &saved);
+            save_signatures(conn, &synced).context("Failed to save collection signatures")?;
+            if let Some(signing) = signing {
+                let rows: Vec<CollectionSignatureNew> = synced
+                    .iter()
+                    .filter_map(|(data, version_id)| {
+                        let signature = signed.get(&version_key(data))?;
+                        Some(signature.row(signing, *version_id))
+                    })
+                    .collect();
+                signatures::save(conn, &rows).context("Failed to save collection signatures")?;
+            }
+            let version_ids: Vec<i32> =
+                saved.iter().map(|(version_id, _, _)| *version_id).collect();
+            repository::add_new_versions(conn, repository::PUBLISHED, &version_ids)
+                .context("Failed to add collection versions to the published repository")?;
+            anyhow::Ok(())
+        })?;
         if fetch_dependencies {
-            let galaxy_url =
-                dotenv::var("GALAXY_URL").unwrap_or("https://galaxy.ansible.com/".to_string());
-            let collections_endpoint = format!("{}api/v3/collections/", galaxy_url);
+            let collections_endpoint = format!("{}api/v3/collections/", config.sync.galaxy_url);
             let dependencies: Vec<Vec<String>> = versions
                 .iter()
                 .filter(|c| !c.metadata["dependencies"].as_object().unwrap().is_empty())
@@ -328,7 +545,7 @@ pub async fn process_collection_data(
                 let deps_json = try_join_all(dependencies).await.unwrap();
                 let to_fetch: Vec<_> = deps_json
                     .iter()
-                    .map(|c| fetch_versions(service.clone(), &c["versions_url"]))
+                    .map(|c| fetch_versions(service.clone(), c, &config.sync.galaxy_url))
                     .collect();
                 to_process = try_join_all(to_fetch).await.unwrap();
             } else {
@@ -1,25 +1,28 @@
+use super::role_archives::RoleRemote;
+use super::validate::{parse_filename, validate_collection, InvalidCollection};
 use super::{
-    a2b_base64, build_service, fetch_versions, get_json, process_collection_data, sync_collections,
-    sync_roles,
+    a2b_base64, build_service, fetch_versions, get_json, process_collection_data, save_namespaces,
+    sync_collections, sync_roles,
 };
+use crate::config::Config;
+use crate::db_utils::{write_transaction, DbConnection};
 use crate::models;
+use crate::repository;
+use crate::signatures;
+use crate::tasks::Tasks;
+use crate::web::UrlBuilder;
 use actix_web::{http::header::HeaderMap, web};
-use anyhow::{Context, Result};
-use diesel::pg::upsert::excluded;
+use anyhow::{anyhow, Context, Result};
 use diesel::prelude::*;
-use diesel::{
-    r2d2::{ConnectionManager, Pool},
-    PgConnection,
-};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::upsert::excluded;
 use futures::future::try_join_all;
 use log::info;
-use r2d2_redis::redis::Commands;
-use r2d2_redis::RedisConnectionManager;
+use serde::Serialize;
 use serde_json::json;
-use std::time::Duration;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use std::path::{Path, PathBuf};
 use url::Url;
+use uuid::Uuid;
 use yaml_rust::Yaml;
 use yaml_rust::YamlLoader;
 
@@ -27,15 +30,13 @@ pub async fn process_requirements(
     task_uuid: &str,
     root: Url,
     chunk: Vec<u8>,
-    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
-    rpool: web::Data<Pool<RedisConnectionManager>>,
+    pool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
 ) -> Result<()> {
-    let mut rconn = rpool
-        .get_timeout(Duration::from_secs(1))
-        .expect("couldn't get redis connection from pool");
-    rconn
-        .set::<&str, &str, bool>(task_uuid, "running")
-        .expect("Error setting key");
+    tasks
+        .set_state(task_uuid, "running")
+        .expect("Error setting task state");
 
     let contents = std::str::from_utf8(chunk.as_ref()).unwrap();
     let docs = YamlLoader::load_from_str(contents).unwrap();
@@ -83,37 +84,51 @@ pub async fn process_requirements(
                                 .as_str()
                                 .unwrap(),
                         );
-                        source_url.unwrap().join(path.as_str()).unwrap()
+                        let source_url = source_url.unwrap();
+                        (source_url.join(path.as_str()).unwrap(), source_url)
                     } else {
-                        root.join(path.as_str()).unwrap()
+                        (root.join(path.as_str()).unwrap(), root.clone())
                     }
                 })
                 .collect();
             let content_futures: Vec<_> = content_paths
                 .iter()
-                .map(|data| get_json(data.as_str()))
+                .map(|(data, _)| get_json(data.as_str()))
                 .collect();
             let responses: Vec<_> = try_join_all(content_futures).await?;
             if content == "roles" {
                 info!("Syncing roles");
-                let to_fetch: Vec<_> = responses.iter().map(sync_roles).collect();
+                let remotes: Vec<_> = content_paths
+                    .iter()
+                    .map(|(_, source)| RoleRemote::for_root(source.clone(), &config))
+                    .collect();
+                let to_fetch: Vec<_> = responses
+                    .iter()
+                    .zip(&remotes)
+                    .map(|(r, remote)| sync_roles(pool.clone(), remote, r))
+                    .collect();
                 try_join_all(to_fetch).await?;
             } else {
                 info!("Syncing collections");
                 let client = reqwest::Client::new();
-                let service = build_service(client.clone());
+                let service = build_service(client.clone(), &config.sync);
                 let to_fetch: Vec<_> = responses
                     .iter()
-                    .map(|c| fetch_versions(service.clone(), &c["versions_url"]))
+                    .map(|c| fetch_versions(service.clone(), c, &config.sync.galaxy_url))
                     .collect();
                 let data = try_join_all(to_fetch).await?;
-                process_collection_data(pool.clone(), service.clone(), data, true).await?
+                process_collection_data(pool.clone(), service.clone(), data, true, &config).await?
             };
         }
     }
-    rconn
-        .set::<&str, &str, bool>(task_uuid, "completed")
-        .expect("Error setting key");
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    write_transaction(&mut conn, |conn| {
+        repository::snapshot(conn, repository::PUBLISHED)
+    })
+    .context("Failed to snapshot the published repository")?;
+    tasks
+        .set_state(task_uuid, "completed")
+        .expect("Error setting task state");
 
     Ok(())
 }
@@ -122,32 +137,31 @@ pub async fn mirror_content(
     task_uuid: &str,
     root: Url,
     content_type: &str,
-    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
-    rpool: web::Data<Pool<RedisConnectionManager>>,
+    pool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
 ) -> Result<()> {
-    let mut rconn = rpool
-        .get_timeout(Duration::from_secs(1))
-        .expect("couldn't get redis connection from pool");
-    rconn
-        .set::<&str, &str, bool>(task_uuid, "running")
-        .expect("Error setting key");
+    tasks
+        .set_state(task_uuid, "running")
+        .expect("Error setting task state");
 
     let mut target = if content_type == "roles" {
         root.join("api/v1/roles/?page_size=100")
             .context("Failed to join api/v1/roles")?
     } else if content_type == "collections" {
-        root.join("api/v3/plugin/ansible/search/collection-versions/?is_deprecated=false&repository_label=!hide_from_search&offset=0&limit=100")
+        root.join("api/v3/plugin/ansible/search/collection-versions/?repository_label=!hide_from_search&offset=0&limit=100")
             .context("Failed to join api/v3/collections")?
     } else {
         panic!("Invalid content type!")
     };
     let client = reqwest::Client::new();
-    let service = build_service(client.clone());
+    let service = build_service(client.clone(), &config.sync);
+    let remote = RoleRemote::for_root(root.clone(), &config);
     loop {
         let results = get_json(target.as_str()).await.unwrap();
         if content_type == "roles" {
             info!("Syncing roles");
-            sync_roles(&results).await?;
+            sync_roles(pool.clone(), &remote, &results).await?;
             if results.as_object().unwrap()["next"].as_str().is_none() {
                 info!("Sync is complete!");
                 break;
@@ -157,7 +171,13 @@ pub async fn mirror_content(
                 .context("Failed to join next_link")?
         } else if content_type == "collections" {
             info!("Syncing collections");
-            sync_collections(pool.clone(), &results, service.clone()).await?;
+            sync_collections(
+                pool.clone(),
+                &results,
+                service.clone(),
+                &config.sync.galaxy_url,
+            )
+            .await?;
             if results.as_object().unwrap()["links"]["next"]
                 .as_str()
                 .is_none()
@@ -176,99 +196,310 @@ pub async fn mirror_content(
             panic!("Invalid content type!")
         };
     }
-    rconn
-        .set::<&str, &str, bool>(task_uuid, "completed")
-        .expect("Error setting key");
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    write_transaction(&mut conn, |conn| {
+        repository::snapshot(conn, repository::PUBLISHED)
+    })
+    .context("Failed to snapshot the published repository")?;
+    tasks
+        .set_state(task_uuid, "completed")
+        .expect("Error setting task state");
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn import_task(
     task_uuid: &str,
     filename: &str,
     headers: &HeaderMap,
     data: Vec<u8>,
-    dpool: web::Data<Pool<ConnectionManager<PgConnection>>>,
-    rpool: web::Data<Pool<RedisConnectionManager>>,
+    dpool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
+    urls: UrlBuilder,
+    uploaded_by: String,
+    repository: &'static str,
 ) -> Result<()> {
-    let mut rconn = rpool
-        .get_timeout(Duration::from_secs(1))
-        .expect("couldn't get redis connection from pool");
-    rconn
-        .set::<&str, &str, bool>(task_uuid, "running")
-        .expect("Error setting key");
+    tasks
+        .set_state(task_uuid, "running")
+        .expect("Error setting task state");
 
-    let parts = filename.split('-').collect::<Vec<&str>>();
-    let (namespace, name, version) = (parts[0], parts[1], parts[2].replace(".tar.gz", ""));
+    // Validating, signing and moving files all block, so keep them off the workers.
+    let filename = filename.to_string();
+    let headers = headers.clone();
+    let result = web::block(move || {
+        import_collection(
+            &filename,
+            &headers,
+            data,
+            &dpool,
+            &config,
+            urls,
+            uploaded_by,
+            repository,
+        )
+    })
+    .await
+    .map_err(|err| anyhow!("{err}"))
+    .and_then(|result| result);
+    match &result {
+        Ok(()) => tasks
+            .set_state(task_uuid, "completed")
+            .expect("Error setting task state"),
+        Err(err) => tasks
+            .fail(task_uuid, &error_messages(err))
+            .expect("Error setting task state"),
+    };
+    result
+}
 
-    use crate::schema::*;
-    let mut dbconn = dpool.get().expect("couldn't get db connection from pool");
-    let col = models::CollectionNew { namespace, name };
-    let collection_id: Vec<i32> = diesel::insert_into(collections::table)
-        .values(&col)
-        .on_conflict((collections::columns::namespace, collections::columns::name))
-        .do_update()
-        .set((
-            collections::columns::namespace.eq(excluded(collections::columns::namespace)),
-            collections::columns::name.eq(excluded(collections::columns::name)),
-        ))
-        .returning(collections::columns::id)
-        .get_results(&mut dbconn)
-        .unwrap();
+/// How importing one file of a directory went.
+#[derive(Serialize)]
+pub struct FileReport {
+    pub file: String,
+    pub state: &'static str,
+    pub messages: Vec<String>,
+}
 
-    let content_length: usize = match headers.get("content-length") {
-        Some(header_value) => header_value.to_str().unwrap_or("0").parse().unwrap(),
-        None => "0".parse().unwrap(),
-    };
-    let config = crate::config::Config::from_env().unwrap();
-    let href = format!(
-        "http://{}:{}/api/v2/collections/{}/{}/",
-        config.server.host, config.server.port, namespace, name
-    );
-    let artifact = json!({"filename": filename, "size": content_length, "href": href});
-    let metadata = json!({"name": name, "version": version, "namespace": namespace, "groot": true});
-    let cversion = models::CollectionVersionNew {
-        collection_id: collection_id.first().unwrap(),
-        artifact: &artifact,
-        version: &version,
-        metadata: &metadata,
+/// Imports every `*.tar.gz` in `dir` the way uploads are, one at a time,
+/// going on past the ones that fail. This blocks throughout.
+pub fn import_directory(
+    dir: &Path,
+    dpool: &Pool<ConnectionManager<DbConnection>>,
+    config: &Config,
+    urls: UrlBuilder,
+    uploaded_by: &str,
+    repository: &str,
+) -> Result<Vec<FileReport>> {
+    let mut files: Vec<String> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|file| file.ends_with(".tar.gz"))
+        .collect();
+    if files.is_empty() {
+        return Err(anyhow!("No collection tarballs in {}", dir.display()));
+    }
+    files.sort();
+    let mut report = Vec::new();
+    for file in files {
+        let result = match std::fs::read(dir.join(&file)) {
+            Ok(data) => import_collection(
+                &file,
+                &HeaderMap::new(),
+                data,
+                dpool,
+                config,
+                urls.clone(),
+                uploaded_by.to_string(),
+                repository,
+            ),
+            Err(err) => Err(anyhow!("Failed to read {file}: {err}")),
+        };
+        report.push(match result {
+            Ok(()) => FileReport {
+                file,
+                state: "completed",
+                messages: Vec::new(),
+            },
+            Err(err) => FileReport {
+                file,
+                state: "failed",
+                messages: error_messages(&err),
+            },
+        });
+    }
+    Ok(report)
+}
+
+/// Runs `import_directory` as the task `task_uuid`, keeping the per-file
+/// report under `{task_uuid}:report`. The task fails if any file did.
+#[allow(clippy::too_many_arguments)]
+pub async fn import_directory_task(
+    task_uuid: &str,
+    dir: PathBuf,
+    dpool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
+    urls: UrlBuilder,
+    uploaded_by: String,
+    repository: &'static str,
+) -> Result<()> {
+    tasks
+        .set_state(task_uuid, "running")
+        .expect("Error setting task state");
+
+    let report =
+        web::block(move || import_directory(&dir, &dpool, &config, urls, &uploaded_by, repository))
+            .await
+            .map_err(|err| anyhow!("{err}"))
+            .and_then(|result| result);
+    let report = match report {
+        Ok(report) => report,
+        Err(err) => {
+            tasks
+                .fail(task_uuid, &error_messages(&err))
+                .expect("Error setting task state");
+            return Err(err);
+        }
     };
-    diesel::insert_into(collection_versions::table)
-        .values(&cversion)
-        .on_conflict((
-            collection_versions::columns::collection_id,
-            collection_versions::columns::version,
-        ))
-        .do_nothing()
-        .execute(&mut dbconn)
-        .unwrap();
+    tasks
+        .set_report(task_uuid, &json!(report))
+        .expect("Error setting task report");
+    let failed: Vec<String> = report
+        .iter()
+        .filter(|file| file.state == "failed")
+        .map(|file| format!("{}: {}", file.file, file.messages.join(" ")))
+        .collect();
+    if failed.is_empty() {
+        tasks
+            .set_state(task_uuid, "completed")
+            .expect("Error setting task state");
+    } else {
+        tasks
+            .fail(task_uuid, &failed)
+            .expect("Error setting task state");
+    }
+    Ok(())
+}
 
-    let file_path = format!(
-        "content/collections/{}/{}/versions/{}/",
-        namespace, name, version
-    );
-    tokio::fs::create_dir_all(&file_path)
-        .await
-        .with_context(|| format!("Failed to create dir {file_path}"))?;
-    info!("Uploading {}", filename);
-    let mut file = File::create(format!("{}/{}", file_path, filename))
-        .await
-        .unwrap();
+/// What an import reports for `err`: every problem found in an invalid
+/// collection, or the error chain.
+fn error_messages(err: &anyhow::Error) -> Vec<String> {
+    match err.downcast_ref::<InvalidCollection>() {
+        Some(invalid) => invalid.0.clone(),
+        None => vec![format!("{err:#}")],
+    }
+}
 
+/// Validates, signs and stores one collection tarball. This blocks, on gpg
+/// among others.
+#[allow(clippy::too_many_arguments)]
+fn import_collection(
+    filename: &str,
+    headers: &HeaderMap,
+    data: Vec<u8>,
+    dpool: &Pool<ConnectionManager<DbConnection>>,
+    config: &Config,
+    urls: UrlBuilder,
+    uploaded_by: String,
+    repository: &str,
+) -> Result<()> {
+    let Some((namespace, name, version)) = parse_filename(filename) else {
+        return Err(InvalidCollection(vec![format!(
+            "'{filename}' should be named <namespace>-<name>-<version>.tar.gz."
+        )])
+        .into());
+    };
     let encoded = match headers.get("content-transfer-encoding") {
         None => "",
         Some(encoded) => encoded.to_str().unwrap(),
     };
-    if encoded == "base64" {
-        let decoded = a2b_base64(data, false).unwrap();
-        file.write_all(&decoded).await.unwrap();
+    let data = if encoded == "base64" {
+        a2b_base64(data, false).map_err(|err| InvalidCollection(vec![err.to_string()]))?
     } else {
-        file.write_all(&data).await.unwrap();
-    }
+        data
+    };
+    let archive = validate_collection(&data, namespace, name, version, &config.content)?;
 
-    rconn
-        .set::<&str, &str, bool>(task_uuid, "completed")
-        .expect("Error setting key");
+    // The tarball is written aside and moved into place just before its rows
+    // commit, so a failed import leaves neither behind.
+    let file_path = format!(
+        "content/collections/{}/{}/versions/{}/",
+        namespace, name, version
+    );
+    let tarball = format!("{file_path}{filename}");
+    let staged = format!(
+        "{file_path}.{filename}.importing-{}",
+        Uuid::new_v4().simple()
+    );
+    std::fs::create_dir_all(&file_path)
+        .with_context(|| format!("Failed to create dir {file_path}"))?;
+    std::fs::write(&staged, &data).with_context(|| format!("Failed to write {staged}"))?;
+    let discard = || {
+        let _ = std::fs::remove_file(&staged);
+        let _ = std::fs::remove_dir(&file_path);
+    };
+    // Signed before the transaction, which would otherwise hold the
+    // repository lock while gpg runs; only the row is saved inside it.
+    let signature = match &config.signing {
+        Some(signing) => match signatures::sign_tarball(signing, Path::new(&staged)) {
+            Ok(signature) => Some((signing, signature)),
+            Err(err) => {
+                discard();
+                return Err(err.context(format!("Failed to sign {filename}")));
+            }
+        },
+        None => None,
+    };
 
+    let href = urls.collection(namespace, name);
+    let artifact = json!({
+        "filename": filename,
+        "size": data.len(),
+        "sha256": archive.sha256,
+        "href": href,
+    });
+    let mut metadata = archive.collection_info;
+    metadata["groot"] = json!(true);
+    let readme = archive.readme;
+    let mut dbconn = dpool.get().expect("couldn't get db connection from pool");
+    let saved = write_transaction(&mut dbconn, |conn| {
+        use crate::schema::*;
+        save_namespaces(
+            conn,
+            &[models::NamespaceData::from_json(namespace, &json!(null))],
+            false,
+        )?;
+        let col = models::CollectionNew {
+            namespace,
+            name,
+            deprecated: false,
+        };
+        let collection_id: i32 = diesel::insert_into(collections::table)
+            .values(&col)
+            .on_conflict((collections::columns::namespace, collections::columns::name))
+            .do_update()
+            .set((
+                collections::columns::namespace.eq(excluded(collections::columns::namespace)),
+                collections::columns::name.eq(excluded(collections::columns::name)),
+            ))
+            .returning(collections::columns::id)
+            .get_result(conn)?;
+        let mut cversion =
+            models::CollectionVersionNew::new(&collection_id, &artifact, version, &metadata);
+        cversion.uploaded_by = Some(&uploaded_by);
+        cversion.readme = &readme;
+        let version_id = diesel::insert_into(collection_versions::table)
+            .values(&cversion)
+            .on_conflict((
+                collection_versions::columns::collection_id,
+                collection_versions::columns::version,
+            ))
+            .do_nothing()
+            .returning(collection_versions::columns::id)
+            .get_result::<i32>(conn)
+            .optional()?;
+        // Published artifacts are never replaced; their checksum and
+        // signatures were recorded.
+        let Some(version_id) = version_id else {
+            return Err(anyhow!("{namespace}.{name}:{version} already exists"));
+        };
+        // Recorded before it's added, so it's never served unsigned.
+        if let Some((signing, signature)) = &signature {
+            signatures::save(conn, &[signature.row(signing, version_id)])?;
+        }
+        repository::add_new_versions(conn, repository, &[version_id])
+            .with_context(|| format!("Failed to add {filename} to the {repository} repository"))?;
+        repository::snapshot(conn, repository)
+            .with_context(|| format!("Failed to snapshot the {repository} repository"))?;
+        info!("Uploading {}", filename);
+        std::fs::rename(&staged, &tarball)
+            .with_context(|| format!("Failed to move {filename} into place"))
+    });
+    if let Err(err) = saved {
+        discard();
+        return Err(err);
+    }
     Ok(())
 }
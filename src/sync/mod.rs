@@ -1,10 +1,20 @@
 mod collections;
 mod common;
 mod decode;
+mod namespaces;
+mod role_archives;
+mod role_import;
 mod roles;
 mod utils;
+mod validate;
+
 pub use collections::{fetch_versions, process_collection_data, sync_collections};
-pub use common::{import_task, mirror_content, process_requirements};
+pub use common::{
+    import_directory, import_directory_task, import_task, mirror_content, process_requirements,
+};
 pub use decode::a2b_base64;
+pub use namespaces::{save_namespaces, sync_namespaces};
+pub use role_import::{check_git_source, role_import_task, RoleImport, RoleSource};
 pub use roles::sync_roles;
-pub use utils::{build_service, download_tar, get_json, request};
+pub use utils::{build_service, download_tar, get_json, request, request_json};
+pub use validate::{is_collection_name, is_role_segment, parse_filename};
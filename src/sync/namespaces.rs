@@ -0,0 +1,82 @@
+use super::request_json;
+use crate::db_utils::DbConnection;
+use crate::models::NamespaceData;
+use anyhow::{Context, Result};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::upsert::excluded;
+use futures::future::join_all;
+use log::{info, warn};
+use reqwest::{Client, Request};
+use serde_json::Value;
+use std::collections::HashSet;
+use tower::buffer::Buffer;
+use tower::limit::{ConcurrencyLimit, RateLimit};
+
+/// Inserts namespaces that don't exist yet. With `update`, existing ones get
+/// the given metadata too; otherwise they are left alone.
+pub fn save_namespaces(
+    conn: &mut DbConnection,
+    to_save: &[NamespaceData],
+    update: bool,
+) -> QueryResult<usize> {
+    use crate::schema::namespaces::dsl::*;
+    if to_save.is_empty() {
+        return Ok(0);
+    }
+    let insert = diesel::insert_into(namespaces)
+        .values(to_save)
+        .on_conflict(name);
+    if update {
+        insert
+            .do_update()
+            .set((
+                company.eq(excluded(company)),
+                email.eq(excluded(email)),
+                avatar_url.eq(excluded(avatar_url)),
+                description.eq(excluded(description)),
+                resources.eq(excluded(resources)),
+                links.eq(excluded(links)),
+            ))
+            .execute(conn)
+    } else {
+        insert.do_nothing().execute(conn)
+    }
+}
+
+/// Fetches the upstream metadata of the collection namespaces in `names` and
+/// stores it. Namespaces upstream doesn't know are created with just a name.
+/// Requests go through `service` like the rest of a sync's.
+pub async fn sync_namespaces(
+    pool: &Pool<ConnectionManager<DbConnection>>,
+    service: Buffer<ConcurrencyLimit<RateLimit<Client>>, Request>,
+    names: HashSet<&str>,
+    galaxy_url: &str,
+) -> Result<()> {
+    let namespace_futures: Vec<_> = names
+        .iter()
+        .map(|ns| {
+            let url = format!("{galaxy_url}api/v3/namespaces/{ns}/");
+            let service = service.clone();
+            async move { (*ns, request_json(&url, service).await) }
+        })
+        .collect();
+    let (mut found, mut missing) = (Vec::new(), Vec::new());
+    for (ns, response) in join_all(namespace_futures).await {
+        match response {
+            Ok(data) if data["name"].as_str() == Some(ns) => {
+                found.push(NamespaceData::from_json(ns, &data))
+            }
+            Ok(_) => missing.push(NamespaceData::from_json(ns, &Value::Null)),
+            Err(e) => {
+                warn!("Failed to fetch namespace {ns}: {e}");
+                missing.push(NamespaceData::from_json(ns, &Value::Null))
+            }
+        }
+    }
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    info!("Inserting namespace data into the DB");
+    save_namespaces(&mut conn, &found, true).context("Failed to save namespaces")?;
+    save_namespaces(&mut conn, &missing, false).context("Failed to save namespaces")?;
+    Ok(())
+}
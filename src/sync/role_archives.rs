@@ -0,0 +1,191 @@
+use super::role_import::{version_path, Checkout};
+use super::{download_tar, get_json};
+use crate::config::{Config, RoleArchiveSource};
+use actix_web::web;
+use anyhow::{anyhow, Context, Result};
+use futures::future::try_join_all;
+use log::info;
+use serde_json::Value;
+use url::Url;
+
+/// The Galaxy a sync pulls roles from, and where it gets their archives.
+#[derive(Clone)]
+pub struct RoleRemote {
+    pub root: Url,
+    source: RoleArchiveSource,
+    forge_url: Option<String>,
+    url_template: Option<String>,
+}
+
+impl RoleRemote {
+    /// The remote configured for `root`, or GitHub archives when none is.
+    pub fn for_root(root: Url, config: &Config) -> Self {
+        let same = |url: &str| url.trim_end_matches('/') == root.as_str().trim_end_matches('/');
+        let remote = config.remotes.values().find(|remote| same(&remote.url));
+        match remote {
+            Some(remote) => RoleRemote {
+                root,
+                source: remote.role_source,
+                forge_url: remote.forge_url.clone(),
+                url_template: remote.role_url_template.clone(),
+            },
+            None => RoleRemote {
+                root,
+                source: RoleArchiveSource::Github,
+                forge_url: None,
+                url_template: None,
+            },
+        }
+    }
+
+    /// The v1 search for a `namespace.name` role, which is how dependencies
+    /// are looked up.
+    pub fn role_url(&self, role: &str) -> Result<Url> {
+        self.root
+            .join(&format!(
+                "api/v1/roles/?namespace={}",
+                role.replace('.', "&name=")
+            ))
+            .context("Failed to join api/v1/roles")
+    }
+
+    /// Stores each of `versions` of the role `data` describes as
+    /// `versions/{version}/{version}.tar.gz`, the layout the v1 API serves.
+    pub async fn fetch(&self, data: &Value, versions: Vec<String>) -> Result<()> {
+        let namespace = data["summary_fields"]["namespace"]["name"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let name = data["name"].as_str().unwrap().to_string();
+        // Versions end up in paths and git refs, so upstream's are checked
+        // like imported tags are.
+        let paths: Vec<(String, String)> = versions
+            .into_iter()
+            .filter_map(|version| match version_path(&namespace, &name, &version) {
+                Ok(path) => Some((version, path)),
+                Err(_) => {
+                    info!("Skipping version {version} of {namespace}.{name}");
+                    None
+                }
+            })
+            .collect();
+        match self.source {
+            RoleArchiveSource::Git => {
+                let source = self.url(data, "")?;
+                let repo = data["github_repo"].as_str().unwrap().to_string();
+                web::block(move || {
+                    let checkout = Checkout::clone(&source, true)?;
+                    for (version, path) in &paths {
+                        info!("Packing {namespace}-{name}-{version}");
+                        checkout.archive(&repo, version, version, path)?;
+                    }
+                    Result::<()>::Ok(())
+                })
+                .await
+                .map_err(|err| anyhow!("{err}"))??;
+            }
+            RoleArchiveSource::Download => {
+                let listed = self.download_urls(data).await?;
+                let downloads = paths.iter().filter_map(|(version, path)| {
+                    let url = listed
+                        .iter()
+                        .find(|(name, _)| name == version)
+                        .map(|(_, url)| url.clone());
+                    if url.is_none() {
+                        info!("{namespace}-{name}-{version} has no download_url");
+                    }
+                    Some(download(url?, version, path))
+                });
+                try_join_all(downloads)
+                    .await
+                    .context("Failed to join role versions futures")?;
+            }
+            _ => {
+                let downloads = paths.iter().map(|(version, path)| async move {
+                    download(self.url(data, version)?, version, path).await
+                });
+                try_join_all(downloads)
+                    .await
+                    .context("Failed to join role versions futures")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the archive URL of `version`, or the clone URL for `git`.
+    fn url(&self, data: &Value, version: &str) -> Result<String> {
+        let (forge, template) = match self.source {
+            RoleArchiveSource::Github => (
+                Some("https://github.com"),
+                "{forge}/{user}/{repo}/archive/{version}.tar.gz",
+            ),
+            RoleArchiveSource::Gitlab => (
+                Some("https://gitlab.com"),
+                "{forge}/{user}/{repo}/-/archive/{version}/{repo}-{version}.tar.gz",
+            ),
+            RoleArchiveSource::Gitea => (None, "{forge}/{user}/{repo}/archive/{version}.tar.gz"),
+            RoleArchiveSource::Git => (Some("https://github.com"), "{forge}/{user}/{repo}.git"),
+            RoleArchiveSource::Download => unreachable!("download URLs come from the remote"),
+        };
+        let template = self.url_template.as_deref().unwrap_or(template);
+        let forge = self.forge_url.as_deref().or(forge);
+        if template.contains("{forge}") && forge.is_none() {
+            return Err(anyhow!("Set the forge_url of the remote at {}", self.root));
+        }
+        Ok(template
+            .replace("{forge}", forge.unwrap_or("").trim_end_matches('/'))
+            .replace("{user}", data["github_user"].as_str().unwrap())
+            .replace("{repo}", data["github_repo"].as_str().unwrap())
+            .replace("{version}", version))
+    }
+
+    /// `download_url` of each version, from the role itself or else from its
+    /// related versions endpoint.
+    async fn download_urls(&self, data: &Value) -> Result<Vec<(String, String)>> {
+        let listed = |versions: &Value| -> Vec<(String, String)> {
+            versions
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|version| {
+                    Some((
+                        version["name"].as_str()?.to_string(),
+                        version["download_url"].as_str()?.to_string(),
+                    ))
+                })
+                .collect()
+        };
+        let mut urls = listed(&data["summary_fields"]["versions"]);
+        if !urls.is_empty() {
+            return Ok(urls);
+        }
+        let mut next = data["related"]["versions"].as_str().map(str::to_string);
+        while let Some(page) = next {
+            let page = self
+                .root
+                .join(&page)
+                .with_context(|| format!("Failed to join {page}"))?;
+            let results = get_json(page.as_str()).await?;
+            urls.extend(listed(&results["results"]));
+            next = results["next_link"]
+                .as_str()
+                .or(results["next"].as_str())
+                .map(str::to_string);
+        }
+        Ok(urls)
+    }
+}
+
+async fn download(url: String, version: &str, version_path: &str) -> Result<()> {
+    let download_url =
+        Url::parse(url.as_str()).with_context(|| format!("Failed to parse url {url}"))?;
+    let response = reqwest::get(download_url.as_str())
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Failed to download {download_url}"))?;
+    tokio::fs::create_dir_all(version_path)
+        .await
+        .with_context(|| format!("Failed to create dir {version_path}"))?;
+    info!("Downloading {download_url}");
+    download_tar(&format!("{version_path}{version}.tar.gz"), response).await
+}
@@ -0,0 +1,556 @@
+use super::save_namespaces;
+use super::validate::read_archive;
+use crate::config::{Config, ContentConfig};
+use crate::db_utils::DbConnection;
+use crate::models::{NamespaceData, RoleNew};
+use crate::tasks::Tasks;
+use actix_web::web;
+use anyhow::{anyhow, Context, Result};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::upsert::excluded;
+use log::info;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use url::Url;
+use uuid::Uuid;
+use yaml_rust::{Yaml, YamlLoader};
+
+const METAS: [&str; 2] = ["meta/main.yml", "meta/main.yaml"];
+const README: &str = "README.md";
+
+/// Everything wrong with an imported role, reported back through the import
+/// task.
+#[derive(Debug)]
+pub struct InvalidRole(pub Vec<String>);
+
+impl fmt::Display for InvalidRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid role: {}", self.0.join(" "))
+    }
+}
+
+impl std::error::Error for InvalidRole {}
+
+pub enum RoleSource {
+    /// A role tarball, stored as `version`.
+    Tarball { data: Vec<u8>, version: String },
+    /// A git repository path or URL; its tags become versions.
+    Git(String),
+}
+
+pub struct RoleImport {
+    pub namespace: String,
+    /// Defaults to `galaxy_info.role_name`, then to the repository name.
+    pub name: Option<String>,
+    pub source: RoleSource,
+    pub uploaded_by: String,
+}
+
+/// What an import keeps from `meta/main.yml`.
+struct RoleMeta {
+    role_name: Option<String>,
+    description: String,
+    tags: Vec<String>,
+    dependencies: Vec<String>,
+}
+
+pub async fn role_import_task(
+    task_uuid: &str,
+    import: RoleImport,
+    dpool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
+) -> Result<()> {
+    tasks
+        .set_state(task_uuid, "running")
+        .expect("Error setting task state");
+
+    // Cloning and archiving shell out to git, so keep it off the workers.
+    let result = web::block(move || import_role(import, &dpool, &config))
+        .await
+        .map_err(|err| anyhow!("{err}"))
+        .and_then(|result| result);
+    match &result {
+        Ok(()) => tasks
+            .set_state(task_uuid, "completed")
+            .expect("Error setting task state"),
+        Err(err) => {
+            let messages = match err.downcast_ref::<InvalidRole>() {
+                Some(invalid) => invalid.0.clone(),
+                None => vec![format!("{err:#}")],
+            };
+            tasks
+                .fail(task_uuid, &messages)
+                .expect("Error setting task state")
+        }
+    };
+    result
+}
+
+/// Checks a git `source` before it's cloned, as cloning makes requests from
+/// the server: http(s) URLs go, limited to `hosts` unless that's empty, and
+/// `file://` URLs and paths on the server only when `local` is allowed.
+pub fn check_git_source(source: &str, hosts: &[String], local: bool) -> Result<(), String> {
+    match Url::parse(source) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+            let host = url.host_str().unwrap_or("");
+            if host.is_empty()
+                || (!hosts.is_empty() && !hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+            {
+                return Err(format!("Roles can't be imported from '{host}'."));
+            }
+            Ok(())
+        }
+        Ok(url) if url.scheme() == "file" && local => Ok(()),
+        // Anything else that parses is another transport, e.g. ssh or git.
+        Ok(_) => Err("Only http(s) repositories can be imported.".to_string()),
+        // scp-like `host:path` is ssh, the rest is a path on the server.
+        Err(_) if local && !source.contains(':') && !source.starts_with('-') => Ok(()),
+        Err(_) => Err("Only http(s) repositories can be imported.".to_string()),
+    }
+}
+
+fn import_role(
+    import: RoleImport,
+    pool: &Pool<ConnectionManager<DbConnection>>,
+    config: &Config,
+) -> Result<()> {
+    match import.source {
+        RoleSource::Tarball {
+            ref data,
+            ref version,
+        } => {
+            let (meta, readme) = read_role_tarball(data, &config.content)?;
+            let name = role_name(&import, &meta, None)?;
+            let version_path = version_path(&import.namespace, &name, version)?;
+            if Path::new(&version_path).exists() {
+                return Err(InvalidRole(vec![format!(
+                    "{}.{name} {version} already exists.",
+                    import.namespace
+                )])
+                .into());
+            }
+            std::fs::create_dir_all(&version_path)
+                .with_context(|| format!("Failed to create dir {version_path}"))?;
+            std::fs::write(format!("{version_path}{version}.tar.gz"), data)
+                .with_context(|| format!("Failed to write {version_path}{version}.tar.gz"))?;
+            info!("Imported {}.{name} {version}", import.namespace);
+            save_role(pool, &import, &name, &meta, &readme, None)
+        }
+        RoleSource::Git(ref source) => {
+            // Redirects could leave the hosts the source was checked against.
+            let checkout = Checkout::clone(source, false)?;
+            let meta = METAS
+                .iter()
+                .find_map(|meta| checkout.git(&["show", &format!("HEAD:{meta}")]).ok())
+                .ok_or_else(|| InvalidRole(vec![format!("{} is missing.", METAS[0])]))?;
+            let meta = parse_meta(&meta)?;
+            let readme = checkout
+                .git(&["show", &format!("HEAD:{README}")])
+                .map(|readme| String::from_utf8_lossy(&readme).into_owned())
+                .unwrap_or_default();
+            let name = role_name(&import, &meta, Some(source))?;
+            let branch = checkout.git(&["symbolic-ref", "--short", "HEAD"])?;
+            let branch = String::from_utf8_lossy(&branch).trim().to_string();
+            let tags = checkout.git(&["tag", "--list"])?;
+            for tag in String::from_utf8_lossy(&tags).lines() {
+                let Ok(version_path) = version_path(&import.namespace, &name, tag) else {
+                    info!("Skipping tag {tag} of {source}");
+                    continue;
+                };
+                // Tags don't move; importing again only adds the new ones.
+                if Path::new(&version_path).exists() {
+                    continue;
+                }
+                checkout.archive(&name, &format!("refs/tags/{tag}"), tag, &version_path)?;
+                info!("Imported {}.{name} {tag}", import.namespace);
+            }
+            let version_path = version_path(&import.namespace, &name, &branch)?;
+            checkout.archive(&name, "HEAD", &branch, &version_path)?;
+            save_role(pool, &import, &name, &meta, &readme, Some(&branch))
+        }
+    }
+}
+
+/// The directory `role_version_list` serves `version` from. A leading `-`
+/// is refused too, since versions are also passed to git as refs.
+pub(super) fn version_path(
+    namespace: &str,
+    name: &str,
+    version: &str,
+) -> Result<String, InvalidRole> {
+    let valid = |part: &str| {
+        !part.is_empty() && !part.starts_with(['.', '-']) && !part.contains(['/', '\\'])
+    };
+    if !valid(version) {
+        return Err(InvalidRole(vec![format!(
+            "'{version}' can't be used as a version."
+        )]));
+    }
+    if !valid(namespace) || !valid(name) {
+        return Err(InvalidRole(vec![format!(
+            "'{namespace}.{name}' is not a valid role name."
+        )]));
+    }
+    Ok(format!(
+        "content/roles/{namespace}/{name}/versions/{version}/"
+    ))
+}
+
+fn role_name(import: &RoleImport, meta: &RoleMeta, source: Option<&str>) -> Result<String> {
+    let from_source = source.and_then(|source| {
+        let repo = source.trim_end_matches('/').rsplit(['/', ':']).next()?;
+        let repo = repo.strip_suffix(".git").unwrap_or(repo);
+        Some(
+            repo.strip_prefix("ansible-role-")
+                .unwrap_or(repo)
+                .to_string(),
+        )
+    });
+    import
+        .name
+        .clone()
+        .or_else(|| meta.role_name.clone())
+        .or(from_source)
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| {
+            InvalidRole(vec![
+                "Pass a name or set galaxy_info.role_name in meta/main.yml.".to_string(),
+            ])
+            .into()
+        })
+}
+
+/// Reads `meta/main.yml` and `README.md` from a role tarball, either at its
+/// root or inside a single top-level directory as git archives have them.
+fn read_role_tarball(
+    data: &[u8],
+    limits: &ContentConfig,
+) -> Result<(RoleMeta, String), InvalidRole> {
+    if data.len() as u64 > limits.max_upload_size {
+        return Err(InvalidRole(vec![format!(
+            "The archive is {} bytes, over the {} byte limit.",
+            data.len(),
+            limits.max_upload_size
+        )]));
+    }
+    let in_root = |name: &str, file: &str| {
+        name == file
+            || name
+                .strip_suffix(file)
+                .and_then(|dir| dir.strip_suffix('/'))
+                .is_some_and(|dir| !dir.contains('/'))
+    };
+    let keep = |name: &str| {
+        METAS
+            .iter()
+            .chain([&README])
+            .any(|file| in_root(name, file))
+    };
+    let mut errors = Vec::new();
+    let contents = read_archive(data, limits.max_unpacked_size, keep, &mut errors)
+        .map_err(|err| InvalidRole(vec![err]))?;
+    let mut metas: Vec<&String> = contents
+        .kept
+        .keys()
+        .filter(|name| METAS.iter().any(|meta| name.ends_with(meta)))
+        .collect();
+    metas.sort();
+    let meta = match metas.as_slice() {
+        [meta] => *meta,
+        [] => {
+            errors.push(format!("{} is missing.", METAS[0]));
+            return Err(InvalidRole(errors));
+        }
+        _ => {
+            errors.push("The archive holds more than one role.".to_string());
+            return Err(InvalidRole(errors));
+        }
+    };
+    let root = METAS
+        .iter()
+        .find_map(|file| meta.strip_suffix(file))
+        .unwrap();
+    let readme = contents
+        .kept
+        .get(&format!("{root}{README}"))
+        .map(|readme| String::from_utf8_lossy(readme).into_owned())
+        .unwrap_or_default();
+    let meta = match parse_meta(&contents.kept[meta]) {
+        Ok(meta) => meta,
+        Err(invalid) => {
+            errors.extend(invalid.0);
+            return Err(InvalidRole(errors));
+        }
+    };
+    if !errors.is_empty() {
+        return Err(InvalidRole(errors));
+    }
+    Ok((meta, readme))
+}
+
+fn parse_meta(meta: &[u8]) -> Result<RoleMeta, InvalidRole> {
+    let invalid = |message: String| InvalidRole(vec![message]);
+    let meta = std::str::from_utf8(meta)
+        .map_err(|_| invalid(format!("{} is not valid UTF-8.", METAS[0])))?;
+    let docs = YamlLoader::load_from_str(meta)
+        .map_err(|err| invalid(format!("{} is not valid YAML: {err}.", METAS[0])))?;
+    let Some(doc) = docs.first() else {
+        return Err(invalid(format!("{} is empty.", METAS[0])));
+    };
+    let info = &doc["galaxy_info"];
+    if info.as_hash().is_none() {
+        return Err(invalid(format!("{} has no galaxy_info.", METAS[0])));
+    }
+    let strings = |yaml: &Yaml| -> Vec<String> {
+        yaml.as_vec()
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item.as_str().map(str::to_string))
+            .collect()
+    };
+    // Dependencies are `ns.name` strings or hashes naming the role.
+    let dependencies = doc["dependencies"]
+        .as_vec()
+        .into_iter()
+        .flatten()
+        .filter_map(|dep| {
+            dep.as_str()
+                .or_else(|| {
+                    ["role", "name", "src"]
+                        .iter()
+                        .find_map(|key| dep[*key].as_str())
+                })
+                .map(str::to_string)
+        })
+        .collect();
+    Ok(RoleMeta {
+        role_name: info["role_name"].as_str().map(str::to_string),
+        description: info["description"].as_str().unwrap_or("").to_string(),
+        tags: strings(&info["galaxy_tags"]),
+        dependencies,
+    })
+}
+
+/// Upserts the role with the Galaxy-shaped metadata a sync would store,
+/// keeping what an earlier import or sync recorded where this one has nothing.
+fn save_role(
+    pool: &Pool<ConnectionManager<DbConnection>>,
+    import: &RoleImport,
+    name: &str,
+    meta: &RoleMeta,
+    readme: &str,
+    branch: Option<&str>,
+) -> Result<()> {
+    use crate::schema::roles;
+    let namespace = import.namespace.as_str();
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    let mut metadata = roles::table
+        .filter(roles::namespace.eq(namespace))
+        .filter(roles::name.eq(name))
+        .select(roles::metadata)
+        .first::<Value>(&mut conn)
+        .optional()?
+        .filter(Value::is_object)
+        .unwrap_or_else(|| json!({}));
+    let versions: Vec<Value> =
+        std::fs::read_dir(format!("content/roles/{namespace}/{name}/versions"))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .filter(|v| !v.starts_with('.') && Some(v.as_str()) != branch)
+                    .map(|v| json!({ "name": v }))
+                    .collect()
+            })
+            .unwrap_or_default();
+    let tags = json!(meta.tags);
+    metadata["name"] = json!(name);
+    metadata["description"] = json!(meta.description);
+    metadata["uploaded_by"] = json!(import.uploaded_by);
+    metadata["groot"] = json!(true);
+    if let Some(branch) = branch {
+        metadata["github_branch"] = json!(branch);
+    }
+    if !metadata["summary_fields"].is_object() {
+        metadata["summary_fields"] = json!({});
+    }
+    let summary = &mut metadata["summary_fields"];
+    summary["namespace"] = json!({ "name": namespace });
+    summary["tags"] = tags.clone();
+    summary["versions"] = Value::Array(versions);
+    summary["dependencies"] = meta
+        .dependencies
+        .iter()
+        .map(|dep| json!({ "name": dep }))
+        .collect();
+    let role = RoleNew {
+        namespace,
+        name,
+        description: &meta.description,
+        tags: &tags,
+        readme,
+        metadata: &metadata,
+    };
+    conn.transaction(|conn| {
+        save_namespaces(
+            conn,
+            &[NamespaceData::from_json(namespace, &json!(null))],
+            false,
+        )?;
+        diesel::insert_into(roles::table)
+            .values(&role)
+            .on_conflict((roles::namespace, roles::name))
+            .do_update()
+            .set((
+                roles::description.eq(excluded(roles::description)),
+                roles::tags.eq(excluded(roles::tags)),
+                roles::readme.eq(excluded(roles::readme)),
+                roles::metadata.eq(excluded(roles::metadata)),
+            ))
+            .execute(conn)?;
+        Result::<()>::Ok(())
+    })
+    .context("Failed to save role")
+}
+
+/// A bare clone in a temporary directory, removed when dropped.
+pub(super) struct Checkout(PathBuf);
+
+impl Checkout {
+    pub(super) fn clone(source: &str, follow_redirects: bool) -> Result<Self> {
+        let checkout =
+            Checkout(std::env::temp_dir().join(format!("groot-role-{}", Uuid::new_v4())));
+        let redirects = if follow_redirects { "initial" } else { "false" };
+        run(Command::new("git")
+            .args(["-c", &format!("http.followRedirects={redirects}")])
+            .args(["clone", "--quiet", "--bare", "--"])
+            .arg(source)
+            .arg(&checkout.0))
+        .with_context(|| format!("Failed to clone {source}"))?;
+        Ok(checkout)
+    }
+
+    pub(super) fn git(&self, args: &[&str]) -> Result<Vec<u8>> {
+        run(Command::new("git").arg("-C").arg(&self.0).args(args))
+    }
+
+    /// Writes `reference` as `{version_path}{version}.tar.gz`, with the
+    /// `{name}-{version}/` prefix GitHub archives have.
+    pub(super) fn archive(
+        &self,
+        name: &str,
+        reference: &str,
+        version: &str,
+        version_path: &str,
+    ) -> Result<()> {
+        std::fs::create_dir_all(version_path)
+            .with_context(|| format!("Failed to create dir {version_path}"))?;
+        let output = std::env::current_dir()?.join(format!("{version_path}{version}.tar.gz"));
+        self.git(&[
+            "archive",
+            "--format=tar.gz",
+            &format!("--prefix={name}-{version}/"),
+            &format!("--output={}", output.display()),
+            reference,
+        ])
+        .with_context(|| format!("Failed to archive {reference}"))?;
+        Ok(())
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn run(command: &mut Command) -> Result<Vec<u8>> {
+    // Never wait on a credentials prompt nobody will answer.
+    let output = command.env("GIT_TERMINAL_PROMPT", "0").output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_sources_are_limited_to_http() {
+        let any: &[String] = &[];
+        assert_eq!(
+            check_git_source("https://github.com/o/r", any, false),
+            Ok(())
+        );
+        assert_eq!(
+            check_git_source("http://git.example.com/r.git", any, false),
+            Ok(())
+        );
+        for source in [
+            "file:///srv/git/r",
+            "/srv/git/r",
+            "../r",
+            "ssh://git@github.com/o/r",
+            "git@github.com:o/r.git",
+            "git://github.com/o/r",
+            "ext::sh -c touch% /tmp/pwned",
+            "--upload-pack=touch /tmp/pwned",
+        ] {
+            assert!(check_git_source(source, any, false).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn local_sources_need_local() {
+        let any: &[String] = &[];
+        assert_eq!(check_git_source("file:///srv/git/r", any, true), Ok(()));
+        assert_eq!(check_git_source("/srv/git/r", any, true), Ok(()));
+        assert!(check_git_source("ssh://github.com/o/r", any, true).is_err());
+        assert!(check_git_source("git@github.com:o/r.git", any, true).is_err());
+        assert!(check_git_source("-r", any, true).is_err());
+    }
+
+    #[test]
+    fn hosts_are_allowlisted() {
+        let hosts = ["github.com".to_string()];
+        assert_eq!(
+            check_git_source("https://GitHub.com/o/r", &hosts, false),
+            Ok(())
+        );
+        assert_eq!(
+            check_git_source("http://169.254.169.254/latest", &hosts, false),
+            Err("Roles can't be imported from '169.254.169.254'.".to_string())
+        );
+        assert!(check_git_source("https://github.com.evil.net/o/r", &hosts, false).is_err());
+    }
+
+    #[test]
+    fn versions_stay_in_their_directory() {
+        assert_eq!(
+            version_path("acme", "web", "v1.2.0").unwrap(),
+            "content/roles/acme/web/versions/v1.2.0/"
+        );
+        for version in [
+            "",
+            ".",
+            "..",
+            ".git",
+            "../../x",
+            "a/b",
+            "a\\b",
+            "--output=x",
+        ] {
+            assert!(version_path("acme", "web", version).is_err(), "{}", version);
+        }
+        assert!(version_path("..", "web", "1.0.0").is_err());
+        assert!(version_path("acme", "-web", "1.0.0").is_err());
+    }
+}
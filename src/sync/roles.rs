@@ -1,15 +1,30 @@
-use super::{download_tar, get_json};
+use super::role_archives::RoleRemote;
+use super::{get_json, save_namespaces};
+use crate::db_utils::DbConnection;
+use crate::models::{NamespaceData, RoleNew};
+use actix_web::web;
 use anyhow::{Context, Result};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::upsert::excluded;
 use futures::future::try_join_all;
 use log::info;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::future::Future;
 use std::pin::Pin;
 use url::Url;
 
-pub async fn sync_roles(response: &Value) -> Result<()> {
+pub async fn sync_roles(
+    pool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    remote: &RoleRemote,
+    response: &Value,
+) -> Result<()> {
     let results = response.as_object().unwrap()["results"].as_array().unwrap();
-    let role_futures: Vec<_> = results.iter().map(fetch_role).collect();
+    save_roles(&pool, results)?;
+    let role_futures: Vec<_> = results
+        .iter()
+        .map(|data| fetch_role(pool.clone(), remote, data))
+        .collect();
     try_join_all(role_futures)
         .await
         .context("Failed to join roles futures")?;
@@ -17,7 +32,58 @@ pub async fn sync_roles(response: &Value) -> Result<()> {
     Ok(())
 }
 
-async fn fetch_role(data: &Value) -> Result<()> {
+fn save_roles(pool: &Pool<ConnectionManager<DbConnection>>, results: &[Value]) -> Result<()> {
+    use crate::schema::roles::dsl::*;
+    let empty_tags = json!([]);
+    let to_save: Vec<RoleNew> = results
+        .iter()
+        .map(|data| RoleNew {
+            namespace: data["summary_fields"]["namespace"]["name"]
+                .as_str()
+                .unwrap(),
+            name: data["name"].as_str().unwrap(),
+            description: data["description"].as_str().unwrap_or(""),
+            tags: match data["summary_fields"]["tags"] {
+                Value::Array(_) => &data["summary_fields"]["tags"],
+                _ => &empty_tags,
+            },
+            readme: data["readme"].as_str().unwrap_or(""),
+            metadata: data,
+        })
+        .collect();
+    if to_save.is_empty() {
+        return Ok(());
+    }
+    let owners: Vec<NamespaceData> = results
+        .iter()
+        .map(|data| {
+            let owner = &data["summary_fields"]["namespace"];
+            NamespaceData::from_json(owner["name"].as_str().unwrap(), owner)
+        })
+        .collect();
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    save_namespaces(&mut conn, &owners, false).context("Failed to save namespaces")?;
+    info!("Inserting role data into the DB");
+    diesel::insert_into(roles)
+        .values(&to_save)
+        .on_conflict((namespace, name))
+        .do_update()
+        .set((
+            description.eq(excluded(description)),
+            tags.eq(excluded(tags)),
+            readme.eq(excluded(readme)),
+            metadata.eq(excluded(metadata)),
+        ))
+        .execute(&mut conn)
+        .context("Failed to save roles")?;
+    Ok(())
+}
+
+async fn fetch_role(
+    pool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    remote: &RoleRemote,
+    data: &Value,
+) -> Result<()> {
     let content_path = format!(
         "content/roles/{}/{}/",
         data["summary_fields"]["namespace"]["name"]
@@ -28,10 +94,10 @@ async fn fetch_role(data: &Value) -> Result<()> {
     tokio::fs::create_dir_all(&content_path)
         .await
         .with_context(|| format!("Failed to create dir {content_path}"))?;
-    fetch_versions(data)
+    fetch_versions(remote, data)
         .await
         .with_context(|| format!("Failed to fetch role versions from {}", data["commit_url"]))?;
-    let dependencies: Vec<String> = data["summary_fields"]["dependencies"]
+    let dependencies: Vec<Url> = data["summary_fields"]["dependencies"]
         .as_array()
         .unwrap()
         .iter()
@@ -48,74 +114,39 @@ async fn fetch_role(data: &Value) -> Result<()> {
                 d["name"].as_str().unwrap().replace('.', "/")
             );
             std::fs::create_dir_all(dep_path).unwrap();
-            format!(
-                "https://galaxy.ansible.com/api/v1/roles/?namespace={}",
-                d["name"].as_str().unwrap().replace('.', "&name=")
-            )
+            remote.role_url(d["name"].as_str().unwrap())
         })
-        .collect();
+        .collect::<Result<_>>()?;
     if !dependencies.is_empty() {
-        fetch_dependencies(dependencies).await;
+        fetch_dependencies(pool, remote.clone(), dependencies).await;
     }
     Ok(())
 }
-async fn fetch_versions(data: &Value) -> Result<()> {
-    let versions = data["summary_fields"]["versions"].as_array().unwrap();
-    let version_futures: Vec<_> = versions
+async fn fetch_versions(remote: &RoleRemote, data: &Value) -> Result<()> {
+    let mut versions: Vec<String> = data["summary_fields"]["versions"]
+        .as_array()
+        .unwrap()
         .iter()
-        .map(|version| fetch_role_version(data, &version["name"]))
+        .map(|version| version["name"].as_str().unwrap().to_string())
         .collect();
-    try_join_all(version_futures)
-        .await
-        .context("Failed to join role versions futures")?;
-    if data["github_branch"].as_str().is_some() {
-        fetch_role_version(data, &data["github_branch"]).await?;
+    if let Some(branch) = data["github_branch"].as_str() {
+        versions.push(branch.to_string());
     }
-    Ok(())
-}
-async fn fetch_role_version(data: &Value, version: &Value) -> Result<()> {
-    let namespace = data["summary_fields"]["namespace"]["name"]
-        .as_str()
-        .unwrap();
-    let name = data["name"].as_str().unwrap();
-    let version_path = format!(
-        "content/roles/{}/{}/versions/{}/",
-        namespace,
-        name,
-        version.as_str().unwrap(),
-    );
-    tokio::fs::create_dir_all(&version_path)
-        .await
-        .with_context(|| format!("Failed to create dir {version_path}"))?;
-    let github_url = format!(
-        "https://github.com/{}/{}/archive/{}.tar.gz",
-        data["github_user"].as_str().unwrap(),
-        data["github_repo"].as_str().unwrap(),
-        version.as_str().unwrap()
-    );
-    let download_url = Url::parse(github_url.as_str())
-        .with_context(|| format!("Failed to parse url {github_url}"))?;
-    let response = reqwest::get(download_url.as_str())
-        .await
-        .with_context(|| format!("Failed to download {download_url}"))?;
-    let filename = download_url.path_segments().unwrap().next_back().unwrap();
-    info!(
-        "Downloading {}-{}-{}",
-        namespace,
-        name,
-        version.as_str().unwrap()
-    );
-    download_tar(format!("{version_path}{filename}").as_str(), response)
-        .await
-        .unwrap();
-    Ok(())
+    remote.fetch(data, versions).await
 }
 
-fn fetch_dependencies(dependencies: Vec<String>) -> Pin<Box<dyn Future<Output = ()>>> {
+fn fetch_dependencies(
+    pool: web::Data<Pool<ConnectionManager<DbConnection>>>,
+    remote: RoleRemote,
+    dependencies: Vec<Url>,
+) -> Pin<Box<dyn Future<Output = ()>>> {
     Box::pin(async move {
-        let deps: Vec<_> = dependencies.iter().map(|x| get_json(x)).collect();
+        let deps: Vec<_> = dependencies.iter().map(|x| get_json(x.as_str())).collect();
         let deps_json = try_join_all(deps).await.unwrap();
-        let to_fetch: Vec<_> = deps_json.iter().map(sync_roles).collect();
+        let to_fetch: Vec<_> = deps_json
+            .iter()
+            .map(|d| sync_roles(pool.clone(), &remote, d))
+            .collect();
         try_join_all(to_fetch).await.unwrap();
     })
 }
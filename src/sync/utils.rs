@@ -1,4 +1,5 @@
-use anyhow::{Context, Result};
+use crate::config::SyncConfig;
+use anyhow::{anyhow, Context, Result};
 use log::warn;
 use reqwest::{Client, Request, Response};
 use serde_json::Value;
@@ -62,25 +63,14 @@ pub async fn get_json(url: &str) -> Result<Value> {
     Ok(values)
 }
 
-pub fn build_service(client: Client) -> Buffer<ConcurrencyLimit<RateLimit<Client>>, Request> {
-    let buffer = dotenv::var("GROOT_BUFFER")
-        .unwrap_or("100".to_string())
-        .as_str()
-        .parse::<usize>()
-        .unwrap();
-    let limit = dotenv::var("GROOT_CONCURRENCY_LIMIT")
-        .unwrap_or("10".to_string())
-        .as_str()
-        .parse::<usize>()
-        .unwrap();
-    let total_req = dotenv::var("GROOT_TOTAL_REQUESTS_PER_SECOND")
-        .unwrap_or("5".to_string())
-        .parse::<u64>()
-        .unwrap();
+pub fn build_service(
+    client: Client,
+    config: &SyncConfig,
+) -> Buffer<ConcurrencyLimit<RateLimit<Client>>, Request> {
     tower::ServiceBuilder::new()
-        .buffer(buffer)
-        .concurrency_limit(limit)
-        .rate_limit(total_req, Duration::from_secs(1))
+        .buffer(config.buffer)
+        .concurrency_limit(config.concurrency_limit)
+        .rate_limit(config.requests_per_second, Duration::from_secs(1))
         .service(client.clone())
 }
 
@@ -100,3 +90,23 @@ pub async fn request(
     let response = service.call(http_request).await.unwrap();
     (service, response)
 }
+
+/// `get_json` through `service`, so the request waits its turn under the
+/// sync's buffer, concurrency and rate limits.
+pub async fn request_json(
+    url: &str,
+    mut service: Buffer<ConcurrencyLimit<RateLimit<Client>>, Request>,
+) -> Result<Value> {
+    let http_request = Client::new().get(url).build()?;
+    let response = service
+        .ready()
+        .await
+        .map_err(|err| anyhow!("{err}"))?
+        .call(http_request)
+        .await
+        .map_err(|err| anyhow!("Failed to get {url}: {err}"))?;
+    response
+        .json::<Value>()
+        .await
+        .with_context(|| format!("Failed to parse JSON from {url}"))
+}
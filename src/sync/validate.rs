@@ -0,0 +1,634 @@
+use crate::config::ContentConfig;
+use flate2::read::GzDecoder;
+use semver::Version;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+use std::path::{Component, Path};
+use tar::EntryType;
+use yaml_rust::YamlLoader;
+
+const MANIFEST: &str = "MANIFEST.json";
+const RUNTIME: &str = "meta/runtime.yml";
+
+/// Everything wrong with an uploaded collection, reported back through the
+/// import task.
+#[derive(Debug)]
+pub struct InvalidCollection(pub Vec<String>);
+
+impl fmt::Display for InvalidCollection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid collection: {}", self.0.join(" "))
+    }
+}
+
+impl std::error::Error for InvalidCollection {}
+
+/// Whether `value` can be a collection namespace or name: lowercase letters,
+/// digits and underscores.
+pub fn is_collection_name(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+/// Whether `value` can name a role's namespace, name or version. These come
+/// from GitHub and git tags, so anything that stays one visible path
+/// component goes.
+pub fn is_role_segment(value: &str) -> bool {
+    !value.is_empty() && !value.starts_with('.') && !value.contains(['/', '\\', '\0'])
+}
+
+/// Splits `<namespace>-<name>-<version>.tar.gz`; namespaces and names can't
+/// contain `-`, but prerelease versions can. `None` unless the namespace and
+/// name are valid collection names and the version is semver, since they
+/// end up in paths.
+pub fn parse_filename(filename: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = filename.strip_suffix(".tar.gz")?.splitn(3, '-');
+    let (namespace, name, version) = (parts.next()?, parts.next()?, parts.next()?);
+    if !is_collection_name(namespace) || !is_collection_name(name) {
+        return None;
+    }
+    Version::parse(version).ok()?;
+    Some((namespace, name, version))
+}
+
+/// What an import keeps from a valid collection tarball.
+pub struct CollectionArchive {
+    /// `collection_info` from `MANIFEST.json`.
+    pub collection_info: Value,
+    pub sha256: String,
+    pub readme: String,
+}
+
+/// The archive as read: file checksums, directories and links, plus the
+/// contents of the few files that get parsed.
+#[derive(Default)]
+pub(super) struct Contents {
+    pub checksums: HashMap<String, String>,
+    pub dirs: HashSet<String>,
+    pub links: HashMap<String, String>,
+    pub kept: HashMap<String, Vec<u8>>,
+}
+
+/// Checks a collection tarball before it's imported: a well-formed archive
+/// within the size limits, paths and links that stay inside it, and
+/// `MANIFEST.json`/`FILES.json` agreeing with its contents and file name.
+pub fn validate_collection(
+    data: &[u8],
+    namespace: &str,
+    name: &str,
+    version: &str,
+    limits: &ContentConfig,
+) -> Result<CollectionArchive, InvalidCollection> {
+    if data.len() as u64 > limits.max_upload_size {
+        return Err(InvalidCollection(vec![format!(
+            "The archive is {} bytes, over the {} byte limit.",
+            data.len(),
+            limits.max_upload_size
+        )]));
+    }
+    let mut errors = Vec::new();
+    // The manifests and the README live at the top level.
+    let keep = |name: &str| {
+        name == RUNTIME || (!name.contains('/') && (name.ends_with(".json") || is_readme(name)))
+    };
+    let contents = read_archive(data, limits.max_unpacked_size, keep, &mut errors)
+        .map_err(|err| InvalidCollection(vec![err]))?;
+
+    let Some(manifest) = contents.kept.get(MANIFEST) else {
+        errors.push(format!("{MANIFEST} is missing."));
+        return Err(InvalidCollection(errors));
+    };
+    let manifest: Value = match serde_json::from_slice(manifest) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            errors.push(format!("{MANIFEST} is not valid JSON: {err}."));
+            return Err(InvalidCollection(errors));
+        }
+    };
+    let info = &manifest["collection_info"];
+    for (field, expected) in [
+        ("namespace", namespace),
+        ("name", name),
+        ("version", version),
+    ] {
+        let found = info[field].as_str().unwrap_or("");
+        if found != expected {
+            errors.push(format!(
+                "{MANIFEST} has {field} '{found}' but the file name says '{expected}'."
+            ));
+        }
+    }
+    if Version::parse(version).is_err() {
+        errors.push(format!("'{version}' is not a valid semantic version."));
+    }
+    check_files(&manifest, &contents, &mut errors);
+    check_runtime(&contents, &mut errors);
+
+    if !errors.is_empty() {
+        return Err(InvalidCollection(errors));
+    }
+    Ok(CollectionArchive {
+        readme: pick_readme(info, &contents.kept),
+        collection_info: info.clone(),
+        sha256: format!("{:x}", Sha256::digest(data)),
+    })
+}
+
+/// The README of a collection tarball, for synced collections that aren't
+/// validated; empty when the archive can't be read or has none.
+pub fn read_readme(data: &[u8]) -> String {
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    let mut kept = HashMap::new();
+    let Ok(entries) = archive.entries() else {
+        return String::new();
+    };
+    for mut entry in entries.flatten() {
+        let Some(name) = entry.path().ok().and_then(|path| normalize(&path)) else {
+            continue;
+        };
+        let regular = entry.header().entry_type() == EntryType::Regular;
+        if regular && (name == MANIFEST || is_readme(&name)) {
+            let mut content = Vec::new();
+            if entry.read_to_end(&mut content).is_ok() {
+                kept.insert(name, content);
+            }
+        }
+    }
+    let manifest: Value = kept
+        .get(MANIFEST)
+        .and_then(|manifest| serde_json::from_slice(manifest).ok())
+        .unwrap_or_default();
+    pick_readme(&manifest["collection_info"], &kept)
+}
+
+fn is_readme(name: &str) -> bool {
+    !name.contains('/') && name.to_ascii_lowercase().starts_with("readme")
+}
+
+/// The file `collection_info` names as the README, `README.md` by default.
+fn pick_readme(info: &Value, kept: &HashMap<String, Vec<u8>>) -> String {
+    let name = info["readme"].as_str().unwrap_or("README.md");
+    kept.get(name)
+        .map(|readme| String::from_utf8_lossy(readme).into_owned())
+        .unwrap_or_default()
+}
+
+/// Walks the archive once, hashing every file and keeping the ones `keep`
+/// asks for. Problems with single entries are collected; a corrupt stream or
+/// an archive over the limit stops it.
+pub(super) fn read_archive(
+    data: &[u8],
+    max_unpacked_size: u64,
+    keep: impl Fn(&str) -> bool,
+    errors: &mut Vec<String>,
+) -> Result<Contents, String> {
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    let entries = archive
+        .entries()
+        .map_err(|err| format!("Not a gzipped tar archive: {err}."))?;
+    let mut contents = Contents::default();
+    let mut unpacked: u64 = 0;
+    for entry in entries {
+        let mut entry = entry.map_err(|err| format!("The archive is corrupt: {err}."))?;
+        let path = entry
+            .path()
+            .map_err(|err| format!("The archive is corrupt: {err}."))?
+            .into_owned();
+        let Some(name) = normalize(&path) else {
+            errors.push(format!(
+                "'{}' must be a relative path inside the archive.",
+                path.display()
+            ));
+            continue;
+        };
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => {
+                let mut content = Vec::new();
+                (&mut entry)
+                    .take(max_unpacked_size - unpacked + 1)
+                    .read_to_end(&mut content)
+                    .map_err(|err| format!("The archive is corrupt: {err}."))?;
+                unpacked += content.len() as u64;
+                if unpacked > max_unpacked_size {
+                    return Err(format!(
+                        "The archive unpacks to more than {max_unpacked_size} bytes."
+                    ));
+                }
+                contents
+                    .checksums
+                    .insert(name.clone(), format!("{:x}", Sha256::digest(&content)));
+                if keep(&name) {
+                    contents.kept.insert(name, content);
+                }
+            }
+            EntryType::Directory => {
+                contents.dirs.insert(name);
+            }
+            kind @ (EntryType::Symlink | EntryType::Link) => {
+                let target = entry.link_name().ok().flatten().map(|t| t.into_owned());
+                // Symlinks are relative to their directory, hard links to the root.
+                let resolved = target.as_deref().and_then(|target| {
+                    if kind == EntryType::Symlink {
+                        let parent = Path::new(&name).parent().unwrap_or(Path::new(""));
+                        normalize(&parent.join(target))
+                    } else {
+                        normalize(target)
+                    }
+                });
+                match resolved {
+                    Some(resolved) => {
+                        contents.links.insert(name, resolved);
+                    }
+                    None => errors.push(format!("Link '{name}' points outside the archive.")),
+                }
+            }
+            EntryType::XGlobalHeader | EntryType::XHeader => {}
+            _ => errors.push(format!("'{name}' is not a file, directory or link.")),
+        }
+    }
+    Ok(contents)
+}
+
+/// Joins the normal components of `path` with `/`, or `None` when it's
+/// absolute or climbs out of the root. The root itself is `.`.
+fn normalize(path: &Path) -> Option<String> {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if parts.is_empty() {
+        Some(".".to_string())
+    } else {
+        Some(parts.join("/"))
+    }
+}
+
+/// Checks `FILES.json` against its checksum in `MANIFEST.json`, and the
+/// archive against `FILES.json`.
+fn check_files(manifest: &Value, contents: &Contents, errors: &mut Vec<String>) {
+    let files_manifest = &manifest["file_manifest_file"];
+    let files_name = files_manifest["name"].as_str().unwrap_or("FILES.json");
+    let Some(files) = contents.kept.get(files_name) else {
+        errors.push(format!("{files_name} is missing."));
+        return;
+    };
+    if files_manifest["chksum_sha256"].as_str()
+        != contents.checksums.get(files_name).map(String::as_str)
+    {
+        errors.push(format!(
+            "{files_name} doesn't match its checksum in {MANIFEST}."
+        ));
+    }
+    let files: Value = match serde_json::from_slice(files) {
+        Ok(files) => files,
+        Err(err) => {
+            errors.push(format!("{files_name} is not valid JSON: {err}."));
+            return;
+        }
+    };
+    let checksum = |name: &str| {
+        let name = contents.links.get(name).map(String::as_str).unwrap_or(name);
+        contents.checksums.get(name)
+    };
+    let mut listed = HashSet::new();
+    for file in files["files"].as_array().into_iter().flatten() {
+        let Some(name) = file["name"].as_str() else {
+            errors.push(format!("{files_name} has an entry without a name."));
+            continue;
+        };
+        listed.insert(name);
+        match file["ftype"].as_str() {
+            Some("dir") => {
+                let prefix = format!("{name}/");
+                let present = name == "."
+                    || contents.dirs.contains(name)
+                    || contents
+                        .checksums
+                        .keys()
+                        .any(|file| file.starts_with(&prefix));
+                if !present {
+                    errors.push(format!(
+                        "Directory '{name}' is listed in {files_name} but missing."
+                    ));
+                }
+            }
+            Some("file") => match checksum(name) {
+                None => errors.push(format!("'{name}' is listed in {files_name} but missing.")),
+                Some(found) if file["chksum_sha256"].as_str() != Some(found) => errors.push(
+                    format!("'{name}' doesn't match its checksum in {files_name}."),
+                ),
+                Some(_) => {}
+            },
+            _ => errors.push(format!("'{name}' has an unknown ftype in {files_name}.")),
+        }
+    }
+    let mut unlisted: Vec<&String> = contents
+        .checksums
+        .keys()
+        .chain(contents.links.keys())
+        .filter(|name| *name != MANIFEST && *name != files_name && !listed.contains(name.as_str()))
+        .collect();
+    unlisted.sort();
+    for name in unlisted {
+        errors.push(format!("'{name}' is not listed in {files_name}."));
+    }
+}
+
+/// Galaxy requires `requires_ansible` so clients know which ansible-core
+/// releases the collection supports.
+fn check_runtime(contents: &Contents, errors: &mut Vec<String>) {
+    let requires_ansible = contents
+        .kept
+        .get(RUNTIME)
+        .and_then(|runtime| std::str::from_utf8(runtime).ok())
+        .and_then(|runtime| YamlLoader::load_from_str(runtime).ok())
+        .and_then(|docs| {
+            docs.first()?["requires_ansible"]
+                .as_str()
+                .map(str::to_string)
+        })
+        .filter(|requires_ansible| !requires_ansible.trim().is_empty());
+    if requires_ansible.is_none() {
+        errors.push(format!("{RUNTIME} must set requires_ansible."));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use serde_json::json;
+    use tar::Header;
+
+    enum Entry<'a> {
+        File(&'a str, &'a [u8]),
+        Dir(&'a str),
+        Symlink(&'a str, &'a str),
+    }
+
+    /// Builds a gzipped tarball, writing names as given so archives can hold
+    /// the paths `tar::Builder` refuses.
+    fn tarball(entries: &[Entry]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+        for entry in entries {
+            let mut header = Header::new_gnu();
+            let (name, data): (&str, &[u8]) = match entry {
+                Entry::File(name, data) => {
+                    header.set_entry_type(EntryType::Regular);
+                    (name, data)
+                }
+                Entry::Dir(name) => {
+                    header.set_entry_type(EntryType::Directory);
+                    (name, b"")
+                }
+                Entry::Symlink(name, target) => {
+                    header.set_entry_type(EntryType::Symlink);
+                    header.as_gnu_mut().unwrap().linkname[..target.len()]
+                        .copy_from_slice(target.as_bytes());
+                    (name, b"")
+                }
+            };
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    fn read(data: &[u8]) -> (Contents, Vec<String>) {
+        let mut errors = Vec::new();
+        let contents = read_archive(data, 1024, |_| true, &mut errors).unwrap();
+        (contents, errors)
+    }
+
+    /// `MANIFEST.json` for a `FILES.json` listing `files`.
+    fn manifests(files: Value) -> (Vec<u8>, Vec<u8>) {
+        let files = serde_json::to_vec(&json!({ "files": files })).unwrap();
+        let manifest = json!({
+            "collection_info": {"namespace": "ns", "name": "col", "version": "1.0.0"},
+            "file_manifest_file": {"name": "FILES.json", "chksum_sha256": sha256(&files)},
+        });
+        (serde_json::to_vec(&manifest).unwrap(), files)
+    }
+
+    fn check(entries: &[Entry], manifest: &[u8]) -> Vec<String> {
+        let (contents, mut errors) = read(&tarball(entries));
+        check_files(
+            &serde_json::from_slice(manifest).unwrap(),
+            &contents,
+            &mut errors,
+        );
+        errors
+    }
+
+    #[test]
+    fn parse_filename_splits_valid_names() {
+        assert_eq!(
+            parse_filename("my_ns-col2-1.0.0.tar.gz"),
+            Some(("my_ns", "col2", "1.0.0"))
+        );
+        assert_eq!(
+            parse_filename("ns-col-1.0.0-rc.1+build-7.tar.gz"),
+            Some(("ns", "col", "1.0.0-rc.1+build-7"))
+        );
+    }
+
+    #[test]
+    fn parse_filename_rejects_invalid_names() {
+        for filename in [
+            "ns-col-1.0.0.tgz",
+            "ns-col.tar.gz",
+            "-col-1.0.0.tar.gz",
+            "Ns-col-1.0.0.tar.gz",
+            "ns-co.l-1.0.0.tar.gz",
+            "..-col-1.0.0.tar.gz",
+            "ns-col-1.0.tar.gz",
+            "ns-col-1.0.0/../x.tar.gz",
+            "ns-col-../1.0.0.tar.gz",
+        ] {
+            assert_eq!(parse_filename(filename), None, "{filename}");
+        }
+    }
+
+    #[test]
+    fn normalize_keeps_paths_inside_the_root() {
+        assert_eq!(normalize(Path::new("a/b")).as_deref(), Some("a/b"));
+        assert_eq!(normalize(Path::new("./a/../b/")).as_deref(), Some("b"));
+        assert_eq!(normalize(Path::new(".")).as_deref(), Some("."));
+        assert_eq!(normalize(Path::new("a/..")).as_deref(), Some("."));
+        assert_eq!(normalize(Path::new("/etc/passwd")), None);
+        assert_eq!(normalize(Path::new("../a")), None);
+        assert_eq!(normalize(Path::new("a/../../b")), None);
+    }
+
+    #[test]
+    fn read_archive_rejects_members_outside_the_root() {
+        let (contents, errors) = read(&tarball(&[
+            Entry::File("/etc/passwd", b"root"),
+            Entry::File("../escape", b"x"),
+            Entry::File("a/../../escape", b"x"),
+            Entry::File("ok", b"ok"),
+        ]));
+        assert_eq!(
+            errors,
+            [
+                "'/etc/passwd' must be a relative path inside the archive.",
+                "'../escape' must be a relative path inside the archive.",
+                "'a/../../escape' must be a relative path inside the archive.",
+            ]
+        );
+        assert_eq!(contents.checksums.keys().collect::<Vec<_>>(), ["ok"]);
+    }
+
+    #[test]
+    fn read_archive_resolves_symlinks_inside_the_root() {
+        let (contents, errors) = read(&tarball(&[
+            Entry::File("docs/a.md", b"a"),
+            Entry::Symlink("docs/b.md", "a.md"),
+            Entry::Symlink("docs/up.md", "../docs/a.md"),
+            Entry::Symlink("passwd", "/etc/passwd"),
+            Entry::Symlink("docs/out", "../../out"),
+        ]));
+        assert_eq!(
+            errors,
+            [
+                "Link 'passwd' points outside the archive.",
+                "Link 'docs/out' points outside the archive.",
+            ]
+        );
+        assert_eq!(contents.links["docs/b.md"], "docs/a.md");
+        assert_eq!(contents.links["docs/up.md"], "docs/a.md");
+        assert_eq!(contents.links.len(), 2);
+    }
+
+    #[test]
+    fn check_files_accepts_a_matching_archive() {
+        let (manifest, files) = manifests(json!([
+            {"name": ".", "ftype": "dir"},
+            {"name": "docs", "ftype": "dir"},
+            {"name": "docs/a.md", "ftype": "file", "chksum_sha256": sha256(b"a")},
+            {"name": "docs/b.md", "ftype": "file", "chksum_sha256": sha256(b"a")},
+        ]));
+        let errors = check(
+            &[
+                Entry::File("MANIFEST.json", &manifest),
+                Entry::File("FILES.json", &files),
+                Entry::Dir("docs"),
+                Entry::File("docs/a.md", b"a"),
+                Entry::Symlink("docs/b.md", "a.md"),
+            ],
+            &manifest,
+        );
+        assert_eq!(errors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn check_files_reports_mismatches() {
+        let (manifest, files) = manifests(json!([
+            {"name": "changed", "ftype": "file", "chksum_sha256": sha256(b"before")},
+            {"name": "missing", "ftype": "file", "chksum_sha256": sha256(b"x")},
+            {"name": "gone", "ftype": "dir"},
+            {"name": "odd", "ftype": "fifo"},
+        ]));
+        let errors = check(
+            &[
+                Entry::File("MANIFEST.json", &manifest),
+                Entry::File("FILES.json", &files),
+                Entry::File("changed", b"after"),
+                Entry::File("extra", b"x"),
+                Entry::Symlink("link", "extra"),
+            ],
+            &manifest,
+        );
+        assert_eq!(
+            errors,
+            [
+                "'changed' doesn't match its checksum in FILES.json.",
+                "'missing' is listed in FILES.json but missing.",
+                "Directory 'gone' is listed in FILES.json but missing.",
+                "'odd' has an unknown ftype in FILES.json.",
+                "'extra' is not listed in FILES.json.",
+                "'link' is not listed in FILES.json.",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_files_requires_the_recorded_files_checksum() {
+        let (manifest, _) = manifests(json!([]));
+        let files = serde_json::to_vec(&json!({"files": []})).unwrap();
+        let tampered = serde_json::to_vec(&json!({"files": [], "extra": true})).unwrap();
+        assert!(check(
+            &[
+                Entry::File("MANIFEST.json", &manifest),
+                Entry::File("FILES.json", &files)
+            ],
+            &manifest
+        )
+        .is_empty());
+        assert_eq!(
+            check(
+                &[
+                    Entry::File("MANIFEST.json", &manifest),
+                    Entry::File("FILES.json", &tampered)
+                ],
+                &manifest
+            ),
+            ["FILES.json doesn't match its checksum in MANIFEST.json."]
+        );
+        assert_eq!(
+            check(&[Entry::File("MANIFEST.json", &manifest)], &manifest),
+            ["FILES.json is missing."]
+        );
+    }
+
+    #[test]
+    fn size_limits_are_enforced() {
+        let data = tarball(&[Entry::File("big", &[0; 4096])]);
+        let limits = ContentConfig {
+            max_upload_size: data.len() as u64 - 1,
+            ..ContentConfig::default()
+        };
+        let Err(InvalidCollection(errors)) =
+            validate_collection(&data, "ns", "col", "1.0.0", &limits)
+        else {
+            panic!("an oversized upload was accepted");
+        };
+        assert_eq!(
+            errors,
+            [format!(
+                "The archive is {} bytes, over the {} byte limit.",
+                data.len(),
+                data.len() - 1
+            )]
+        );
+
+        let mut errors = Vec::new();
+        assert_eq!(
+            read_archive(&data, 4095, |_| true, &mut errors)
+                .err()
+                .as_deref(),
+            Some("The archive unpacks to more than 4095 bytes.")
+        );
+        assert!(read_archive(&data, 4096, |_| true, &mut errors).is_ok());
+    }
+}
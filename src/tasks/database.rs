@@ -0,0 +1,93 @@
+use super::TaskStore;
+use crate::db_utils::DbConnection;
+use crate::schema::task_states;
+use anyhow::Result;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use diesel::prelude::*;
+use diesel::{
+    r2d2::{ConnectionManager, Pool},
+    upsert::excluded,
+};
+use serde_json::{json, Value};
+
+/// Keeps task states in the `task_states` table, for deployments without
+/// Redis.
+pub struct DatabaseStore {
+    pool: Pool<ConnectionManager<DbConnection>>,
+}
+
+impl DatabaseStore {
+    pub fn new(pool: Pool<ConnectionManager<DbConnection>>) -> Self {
+        DatabaseStore { pool }
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+impl TaskStore for DatabaseStore {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut conn = self.pool.get()?;
+        Ok(task_states::table
+            .select(task_states::value)
+            .filter(task_states::key.eq(key))
+            .filter(
+                task_states::expires_at
+                    .is_null()
+                    .or(task_states::expires_at.gt(now())),
+            )
+            .first(&mut conn)
+            .optional()?)
+    }
+
+    fn set(&self, key: &str, value: &str, ttl: Option<u64>) -> Result<()> {
+        let mut conn = self.pool.get()?;
+        let expires_at = ttl.map(|ttl| now() + TimeDelta::seconds(ttl as i64));
+        // Expired keys are only ever skipped, so drop them as new ones come.
+        diesel::delete(task_states::table.filter(task_states::expires_at.le(now())))
+            .execute(&mut conn)?;
+        diesel::insert_into(task_states::table)
+            .values((
+                task_states::key.eq(key),
+                task_states::value.eq(value),
+                task_states::expires_at.eq(expires_at),
+            ))
+            .on_conflict(task_states::key)
+            .do_update()
+            .set((
+                task_states::value.eq(excluded(task_states::value)),
+                task_states::expires_at.eq(excluded(task_states::expires_at)),
+            ))
+            .execute(&mut conn)?;
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut conn = self.pool.get()?;
+        let keys: Vec<String> = task_states::table
+            .select(task_states::key)
+            .filter(task_states::key.like(format!("{prefix}%")))
+            .filter(
+                task_states::expires_at
+                    .is_null()
+                    .or(task_states::expires_at.gt(now())),
+            )
+            .load(&mut conn)?;
+        // `_` in a prefix would match any character.
+        Ok(keys
+            .into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect())
+    }
+
+    fn status(&self) -> Result<Value> {
+        self.pool.get()?;
+        let backend = if cfg!(feature = "sqlite") {
+            "sqlite"
+        } else {
+            "postgres"
+        };
+        Ok(json!({ "backend": backend }))
+    }
+}
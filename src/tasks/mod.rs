@@ -0,0 +1,121 @@
+//! Task states, kept as `{kind}:{uuid}` keys with the failure messages and
+//! reports of a task under `{task}:messages` and `{task}:report`.
+mod database;
+mod redis;
+
+pub use self::database::DatabaseStore;
+pub use self::redis::RedisStore;
+
+use crate::config::Config;
+use crate::db_utils::DbConnection;
+use anyhow::Result;
+use diesel::r2d2::{ConnectionManager, Pool};
+use serde_json::{json, Value};
+use std::ops::Deref;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Every kind of task the server starts, the prefix of their ids.
+pub const TASK_KINDS: &[&str] = &[
+    "upload",
+    "role-import",
+    "mirror",
+    "requirements",
+    "export",
+    "bundle-import",
+];
+
+/// A key-value store for task states, with keys that may expire.
+pub trait TaskStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    /// Sets `key` to `value`, forgotten after `ttl` seconds when given.
+    fn set(&self, key: &str, value: &str, ttl: Option<u64>) -> Result<()>;
+    /// The live keys starting with `prefix`.
+    fn keys(&self, prefix: &str) -> Result<Vec<String>>;
+    /// What `/api/status/` reports about the store; fails when it is down.
+    fn status(&self) -> Result<Value>;
+}
+
+/// The task store the server and commands share.
+#[derive(Clone)]
+pub struct Tasks(Arc<dyn TaskStore>);
+
+impl Deref for Tasks {
+    type Target = dyn TaskStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl Tasks {
+    pub fn new(store: impl TaskStore + 'static) -> Self {
+        Tasks(Arc::new(store))
+    }
+
+    /// Redis when `redis.url` is set, else the `task_states` table.
+    pub fn from_config(
+        config: &Config,
+        db_pool: &Pool<ConnectionManager<DbConnection>>,
+    ) -> Result<Self> {
+        Ok(match &config.redis.url {
+            Some(_) => Tasks::new(RedisStore::new(&config.redis)?),
+            None => Tasks::new(DatabaseStore::new(db_pool.clone())),
+        })
+    }
+
+    /// Starts a `kind` task as waiting, kept for `ttl` seconds, returning its id.
+    pub fn create(&self, kind: &str, ttl: u64) -> Result<String> {
+        let task_uuid = format!("{kind}:{}", Uuid::new_v4());
+        self.set(&task_uuid, "waiting", Some(ttl))?;
+        Ok(task_uuid)
+    }
+
+    pub fn state(&self, task_uuid: &str) -> Result<Option<String>> {
+        self.get(task_uuid)
+    }
+
+    pub fn set_state(&self, task_uuid: &str, state: &str) -> Result<()> {
+        self.set(task_uuid, state, None)
+    }
+
+    /// Marks a task as failed, keeping its messages for the import endpoints.
+    pub fn fail(&self, task_uuid: &str, messages: &[String]) -> Result<()> {
+        let key = format!("{task_uuid}:messages");
+        self.set(&key, &json!(messages).to_string(), Some(3600))?;
+        self.set_state(task_uuid, "failed")
+    }
+
+    pub fn messages(&self, task_uuid: &str) -> Result<Vec<String>> {
+        Ok(self
+            .get(&format!("{task_uuid}:messages"))?
+            .and_then(|messages| serde_json::from_str(&messages).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn set_report(&self, task_uuid: &str, report: &Value) -> Result<()> {
+        let key = format!("{task_uuid}:report");
+        self.set(&key, &report.to_string(), Some(172800))
+    }
+
+    pub fn report(&self, task_uuid: &str) -> Result<Option<Value>> {
+        Ok(self
+            .get(&format!("{task_uuid}:report"))?
+            .and_then(|report| serde_json::from_str(&report).ok()))
+    }
+
+    /// The `kind` tasks by id, with their states.
+    pub fn list(&self, kind: &str) -> Result<Vec<(String, String)>> {
+        let mut ids = self.keys(&format!("{kind}:"))?;
+        // Skip the messages and reports kept beside tasks.
+        ids.retain(|id| id.matches(':').count() == 1);
+        ids.sort();
+        let mut tasks = Vec::new();
+        for id in ids {
+            if let Some(state) = self.get(&id)? {
+                tasks.push((id, state));
+            }
+        }
+        Ok(tasks)
+    }
+}
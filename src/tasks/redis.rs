@@ -0,0 +1,60 @@
+use super::TaskStore;
+use crate::config::RedisConfig;
+use anyhow::{anyhow, Context, Result};
+use diesel::r2d2::Pool;
+use r2d2_redis::redis::Commands;
+use r2d2_redis::RedisConnectionManager;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+pub struct RedisStore {
+    pool: Pool<RedisConnectionManager>,
+}
+
+impl RedisStore {
+    pub fn new(config: &RedisConfig) -> Result<Self> {
+        let url = config
+            .url
+            .as_deref()
+            .ok_or_else(|| anyhow!("redis.url is not set"))?;
+        let manager = RedisConnectionManager::new(url).context("Invalid redis.url")?;
+        let pool = Pool::builder()
+            .max_size(config.pool_size)
+            .max_lifetime(Some(Duration::from_secs(config.max_lifetime)))
+            .min_idle(Some(config.min_idle))
+            .build(manager)
+            .context("Failed to connect to Redis")?;
+        Ok(RedisStore { pool })
+    }
+}
+
+impl TaskStore for RedisStore {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut conn = self.pool.get_timeout(Duration::from_secs(1))?;
+        Ok(conn.get(key)?)
+    }
+
+    fn set(&self, key: &str, value: &str, ttl: Option<u64>) -> Result<()> {
+        let mut conn = self.pool.get_timeout(Duration::from_secs(1))?;
+        conn.set::<&str, &str, bool>(key, value)?;
+        if let Some(ttl) = ttl {
+            conn.expire::<&str, usize>(key, ttl as usize)?;
+        }
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut conn = self.pool.get_timeout(Duration::from_secs(1))?;
+        Ok(conn.keys(format!("{prefix}*"))?)
+    }
+
+    fn status(&self) -> Result<Value> {
+        self.pool.get()?;
+        let state = self.pool.state();
+        Ok(json!({
+            "backend": "redis",
+            "connections": state.connections,
+            "idle_connections": state.idle_connections
+        }))
+    }
+}
@@ -0,0 +1,323 @@
+use semver::{Prerelease, Version};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Compares two version strings by semver precedence, including prerelease
+/// identifiers. Strings that are not valid semver sort below every valid
+/// version and are compared lexically among themselves.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    match (Version::parse(a), Version::parse(b)) {
+        (Ok(x), Ok(y)) => x.cmp_precedence(&y).then_with(|| a.cmp(b)),
+        (Ok(_), Err(_)) => Ordering::Greater,
+        (Err(_), Ok(_)) => Ordering::Less,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Encodes prerelease identifiers so that comparing the keys byte by byte
+/// orders them by semver precedence: numeric identifiers are zero-padded
+/// and sort below alphanumeric ones, and a space separates identifiers so
+/// `rc` sorts below `rc.1` and `a.b` below `a-b`. Stable releases get an
+/// empty key.
+pub fn prerelease_key(pre: &Prerelease) -> String {
+    pre.split('.')
+        .filter(|identifier| !identifier.is_empty())
+        .map(|identifier| {
+            if identifier.bytes().all(|b| b.is_ascii_digit()) {
+                format!("0{identifier:0>20}")
+            } else {
+                format!("1{identifier}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn is_prerelease(version: &str) -> bool {
+    Version::parse(version).is_ok_and(|v| !v.pre.is_empty())
+}
+
+/// Picks the version clients should install by default: the highest stable
+/// release, or the highest prerelease when nothing stable has been published.
+pub fn latest<'a, I>(versions: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    highest(versions, is_prerelease, compare)
+}
+
+/// `latest` for role tags, ignoring the ones that aren't versions.
+pub fn latest_role<'a, I>(tags: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let releases = tags
+        .into_iter()
+        .filter(|tag| RoleVersion::parse(tag).is_some());
+    highest(
+        releases,
+        |tag| RoleVersion::parse(tag).is_some_and(|v| v.is_prerelease()),
+        compare_roles,
+    )
+}
+
+fn highest<'a, I>(
+    versions: I,
+    is_prerelease: impl Fn(&str) -> bool,
+    compare: impl Fn(&str, &str) -> Ordering,
+) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let (prereleases, stable): (Vec<&str>, Vec<&str>) =
+        versions.into_iter().partition(|v| is_prerelease(v));
+    let candidates = if stable.is_empty() {
+        prereleases
+    } else {
+        stable
+    };
+    candidates.into_iter().max_by(|x, y| compare(x, y))
+}
+
+/// A role tag read loosely as a version. Roles are tagged by hand, so a
+/// `v` or `release-` prefix is dropped, missing components count as zero
+/// (`1.0` is `1.0.0`) and what follows the numbers is a prerelease
+/// (`2.0-rc1`).
+#[derive(Debug, Eq)]
+pub struct RoleVersion {
+    numbers: Vec<u64>,
+    pre: Prerelease,
+}
+
+impl RoleVersion {
+    pub fn parse(tag: &str) -> Option<Self> {
+        let start = tag.find(|c: char| c.is_ascii_digit())?;
+        let prefix = &tag[..start];
+        if !prefix
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || "-_.".contains(c))
+        {
+            return None;
+        }
+        // Build metadata doesn't take part in ordering.
+        let rest = tag[start..].split('+').next().unwrap_or("");
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let numbers = rest[..end]
+            .trim_end_matches('.')
+            .split('.')
+            .map(|number| number.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        let suffix = rest[end..].trim_start_matches(['-', '_', '.']);
+        let pre = if suffix.is_empty() {
+            Prerelease::EMPTY
+        } else {
+            Prerelease::new(&suffix.replace('_', "-")).ok()?
+        };
+        Some(RoleVersion { numbers, pre })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+impl Ord for RoleVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.numbers.len().max(other.numbers.len());
+        let padded = |numbers: &[u64], i: usize| numbers.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| padded(&self.numbers, i).cmp(&padded(&other.numbers, i)))
+            .find(|ord| ord.is_ne())
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for RoleVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RoleVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+/// Normalized to at least three components, e.g. `1.0` as `1.0.0`.
+impl fmt::Display for RoleVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut numbers = self.numbers.clone();
+        numbers.resize(numbers.len().max(3), 0);
+        let numbers: Vec<String> = numbers.iter().map(u64::to_string).collect();
+        write!(f, "{}", numbers.join("."))?;
+        if self.is_prerelease() {
+            write!(f, "-{}", self.pre)?;
+        }
+        Ok(())
+    }
+}
+
+/// `compare` for role tags: loosely read versions in order, above tags
+/// that aren't versions, with equal versions (`1.0`, `v1.0.0`) ordered by
+/// tag.
+pub fn compare_roles(a: &str, b: &str) -> Ordering {
+    match (RoleVersion::parse(a), RoleVersion::parse(b)) {
+        (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+        (Some(_), None) => Ordering::Greater,
+        (None, Some(_)) => Ordering::Less,
+        (None, None) => a.cmp(b),
+    }
+}
+
+/// Checks `version` against a Galaxy requirement such as `*`, `1.2.0` or
+/// `>=1.0.0,<2.0.0,!=1.1.0`. Clauses that can't be parsed are treated as
+/// satisfied, so callers err on the side of assuming a dependency.
+pub fn satisfies(version: &str, spec: &str) -> bool {
+    spec.split(',').map(str::trim).all(|clause| {
+        if clause.is_empty() || clause == "*" {
+            return true;
+        }
+        let split = clause
+            .find(|c: char| !"<>=!".contains(c))
+            .unwrap_or(clause.len());
+        let (op, target) = (&clause[..split], clause[split..].trim());
+        if Version::parse(target).is_err() {
+            return true;
+        }
+        let ord = compare(version, target);
+        match op {
+            "" | "=" | "==" => ord == Ordering::Equal,
+            "!=" => ord != Ordering::Equal,
+            ">" => ord == Ordering::Greater,
+            ">=" => ord != Ordering::Less,
+            "<" => ord == Ordering::Less,
+            "<=" => ord != Ordering::Greater,
+            _ => true,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(version: &str) -> String {
+        prerelease_key(&Version::parse(version).unwrap().pre)
+    }
+
+    #[test]
+    fn prerelease_keys_sort_like_versions() {
+        let mut versions = vec![
+            "1.0.0",
+            "1.0.0-rc.10",
+            "1.0.0-rc.9",
+            "1.0.0-rc",
+            "1.0.0-beta.11",
+            "1.0.0-beta.2",
+            "1.0.0-beta",
+            "1.0.0-alpha.beta",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha-1",
+            "1.0.0-alpha",
+            "1.0.0-1",
+        ];
+        versions.sort_by_key(|v| (key(v).is_empty(), key(v)));
+        let mut expected = versions.clone();
+        expected.sort_by(|a, b| compare(a, b));
+        assert_eq!(versions, expected);
+        assert_eq!(key("1.0.0+build.5"), "");
+    }
+
+    #[test]
+    fn compare_orders_prereleases_by_precedence() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(compare(pair[0], pair[1]), Ordering::Less, "{pair:?}");
+            assert_eq!(compare(pair[1], pair[0]), Ordering::Greater, "{pair:?}");
+        }
+        assert_eq!(compare("1.0.0-rc.9", "1.0.0-rc.10"), Ordering::Less);
+    }
+
+    #[test]
+    fn compare_breaks_build_metadata_ties_by_string() {
+        assert_eq!(compare("1.0.0+build.1", "1.0.0+build.2"), Ordering::Less);
+        assert_eq!(compare("1.0.0+zzz", "1.0.1"), Ordering::Less);
+        assert_eq!(compare("1.0.0", "1.0.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn compare_puts_invalid_versions_last() {
+        assert_eq!(compare("not-a-version", "0.0.1"), Ordering::Less);
+        assert_eq!(compare("1.0", "0.0.1-alpha"), Ordering::Less);
+        assert_eq!(compare("abc", "abd"), Ordering::Less);
+    }
+
+    #[test]
+    fn latest_prefers_stable_releases() {
+        assert_eq!(latest(["1.0.0", "2.0.0-rc.1", "1.1.0"]), Some("1.1.0"));
+        assert_eq!(
+            latest(["2.0.0-rc.9", "2.0.0-rc.10", "2.0.0-beta"]),
+            Some("2.0.0-rc.10")
+        );
+        assert_eq!(latest(["1.0.0+build.5", "0.9.0"]), Some("1.0.0+build.5"));
+        assert_eq!(latest(Vec::<&str>::new()), None);
+    }
+
+    #[test]
+    fn latest_role_reads_tags_loosely() {
+        assert_eq!(
+            latest_role(["v1.0", "1.2", "release-1.10", "master"]),
+            Some("release-1.10")
+        );
+        assert_eq!(latest_role(["v2.0-rc1", "v1.9"]), Some("v1.9"));
+        assert_eq!(latest_role(["2.0_rc2", "2.0_rc1"]), Some("2.0_rc2"));
+        assert_eq!(latest_role(["main", "develop"]), None);
+    }
+
+    #[test]
+    fn role_versions_pad_missing_components() {
+        let parse = |tag| RoleVersion::parse(tag).unwrap();
+        assert_eq!(parse("1.0"), parse("v1.0.0"));
+        assert!(parse("2.0-rc1") < parse("2.0"));
+        assert!(parse("1.0.0+build") == parse("1.0"));
+        assert_eq!(parse("1").to_string(), "1.0.0");
+        assert_eq!(parse("v2.1-rc.1").to_string(), "2.1.0-rc.1");
+        assert!(RoleVersion::parse("latest").is_none());
+        assert!(RoleVersion::parse("1.0/x").is_none());
+    }
+
+    #[test]
+    fn satisfies_galaxy_ranges() {
+        assert!(satisfies("1.2.0", "*"));
+        assert!(satisfies("1.2.0", ""));
+        assert!(satisfies("1.2.0", "1.2.0"));
+        assert!(satisfies("1.2.0", "==1.2.0"));
+        assert!(!satisfies("1.2.1", "1.2.0"));
+        assert!(satisfies("1.5.0", ">=1.0.0,<2.0.0"));
+        assert!(!satisfies("2.0.0", ">=1.0.0,<2.0.0"));
+        assert!(!satisfies("1.1.0", ">=1.0.0,<2.0.0,!=1.1.0"));
+        assert!(satisfies("2.0.0-rc.1", "<2.0.0"));
+        assert!(satisfies("1.0.0", ">0.9.9, <= 1.0.0"));
+        assert!(satisfies("1.0.0", ">=banana"));
+    }
+}
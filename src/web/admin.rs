@@ -0,0 +1,542 @@
+use super::auth::{issue_token, Identity};
+use super::pagination::Pagination;
+use super::urls::UrlBuilder;
+use crate::db_utils::DbConnection;
+use crate::models::{
+    AuditEntry, GroupMemberNew, GroupNew, NamespaceNew, NamespaceOwnerNew, User, UserNew,
+};
+use actix_web::{error, HttpRequest, HttpResponse, Responder};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use paperclip::actix::{api_v2_operation, delete, get, post, put, web, Apiv2Schema};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct UserRequest {
+    username: String,
+    #[serde(default)]
+    is_superuser: bool,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct GroupRequest {
+    name: String,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct MemberRequest {
+    username: String,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct NamespaceRequest {
+    name: String,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct OwnersRequest {
+    groups: Vec<String>,
+}
+
+fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .json(json!({"detail": "You do not have permission to perform this action."}))
+}
+
+#[api_v2_operation]
+#[get("/api/admin/users/")]
+async fn user_list(identity: Identity, pool: web::Data<DbPool>) -> impl Responder {
+    use crate::schema::users;
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let results = users::table
+        .select(User::as_select())
+        .order(users::username.asc())
+        .load(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    HttpResponse::Ok().json(json!({ "count": results.len(), "results": results }))
+}
+
+/// Creates a user and returns its first token. The key is only shown once.
+#[api_v2_operation]
+#[post("/api/admin/users/")]
+async fn user_create(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    body: web::Json<UserRequest>,
+) -> impl Responder {
+    use crate::schema::users;
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let created = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let user = diesel::insert_into(users::table)
+            .values(&UserNew {
+                username: &body.username,
+                is_superuser: body.is_superuser,
+            })
+            .on_conflict_do_nothing()
+            .returning(User::as_returning())
+            .get_result(conn)
+            .optional()?;
+        match user {
+            Some(user) => Ok(Some((issue_token(conn, user.id)?, user))),
+            None => Ok(None),
+        }
+    });
+    match created.map_err(error::ErrorInternalServerError).unwrap() {
+        Some((token, user)) => {
+            HttpResponse::Created().json(json!({ "user": user, "token": token }))
+        }
+        None => HttpResponse::Conflict()
+            .json(json!({"username": "A user with that username already exists."})),
+    }
+}
+
+#[api_v2_operation]
+#[post("/api/admin/users/{username}/tokens/")]
+async fn user_token_create(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+) -> impl Responder {
+    use crate::schema::users;
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let user_id = users::table
+        .select(users::id)
+        .filter(users::username.eq(path.into_inner()))
+        .first::<i32>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(user_id) = user_id else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let token = issue_token(&mut conn, user_id)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    HttpResponse::Created().json(json!({ "token": token }))
+}
+
+#[api_v2_operation]
+#[delete("/api/admin/users/{username}/tokens/")]
+async fn user_token_revoke(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+) -> impl Responder {
+    use crate::schema::{tokens, users};
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let user_ids = users::table
+        .select(users::id)
+        .filter(users::username.eq(path.into_inner()));
+    let revoked = diesel::delete(tokens::table.filter(tokens::user_id.eq_any(user_ids)))
+        .execute(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    HttpResponse::Ok().json(json!({ "revoked": revoked }))
+}
+
+/// Galaxy-compatible token rotation: replaces every token of the caller with a new one.
+#[api_v2_operation]
+#[post("/api/v3/auth/token/")]
+async fn token_rotate(identity: Identity, pool: web::Data<DbPool>) -> impl Responder {
+    use crate::schema::tokens;
+    let Some(user_id) = identity.user_id else {
+        return HttpResponse::BadRequest()
+            .json(json!({"detail": "The admin token is configured, not issued."}));
+    };
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let token = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            diesel::delete(tokens::table.filter(tokens::user_id.eq(user_id))).execute(conn)?;
+            issue_token(conn, user_id)
+        })
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    HttpResponse::Ok().json(json!({ "token": token }))
+}
+
+#[api_v2_operation]
+#[get("/api/admin/groups/")]
+async fn group_list(identity: Identity, pool: web::Data<DbPool>) -> impl Responder {
+    use crate::schema::{group_members, groups, users};
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let rows = groups::table
+        .left_join(group_members::table.inner_join(users::table))
+        .select((groups::name, users::username.nullable()))
+        .order((groups::name.asc(), users::username.asc()))
+        .load::<(String, Option<String>)>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let mut results: Vec<Value> = Vec::new();
+    for (group, username) in rows {
+        if results
+            .last()
+            .map(|g| g["name"] != group.as_str())
+            .unwrap_or(true)
+        {
+            results.push(json!({"name": group, "users": []}));
+        }
+        if let Some(username) = username {
+            results.last_mut().unwrap()["users"]
+                .as_array_mut()
+                .unwrap()
+                .push(json!(username));
+        }
+    }
+    HttpResponse::Ok().json(json!({ "count": results.len(), "results": results }))
+}
+
+#[api_v2_operation]
+#[post("/api/admin/groups/")]
+async fn group_create(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    body: web::Json<GroupRequest>,
+) -> impl Responder {
+    use crate::schema::groups;
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let created = diesel::insert_into(groups::table)
+        .values(&GroupNew { name: &body.name })
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    if created == 0 {
+        return HttpResponse::Conflict()
+            .json(json!({"name": "A group with that name already exists."}));
+    }
+    HttpResponse::Created().json(json!({"name": body.name, "users": []}))
+}
+
+#[api_v2_operation]
+#[post("/api/admin/groups/{name}/members/")]
+async fn group_member_add(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    body: web::Json<MemberRequest>,
+) -> impl Responder {
+    use crate::schema::{group_members, groups, users};
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let group_id = groups::table
+        .select(groups::id)
+        .filter(groups::name.eq(path.into_inner()))
+        .first::<i32>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let user_id = users::table
+        .select(users::id)
+        .filter(users::username.eq(&body.username))
+        .first::<i32>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let (Some(group_id), Some(user_id)) = (group_id, user_id) else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    diesel::insert_into(group_members::table)
+        .values(&GroupMemberNew { group_id, user_id })
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    HttpResponse::Ok().json(json!({"username": body.username}))
+}
+
+#[api_v2_operation]
+#[delete("/api/admin/groups/{name}/members/{username}/")]
+async fn group_member_remove(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    use crate::schema::{group_members, groups, users};
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let (group, username) = path.into_inner();
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let group_ids = groups::table
+        .select(groups::id)
+        .filter(groups::name.eq(group));
+    let user_ids = users::table
+        .select(users::id)
+        .filter(users::username.eq(username));
+    let removed = diesel::delete(
+        group_members::table
+            .filter(group_members::group_id.eq_any(group_ids))
+            .filter(group_members::user_id.eq_any(user_ids)),
+    )
+    .execute(&mut conn)
+    .map_err(error::ErrorInternalServerError)
+    .unwrap();
+    if removed == 0 {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    }
+    HttpResponse::NoContent().finish()
+}
+
+/// Looks up the ids of the named groups, or returns the names that don't exist.
+fn group_ids(
+    conn: &mut DbConnection,
+    names: &[String],
+) -> QueryResult<Result<Vec<i32>, Vec<String>>> {
+    use crate::schema::groups;
+    let found = groups::table
+        .select((groups::id, groups::name))
+        .filter(groups::name.eq_any(names))
+        .load::<(i32, String)>(conn)?;
+    let missing: Vec<String> = names
+        .iter()
+        .filter(|n| !found.iter().any(|(_, name)| name == *n))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Ok(Err(missing));
+    }
+    Ok(Ok(found.into_iter().map(|(id, _)| id).collect()))
+}
+
+fn set_owners(conn: &mut DbConnection, namespace_id: i32, group_ids: &[i32]) -> QueryResult<()> {
+    use crate::schema::namespace_owners;
+    diesel::delete(namespace_owners::table.filter(namespace_owners::namespace_id.eq(namespace_id)))
+        .execute(conn)?;
+    let owners: Vec<NamespaceOwnerNew> = group_ids
+        .iter()
+        .map(|group_id| NamespaceOwnerNew {
+            namespace_id,
+            group_id: *group_id,
+        })
+        .collect();
+    diesel::insert_into(namespace_owners::table)
+        .values(&owners)
+        .execute(conn)?;
+    Ok(())
+}
+
+#[api_v2_operation]
+#[get("/api/admin/namespaces/")]
+async fn namespace_owner_list(identity: Identity, pool: web::Data<DbPool>) -> impl Responder {
+    use crate::schema::{groups, namespace_owners, namespaces};
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let rows = namespaces::table
+        .left_join(namespace_owners::table.inner_join(groups::table))
+        .select((namespaces::name, groups::name.nullable()))
+        .order((namespaces::name.asc(), groups::name.asc()))
+        .load::<(String, Option<String>)>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let mut results: Vec<Value> = Vec::new();
+    for (namespace, group) in rows {
+        if results
+            .last()
+            .map(|n| n["name"] != namespace.as_str())
+            .unwrap_or(true)
+        {
+            results.push(json!({"name": namespace, "groups": []}));
+        }
+        if let Some(group) = group {
+            results.last_mut().unwrap()["groups"]
+                .as_array_mut()
+                .unwrap()
+                .push(json!(group));
+        }
+    }
+    HttpResponse::Ok().json(json!({ "count": results.len(), "results": results }))
+}
+
+#[api_v2_operation]
+#[post("/api/admin/namespaces/")]
+async fn namespace_owner_create(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    body: web::Json<NamespaceRequest>,
+) -> impl Responder {
+    use crate::schema::namespaces;
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let group_ids = match group_ids(&mut conn, &body.groups)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    {
+        Ok(ids) => ids,
+        Err(missing) => return HttpResponse::BadRequest().json(json!({ "groups": missing })),
+    };
+    let created = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let namespace_id = diesel::insert_into(namespaces::table)
+                .values(&NamespaceNew { name: &body.name })
+                .on_conflict_do_nothing()
+                .returning(namespaces::id)
+                .get_result::<i32>(conn)
+                .optional()?;
+            if let Some(namespace_id) = namespace_id {
+                set_owners(conn, namespace_id, &group_ids)?;
+            }
+            Ok(namespace_id.is_some())
+        })
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    if !created {
+        return HttpResponse::Conflict()
+            .json(json!({"name": "A namespace with that name already exists."}));
+    }
+    HttpResponse::Created().json(json!({"name": body.name, "groups": body.groups}))
+}
+
+#[api_v2_operation]
+#[put("/api/admin/namespaces/{name}/owners/")]
+async fn namespace_owner_update(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    body: web::Json<OwnersRequest>,
+) -> impl Responder {
+    use crate::schema::namespaces;
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let name = path.into_inner();
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let namespace_id = namespaces::table
+        .select(namespaces::id)
+        .filter(namespaces::name.eq(&name))
+        .first::<i32>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(namespace_id) = namespace_id else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let group_ids = match group_ids(&mut conn, &body.groups)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    {
+        Ok(ids) => ids,
+        Err(missing) => return HttpResponse::BadRequest().json(json!({ "groups": missing })),
+    };
+    conn.transaction(|conn| set_owners(conn, namespace_id, &group_ids))
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    HttpResponse::Ok().json(json!({"name": name, "groups": body.groups}))
+}
+
+#[api_v2_operation]
+#[get("/api/admin/audit-log/")]
+async fn audit_log_list(
+    req: HttpRequest,
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    use crate::schema::audit_log;
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let pagination = match Pagination::from_query(&query) {
+        Ok(pagination) => pagination,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
+    let filtered = || {
+        let mut q = audit_log::table.into_boxed();
+        if let Some(action) = query.get("action") {
+            q = q.filter(audit_log::action.eq(action.clone()));
+        }
+        if let Some(username) = query.get("username") {
+            q = q.filter(audit_log::username.eq(username.clone()));
+        }
+        if let Some(target) = query.get("target") {
+            q = q.filter(audit_log::target.eq(target.clone()));
+        }
+        q
+    };
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let count: i64 = filtered()
+        .count()
+        .get_result(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let results = filtered()
+        .select(AuditEntry::as_select())
+        .order(audit_log::id.desc())
+        .limit(pagination.limit)
+        .offset(pagination.offset)
+        .load::<AuditEntry>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let base = UrlBuilder::from_request(&req).absolute(req.path());
+    let resp = pagination.v2_page(&base, &query, count, json!(results));
+    HttpResponse::Ok().json(resp)
+}
@@ -0,0 +1,26 @@
+use super::auth::Identity;
+use crate::db_utils::DbConnection;
+use crate::models::AuditEntryNew;
+use diesel::prelude::*;
+use serde_json::Value;
+
+/// Records that `identity` performed `action` on `target`, e.g. `delete` on
+/// `collection:acme.tools`. Call it inside the transaction making the change.
+pub fn record(
+    conn: &mut DbConnection,
+    identity: &Identity,
+    action: &str,
+    target: &str,
+    details: &Value,
+) -> QueryResult<()> {
+    use crate::schema::audit_log;
+    diesel::insert_into(audit_log::table)
+        .values(&AuditEntryNew {
+            action,
+            target,
+            username: &identity.username,
+            details,
+        })
+        .execute(conn)?;
+    Ok(())
+}
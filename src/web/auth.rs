@@ -0,0 +1,195 @@
+use crate::config::AuthConfig;
+use crate::db_utils::DbConnection;
+use crate::models::{TokenNew, User};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error,
+    http::{header, Method},
+    middleware::Next,
+    web::{self, Data},
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use anyhow::{anyhow, Context, Result};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use futures::future::{ready, Ready};
+use log::{error, warn};
+use paperclip::actix::Apiv2Security;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+/// The authenticated caller, stored in the request extensions by [`authenticate`].
+/// Handlers that take it as an argument reject anonymous requests.
+#[derive(Debug, Clone, Apiv2Security)]
+#[openapi(
+    apiKey,
+    in = "header",
+    name = "Authorization",
+    description = "Use format 'Token <key>'"
+)]
+pub struct Identity {
+    /// `None` for the configured `AUTH.ADMIN_TOKEN`.
+    pub user_id: Option<i32>,
+    pub username: String,
+    pub is_superuser: bool,
+}
+
+impl FromRequest for Identity {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<Identity>().cloned().ok_or_else(|| {
+            error::ErrorUnauthorized("Authentication credentials were not provided.")
+        }))
+    }
+}
+
+pub fn hash_token(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Creates a new token for `user_id` and returns the key; only its hash is stored.
+pub fn issue_token(conn: &mut DbConnection, user_id: i32) -> QueryResult<String> {
+    use crate::schema::tokens;
+    let key = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    diesel::insert_into(tokens::table)
+        .values(&TokenNew {
+            user_id,
+            key_hash: &hash_token(&key),
+        })
+        .execute(conn)?;
+    Ok(key)
+}
+
+/// Superusers may publish anywhere; everybody else needs to be in a group that
+/// owns the namespace.
+pub fn can_publish(
+    conn: &mut DbConnection,
+    identity: &Identity,
+    namespace: &str,
+) -> QueryResult<bool> {
+    use crate::schema::{group_members, namespace_owners, namespaces};
+    if identity.is_superuser {
+        return Ok(true);
+    }
+    let Some(user_id) = identity.user_id else {
+        return Ok(false);
+    };
+    diesel::select(diesel::dsl::exists(
+        namespace_owners::table
+            .inner_join(namespaces::table)
+            .inner_join(
+                group_members::table.on(group_members::group_id.eq(namespace_owners::group_id)),
+            )
+            .filter(namespaces::name.eq(namespace))
+            .filter(group_members::user_id.eq(user_id)),
+    ))
+    .get_result(conn)
+}
+
+fn token_from_header(req: &ServiceRequest) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, key) = value.trim().split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("token") || scheme.eq_ignore_ascii_case("bearer") {
+        Some(key.trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// The identity `key` belongs to, `None` when it's nobody's. `Err` means the
+/// database couldn't be asked, which says nothing about the token.
+fn lookup(pool: Option<&DbPool>, auth: &AuthConfig, key: &str) -> Result<Option<Identity>> {
+    use crate::schema::{tokens, users};
+    let key_hash = hash_token(key);
+    if let Some(admin_token) = &auth.admin_token {
+        if hash_token(admin_token) == key_hash {
+            return Ok(Some(Identity {
+                user_id: None,
+                username: "admin".to_string(),
+                is_superuser: true,
+            }));
+        }
+    }
+    let mut conn = pool.context("No database pool is configured")?.get()?;
+    let found = tokens::table
+        .inner_join(users::table)
+        .filter(tokens::key_hash.eq(&key_hash))
+        .select((tokens::id, User::as_select()))
+        .first::<(i32, User)>(&mut conn)
+        .optional()?;
+    let Some((token_id, user)) = found else {
+        return Ok(None);
+    };
+    // Only bookkeeping, so a failure doesn't cost the caller their request.
+    if let Err(err) = diesel::update(tokens::table.find(token_id))
+        .set(tokens::last_used_at.eq(diesel::dsl::now))
+        .execute(&mut conn)
+    {
+        warn!("Failed to record the use of token {token_id}: {err}");
+    }
+    Ok(Some(Identity {
+        user_id: Some(user.id),
+        username: user.username,
+        is_superuser: user.is_superuser,
+    }))
+}
+
+fn unauthorized<B>(req: ServiceRequest, detail: &str) -> ServiceResponse<EitherBody<B>> {
+    let resp = HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Token"))
+        .json(json!({ "detail": detail }));
+    req.into_response(resp).map_into_right_body()
+}
+
+/// Resolves `Authorization: Token <key>` (or `Bearer <key>`) into an [`Identity`].
+/// Requests that change state always need one; reads only when
+/// `AUTH.ANONYMOUS_READ` is disabled.
+pub async fn authenticate<B: MessageBody>(
+    auth: AuthConfig,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let identity = match token_from_header(&req) {
+        None => None,
+        Some(key) => {
+            let pool = req
+                .app_data::<Data<DbPool>>()
+                .map(|pool| pool.get_ref().clone());
+            let config = auth.clone();
+            let found = web::block(move || lookup(pool.as_ref(), &config, &key))
+                .await
+                .map_err(|err| anyhow!("{err}"))
+                .and_then(|result| result);
+            match found {
+                Ok(Some(identity)) => Some(identity),
+                Ok(None) => return Ok(unauthorized(req, "Invalid token.")),
+                Err(err) => {
+                    error!("Failed to look up a token: {err:#}");
+                    let resp = HttpResponse::ServiceUnavailable()
+                        .json(json!({"detail": "Authentication is unavailable."}));
+                    return Ok(req.into_response(resp).map_into_right_body());
+                }
+            }
+        }
+    };
+    let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    match identity {
+        Some(identity) => {
+            req.extensions_mut().insert(identity);
+        }
+        None if !safe || !auth.anonymous_read => {
+            return Ok(unauthorized(
+                req,
+                "Authentication credentials were not provided.",
+            ))
+        }
+        None => {}
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
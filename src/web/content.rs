@@ -0,0 +1,109 @@
+use super::auth::Identity;
+use crate::db_utils::DbConnection;
+use crate::models::CollectionVersion;
+use crate::repository;
+use actix_files::NamedFile;
+use actix_web::{error, web, HttpMessage, HttpRequest, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use serde_json::json;
+use std::path::Path;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+/// Serves the stored collection and role tarballs. Collection versions that
+/// are only in `staging` or `rejected` are kept to their uploader and
+/// superusers until they're approved.
+#[actix_web::get("/content/{path:.*}")]
+async fn content_file(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let not_found = || HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    let path = path.into_inner();
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    // Uploads, deletions and bundle imports in progress are dot-prefixed or
+    // end in `.import`.
+    if segments.iter().any(|segment| {
+        segment.is_empty() || segment.starts_with('.') || segment.ends_with(".import")
+    }) {
+        return not_found();
+    }
+    match segments.as_slice() {
+        ["collections", namespace, name, "versions", version, file] => {
+            let identity = req.extensions().get::<Identity>().cloned();
+            let mut conn = pool
+                .get()
+                .map_err(error::ErrorInternalServerError)
+                .expect("couldn't get db connection from pool");
+            let allowed =
+                may_download(&mut conn, identity.as_ref(), namespace, name, version, file)
+                    .map_err(error::ErrorInternalServerError)
+                    .unwrap();
+            if !allowed {
+                return not_found();
+            }
+        }
+        ["roles", _, _, "versions", _, _] => {}
+        _ => return not_found(),
+    }
+    let file = Path::new("content").join(segments.join("/"));
+    if !file.is_file() {
+        return not_found();
+    }
+    match NamedFile::open(&file) {
+        Ok(file) => file.into_response(&req),
+        Err(_) => not_found(),
+    }
+}
+
+/// Whether `file` is the artifact of a collection version the caller may
+/// download: anybody's once it's in a repository other than `staging` and
+/// `rejected`, or in a version of one, and only the uploader's and
+/// superusers' before that.
+fn may_download(
+    conn: &mut DbConnection,
+    identity: Option<&Identity>,
+    namespace: &str,
+    name: &str,
+    version: &str,
+    file: &str,
+) -> QueryResult<bool> {
+    use crate::schema::{collection_versions, collections, repositories};
+    use crate::schema::{repository_content, repository_version_content, repository_versions};
+    let found: Option<CollectionVersion> = collection_versions::table
+        .inner_join(collections::table)
+        .filter(collections::namespace.eq(namespace))
+        .filter(collections::name.eq(name))
+        .filter(collection_versions::version.eq(version))
+        .select(CollectionVersion::as_select())
+        .first(conn)
+        .optional()?;
+    let Some(found) = found.filter(|found| found.artifact["filename"].as_str() == Some(file))
+    else {
+        return Ok(false);
+    };
+    let mut held_by: Vec<String> = repository_content::table
+        .inner_join(repositories::table)
+        .filter(repository_content::collection_version_id.eq(found.id))
+        .select(repositories::name)
+        .load(conn)?;
+    held_by.extend(
+        repository_version_content::table
+            .inner_join(repository_versions::table.inner_join(repositories::table))
+            .filter(repository_version_content::collection_version_id.eq(found.id))
+            .select(repositories::name)
+            .load::<String>(conn)?,
+    );
+    if held_by
+        .iter()
+        .any(|held| held != repository::STAGING && held != repository::REJECTED)
+    {
+        return Ok(true);
+    }
+    Ok(!held_by.is_empty()
+        && identity.is_some_and(|identity| {
+            identity.is_superuser || found.uploaded_by.as_deref() == Some(&identity.username)
+        }))
+}
@@ -0,0 +1,555 @@
+use super::audit;
+use super::auth::{can_publish, Identity};
+use super::filters::bool_param;
+use crate::db_utils::DbConnection;
+use crate::sync::{is_collection_name, is_role_segment};
+use crate::versions;
+use actix_web::{error, HttpResponse, Responder};
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::{Bool, Text};
+use log::{error, warn};
+use paperclip::actix::{api_v2_operation, delete, web};
+use semver::Version;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+// The SQL around the `namespace.name` a dependency names.
+#[cfg(not(feature = "sqlite"))]
+const COLLECTION_DEPENDS_ON: [&str; 2] = [
+    "((collection_versions.metadata::jsonb -> 'dependencies') ? ",
+    ")",
+];
+#[cfg(feature = "sqlite")]
+const COLLECTION_DEPENDS_ON: [&str; 2] = [
+    "EXISTS (SELECT 1 FROM json_each(collection_versions.metadata, '$.dependencies') \
+     WHERE key = ",
+    ")",
+];
+// Role dependencies are names or `{"name": ...}` objects, so the name is
+// bound twice.
+#[cfg(not(feature = "sqlite"))]
+const ROLE_DEPENDS_ON: [&str; 3] = [
+    "((roles.metadata::jsonb -> 'summary_fields' -> 'dependencies') \
+     @> jsonb_build_array(jsonb_build_object('name', ",
+    "::text)) OR (roles.metadata::jsonb -> 'summary_fields' -> 'dependencies') ? ",
+    ")",
+];
+#[cfg(feature = "sqlite")]
+const ROLE_DEPENDS_ON: [&str; 3] = [
+    "EXISTS (SELECT 1 FROM json_each(roles.metadata, '$.summary_fields.dependencies') d \
+     WHERE CASE d.type WHEN 'object' THEN json_extract(d.value, '$.name') = ",
+    " ELSE d.value = ",
+    " END)",
+];
+
+/// A content directory moved aside while the database rows go away, so it can
+/// be put back if the transaction fails.
+struct StagedRemoval {
+    original: PathBuf,
+    staged: PathBuf,
+}
+
+impl StagedRemoval {
+    fn new(path: &str) -> std::io::Result<Option<Self>> {
+        let original = PathBuf::from(path.trim_end_matches('/'));
+        if !original.exists() {
+            return Ok(None);
+        }
+        let Some(file_name) = original.file_name().map(|name| name.to_string_lossy()) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "not a content directory",
+            ));
+        };
+        let staged = original.with_file_name(format!(
+            ".{}.deleting-{}",
+            file_name,
+            Uuid::new_v4().simple()
+        ));
+        std::fs::rename(&original, &staged)?;
+        Ok(Some(StagedRemoval { original, staged }))
+    }
+
+    fn restore(self) {
+        if let Err(e) = std::fs::rename(&self.staged, &self.original) {
+            error!("Failed to restore {}: {e}", self.original.display());
+        }
+    }
+
+    fn finish(self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.staged) {
+            warn!("Failed to remove {}: {e}", self.staged.display());
+        }
+    }
+}
+
+/// Removes `dir` and runs `delete_rows` in a transaction; either both happen or
+/// neither does.
+fn delete_atomically<F>(conn: &mut DbConnection, dir: &str, delete_rows: F) -> HttpResponse
+where
+    F: FnOnce(&mut DbConnection) -> QueryResult<()>,
+{
+    let staged = match StagedRemoval::new(dir) {
+        Ok(staged) => staged,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(json!({ "detail": format!("Failed to remove {dir}: {e}") }))
+        }
+    };
+    match conn.transaction(delete_rows) {
+        Ok(()) => {
+            if let Some(staged) = staged {
+                staged.finish();
+            }
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => {
+            if let Some(staged) = staged {
+                staged.restore();
+            }
+            HttpResponse::InternalServerError().json(json!({ "detail": e.to_string() }))
+        }
+    }
+}
+
+fn permission_denied(
+    conn: &mut DbConnection,
+    identity: &Identity,
+    namespace: &str,
+) -> Option<HttpResponse> {
+    let allowed = can_publish(conn, identity, namespace)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    (!allowed).then(|| {
+        HttpResponse::Forbidden().json(json!({
+            "detail": format!("You are not allowed to delete content from the '{namespace}' namespace.")
+        }))
+    })
+}
+
+/// Rejects path segments that can't name stored content, before they go
+/// into a path under `content/`.
+fn invalid_segment<'a>(
+    segments: impl IntoIterator<Item = &'a str>,
+    valid: impl Fn(&str) -> bool,
+) -> Option<HttpResponse> {
+    let segment = segments.into_iter().find(|segment| !valid(segment))?;
+    Some(HttpResponse::BadRequest().json(json!({
+        "detail": format!("'{segment}' is not a valid name.")
+    })))
+}
+
+fn has_dependents(dependents: &[String]) -> HttpResponse {
+    HttpResponse::Conflict().json(json!({
+        "detail": "Other content depends on this; pass force=true to delete it anyway.",
+        "dependents": dependents,
+    }))
+}
+
+/// Repository versions that recorded any of `version_ids`, as
+/// `repository/number`.
+fn recorded_in(conn: &mut DbConnection, version_ids: &[i32]) -> QueryResult<Vec<String>> {
+    use crate::schema::{repositories, repository_version_content, repository_versions};
+    let rows = repository_version_content::table
+        .inner_join(repository_versions::table.inner_join(repositories::table))
+        .filter(repository_version_content::collection_version_id.eq_any(version_ids))
+        .select((repositories::name, repository_versions::number))
+        .distinct()
+        .order((repositories::name.asc(), repository_versions::number.asc()))
+        .load::<(String, i32)>(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|(repository, number)| format!("{repository}/{number}"))
+        .collect())
+}
+
+fn is_recorded(repository_versions: &[String]) -> HttpResponse {
+    HttpResponse::Conflict().json(json!({
+        "detail": "Repository versions still hold this; pass force=true to remove it from them too.",
+        "repository_versions": repository_versions,
+    }))
+}
+
+/// Takes `version_ids` out of the repository versions that recorded them,
+/// which a forced delete has to do before the versions can go.
+fn forget_recorded(conn: &mut DbConnection, version_ids: &[i32]) -> QueryResult<usize> {
+    use crate::schema::repository_version_content;
+    diesel::delete(
+        repository_version_content::table
+            .filter(repository_version_content::collection_version_id.eq_any(version_ids)),
+    )
+    .execute(conn)
+}
+
+/// Versions of other collections that depend on `namespace.name`, as
+/// `namespace.name:version` with the requirement they declare.
+fn collection_dependents(
+    conn: &mut DbConnection,
+    namespace: &str,
+    name: &str,
+) -> QueryResult<Vec<(String, String)>> {
+    use crate::schema::*;
+    let key = format!("{namespace}.{name}");
+    let rows = collection_versions::table
+        .inner_join(collections::table)
+        .filter(
+            sql::<Bool>(COLLECTION_DEPENDS_ON[0])
+                .bind::<Text, _>(key.clone())
+                .sql(COLLECTION_DEPENDS_ON[1]),
+        )
+        .filter(
+            collections::namespace
+                .ne(namespace)
+                .or(collections::name.ne(name)),
+        )
+        .select((
+            collections::namespace,
+            collections::name,
+            collection_versions::version,
+            collection_versions::metadata,
+        ))
+        .load::<(String, String, String, Value)>(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|(ns, n, version, metadata)| {
+            let spec = metadata["dependencies"][&key].as_str().unwrap_or("*");
+            (format!("{ns}.{n}:{version}"), spec.to_string())
+        })
+        .collect())
+}
+
+/// Roles whose dependencies name `namespace.name`.
+fn role_dependents(
+    conn: &mut DbConnection,
+    namespace: &str,
+    name: &str,
+) -> QueryResult<Vec<String>> {
+    use crate::schema::roles;
+    let key = format!("{namespace}.{name}");
+    let rows = roles::table
+        .filter(
+            sql::<Bool>(ROLE_DEPENDS_ON[0])
+                .bind::<Text, _>(key.clone())
+                .sql(ROLE_DEPENDS_ON[1])
+                .bind::<Text, _>(key.clone())
+                .sql(ROLE_DEPENDS_ON[2]),
+        )
+        .filter(roles::namespace.ne(namespace).or(roles::name.ne(name)))
+        .select((roles::namespace, roles::name))
+        .load::<(String, String)>(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|(ns, n)| format!("{ns}.{n}"))
+        .collect())
+}
+
+/// Version directories of a role on disk, skipping ones being deleted.
+fn role_versions(namespace: &str, name: &str) -> Vec<String> {
+    std::fs::read_dir(format!("content/roles/{namespace}/{name}/versions"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|v| !v.starts_with('.'))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn force_param(query: &HashMap<String, String>) -> Result<bool, HttpResponse> {
+    bool_param(query, "force")
+        .map(|force| force.unwrap_or(false))
+        .map_err(|msg| HttpResponse::BadRequest().json(msg))
+}
+
+#[api_v2_operation]
+#[delete("/api/v2/collections/{namespace}/{name}/")]
+async fn collection_delete(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    use crate::schema::*;
+    let force = match force_param(&query) {
+        Ok(force) => force,
+        Err(resp) => return resp,
+    };
+    let (namespace, name) = path.into_inner();
+    if let Some(resp) = invalid_segment([namespace.as_str(), &name], is_collection_name) {
+        return resp;
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    if let Some(resp) = permission_denied(&mut conn, &identity, &namespace) {
+        return resp;
+    }
+    let collection_id = collections::table
+        .select(collections::id)
+        .filter(collections::namespace.eq(&namespace))
+        .filter(collections::name.eq(&name))
+        .first::<i32>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(collection_id) = collection_id else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let dependents: Vec<String> = collection_dependents(&mut conn, &namespace, &name)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+        .into_iter()
+        .map(|(dependent, _)| dependent)
+        .collect();
+    if !dependents.is_empty() && !force {
+        return has_dependents(&dependents);
+    }
+    let version_ids: Vec<i32> = collection_versions::table
+        .filter(collection_versions::collection_id.eq(collection_id))
+        .select(collection_versions::id)
+        .load(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let recorded = recorded_in(&mut conn, &version_ids)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    if !recorded.is_empty() && !force {
+        return is_recorded(&recorded);
+    }
+    let dir = format!("content/collections/{namespace}/{name}/");
+    delete_atomically(&mut conn, &dir, |conn| {
+        forget_recorded(conn, &version_ids)?;
+        let deleted = diesel::delete(
+            collection_versions::table.filter(collection_versions::collection_id.eq(collection_id)),
+        )
+        .returning(collection_versions::version)
+        .get_results::<String>(conn)?;
+        diesel::delete(collections::table.find(collection_id)).execute(conn)?;
+        audit::record(
+            conn,
+            &identity,
+            "delete",
+            &format!("collection:{namespace}.{name}"),
+            &json!({
+                "versions": deleted,
+                "force": force,
+                "dependents": dependents,
+                "repository_versions": recorded,
+            }),
+        )
+    })
+}
+
+#[api_v2_operation]
+#[delete("/api/v2/collections/{namespace}/{name}/versions/{version}/")]
+async fn collection_version_delete(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String, String)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    use crate::schema::*;
+    let force = match force_param(&query) {
+        Ok(force) => force,
+        Err(resp) => return resp,
+    };
+    let (namespace, name, version) = path.into_inner();
+    if let Some(resp) = invalid_segment([namespace.as_str(), &name], is_collection_name)
+        .or_else(|| invalid_segment([version.as_str()], |v| Version::parse(v).is_ok()))
+    {
+        return resp;
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    if let Some(resp) = permission_denied(&mut conn, &identity, &namespace) {
+        return resp;
+    }
+    let existing = collection_versions::table
+        .inner_join(collections::table)
+        .filter(collections::namespace.eq(&namespace))
+        .filter(collections::name.eq(&name))
+        .select((
+            collection_versions::id,
+            collection_versions::collection_id,
+            collection_versions::version,
+        ))
+        .load::<(i32, i32, String)>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some((version_id, collection_id)) = existing
+        .iter()
+        .find(|(_, _, v)| *v == version)
+        .map(|(id, collection_id, _)| (*id, *collection_id))
+    else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let remaining: Vec<&str> = existing
+        .iter()
+        .map(|(_, _, v)| v.as_str())
+        .filter(|v| *v != version)
+        .collect();
+    // A dependent only breaks if no other stored version still satisfies it.
+    let dependents: Vec<String> = collection_dependents(&mut conn, &namespace, &name)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+        .into_iter()
+        .filter(|(_, spec)| {
+            versions::satisfies(&version, spec)
+                && !remaining.iter().any(|v| versions::satisfies(v, spec))
+        })
+        .map(|(dependent, _)| dependent)
+        .collect();
+    if !dependents.is_empty() && !force {
+        return has_dependents(&dependents);
+    }
+    let recorded = recorded_in(&mut conn, &[version_id])
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    if !recorded.is_empty() && !force {
+        return is_recorded(&recorded);
+    }
+    // Deleting the last version takes the collection with it.
+    let last = remaining.is_empty();
+    let dir = if last {
+        format!("content/collections/{namespace}/{name}/")
+    } else {
+        format!("content/collections/{namespace}/{name}/versions/{version}/")
+    };
+    delete_atomically(&mut conn, &dir, |conn| {
+        forget_recorded(conn, &[version_id])?;
+        diesel::delete(collection_versions::table.find(version_id)).execute(conn)?;
+        if last {
+            diesel::delete(collections::table.find(collection_id)).execute(conn)?;
+        }
+        audit::record(
+            conn,
+            &identity,
+            "delete",
+            &format!("collection_version:{namespace}.{name}:{version}"),
+            &json!({
+                "force": force,
+                "dependents": dependents,
+                "repository_versions": recorded,
+            }),
+        )
+    })
+}
+
+#[api_v2_operation]
+#[delete("/api/v1/roles/{namespace}/{name}/")]
+async fn role_delete(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    use crate::schema::roles;
+    let force = match force_param(&query) {
+        Ok(force) => force,
+        Err(resp) => return resp,
+    };
+    let (namespace, name) = path.into_inner();
+    if let Some(resp) = invalid_segment([namespace.as_str(), &name], is_role_segment) {
+        return resp;
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    if let Some(resp) = permission_denied(&mut conn, &identity, &namespace) {
+        return resp;
+    }
+    let dir = format!("content/roles/{namespace}/{name}/");
+    let stored = diesel::select(diesel::dsl::exists(
+        roles::table
+            .filter(roles::namespace.eq(&namespace))
+            .filter(roles::name.eq(&name)),
+    ))
+    .get_result::<bool>(&mut conn)
+    .map_err(error::ErrorInternalServerError)
+    .unwrap();
+    if !stored && std::fs::metadata(&dir).is_err() {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    }
+    let dependents = role_dependents(&mut conn, &namespace, &name)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    if !dependents.is_empty() && !force {
+        return has_dependents(&dependents);
+    }
+    let versions = role_versions(&namespace, &name);
+    delete_atomically(&mut conn, &dir, |conn| {
+        diesel::delete(
+            roles::table
+                .filter(roles::namespace.eq(&namespace))
+                .filter(roles::name.eq(&name)),
+        )
+        .execute(conn)?;
+        audit::record(
+            conn,
+            &identity,
+            "delete",
+            &format!("role:{namespace}.{name}"),
+            &json!({ "versions": versions, "force": force, "dependents": dependents }),
+        )
+    })
+}
+
+#[api_v2_operation]
+#[delete("/api/v1/roles/{namespace}/{name}/versions/{version}/")]
+async fn role_version_delete(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String, String)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    let force = match force_param(&query) {
+        Ok(force) => force,
+        Err(resp) => return resp,
+    };
+    let (namespace, name, version) = path.into_inner();
+    if let Some(resp) = invalid_segment([namespace.as_str(), &name, &version], is_role_segment) {
+        return resp;
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    if let Some(resp) = permission_denied(&mut conn, &identity, &namespace) {
+        return resp;
+    }
+    let versions = role_versions(&namespace, &name);
+    if !versions.contains(&version) {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    }
+    // Role dependencies aren't pinned, so only the last version matters.
+    let dependents = if versions.len() == 1 {
+        role_dependents(&mut conn, &namespace, &name)
+            .map_err(error::ErrorInternalServerError)
+            .unwrap()
+    } else {
+        Vec::new()
+    };
+    if !dependents.is_empty() && !force {
+        return has_dependents(&dependents);
+    }
+    let dir = format!("content/roles/{namespace}/{name}/versions/{version}/");
+    delete_atomically(&mut conn, &dir, |conn| {
+        audit::record(
+            conn,
+            &identity,
+            "delete",
+            &format!("role_version:{namespace}.{name}:{version}"),
+            &json!({ "force": force, "dependents": dependents }),
+        )
+    })
+}
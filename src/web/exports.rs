@@ -0,0 +1,166 @@
+use super::audit;
+use super::auth::Identity;
+use super::routes::import_status;
+use crate::bundle::{export_task, import_bundle_task, ExportOptions, Index, EXPORT_DIR};
+use crate::db_utils::DbConnection;
+use crate::tasks::Tasks;
+use actix_web::{error, HttpResponse, Responder};
+use diesel::r2d2::{ConnectionManager, Pool};
+use paperclip::actix::{api_v2_operation, get, post, web, Apiv2Schema};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct ExportRequest {
+    /// `namespace` or `namespace.name` of each collection, all when missing.
+    collections: Option<Vec<String>>,
+    /// `namespace` or `namespace.name` of each role, all when missing.
+    roles: Option<Vec<String>>,
+    /// Name of an earlier export to build on.
+    since: Option<String>,
+    chunk_size: Option<u64>,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct BundleImportRequest {
+    /// Directory of the export on the server, as carried over.
+    path: String,
+}
+
+fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .json(json!({"detail": "You do not have permission to perform this action."}))
+}
+
+/// Starts writing an export bundle to `exports/`.
+#[api_v2_operation]
+#[post("/api/v3/exports/")]
+async fn export_create(
+    identity: Identity,
+    db_pool: web::Data<DbPool>,
+    tasks: web::Data<Tasks>,
+    body: web::Json<ExportRequest>,
+) -> impl Responder {
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let body = body.into_inner();
+    if body.chunk_size == Some(0) {
+        return HttpResponse::BadRequest()
+            .json(json!({"detail": "chunk_size must be at least one byte."}));
+    }
+    if let Some(since) = &body.since {
+        if Index::read(Path::new(EXPORT_DIR), since).is_err() {
+            return HttpResponse::BadRequest()
+                .json(json!({"detail": format!("Unknown export {since}.")}));
+        }
+    }
+    let mut conn = db_pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let task_uuid = tasks
+        .create("export", 172800)
+        .map_err(error::ErrorInternalServerError)
+        .expect("Error creating task");
+    audit::record(
+        &mut conn,
+        &identity,
+        "export",
+        &task_uuid,
+        &json!({
+            "collections": body.collections,
+            "roles": body.roles,
+            "since": body.since,
+            "chunk_size": body.chunk_size,
+        }),
+    )
+    .map_err(error::ErrorInternalServerError)
+    .unwrap();
+    let options = ExportOptions {
+        collections: body.collections,
+        roles: body.roles,
+        since: body.since,
+        chunk_size: body.chunk_size,
+    };
+    let resp = json!({ "task": task_uuid });
+    actix_web::rt::spawn(
+        async move { export_task(task_uuid.as_str(), options, db_pool, tasks).await },
+    );
+    HttpResponse::Ok().json(resp)
+}
+
+/// Lists the finished exports, newest first, with the files to carry over.
+#[api_v2_operation]
+#[get("/api/v3/exports/")]
+async fn export_list(identity: Identity) -> impl Responder {
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let mut names = Index::names(Path::new(EXPORT_DIR));
+    names.sort_by(|a, b| b.cmp(a));
+    // Exports still being written have no index yet.
+    let results: Vec<_> = names
+        .iter()
+        .filter_map(|name| Index::read(Path::new(EXPORT_DIR), name).ok())
+        .map(|index| {
+            json!({
+                "name": index.name,
+                "created_at": index.created_at,
+                "since": index.since,
+                "chunks": index.chunks,
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({ "count": results.len(), "results": results }))
+}
+
+/// Starts importing an export that was copied to the server.
+#[api_v2_operation]
+#[post("/api/v3/imports/bundles/")]
+async fn bundle_import(
+    identity: Identity,
+    db_pool: web::Data<DbPool>,
+    tasks: web::Data<Tasks>,
+    body: web::Json<BundleImportRequest>,
+) -> impl Responder {
+    if !identity.is_superuser {
+        return forbidden();
+    }
+    let dir = PathBuf::from(&body.path);
+    if !dir.is_dir() {
+        return HttpResponse::BadRequest()
+            .json(json!({"detail": format!("{} is not a directory.", body.path)}));
+    }
+    let mut conn = db_pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let task_uuid = tasks
+        .create("bundle-import", 172800)
+        .map_err(error::ErrorInternalServerError)
+        .expect("Error creating task");
+    audit::record(
+        &mut conn,
+        &identity,
+        "import",
+        &task_uuid,
+        &json!({ "path": body.path }),
+    )
+    .map_err(error::ErrorInternalServerError)
+    .unwrap();
+    let resp = json!({ "task": task_uuid });
+    actix_web::rt::spawn(async move {
+        import_bundle_task(task_uuid.as_str(), dir, db_pool, tasks).await
+    });
+    HttpResponse::Ok().json(resp)
+}
+
+#[api_v2_operation]
+#[get("/api/v3/imports/bundles/{task_id}/")]
+async fn bundle_import_status(tasks: web::Data<Tasks>, path: web::Path<String>) -> impl Responder {
+    import_status(&tasks, path.into_inner())
+}
@@ -0,0 +1,58 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Reads a boolean filter such as `?is_deprecated=false`.
+pub fn bool_param(query: &HashMap<String, String>, key: &str) -> Result<Option<bool>, Value> {
+    match query.get(key).map(|v| v.as_str()) {
+        None => Ok(None),
+        Some("true") | Some("True") | Some("1") => Ok(Some(true)),
+        Some("false") | Some("False") | Some("0") => Ok(Some(false)),
+        Some(_) => Err(json!({ key: "Must be one of: true, false." })),
+    }
+}
+
+/// Reads a list filter such as `?tags=linux,networking`; `+` and spaces also separate values.
+pub fn list_param(query: &HashMap<String, String>, key: &str) -> Vec<String> {
+    query
+        .get(key)
+        .map(|v| {
+            v.split([',', '+', ' '])
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Turns free-text keywords into what the keyword filters bind: a
+/// prefix-matching `tsquery` for the `simple` configuration.
+#[cfg(not(feature = "sqlite"))]
+pub fn keywords_query(keywords: &str) -> Option<String> {
+    let terms = keyword_terms(keywords);
+    if terms.is_empty() {
+        None
+    } else {
+        let prefixes: Vec<String> = terms.iter().map(|t| format!("{t}:*")).collect();
+        Some(prefixes.join(" & "))
+    }
+}
+
+/// Turns free-text keywords into what the keyword filters bind: a JSON
+/// array of the terms to look for.
+#[cfg(feature = "sqlite")]
+pub fn keywords_query(keywords: &str) -> Option<String> {
+    let terms = keyword_terms(keywords);
+    if terms.is_empty() {
+        None
+    } else {
+        Some(json!(terms).to_string())
+    }
+}
+
+fn keyword_terms(keywords: &str) -> Vec<String> {
+    keywords
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
@@ -1,3 +1,18 @@
+mod admin;
+mod audit;
+mod auth;
+mod content;
+mod deletion;
+mod exports;
+mod filters;
+mod namespaces;
+mod pagination;
+mod repositories;
 mod routes;
+mod search;
 mod server;
+mod signatures;
+mod tls;
+mod urls;
 pub use server::start_actix_server;
+pub use urls::UrlBuilder;
@@ -0,0 +1,283 @@
+use super::auth::{can_publish, Identity};
+use super::pagination::Pagination;
+use super::urls::UrlBuilder;
+use crate::db_utils::DbConnection;
+use crate::models::Namespace;
+use actix_web::{error, HttpRequest, HttpResponse, Responder};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use paperclip::actix::{api_v2_operation, get, put, web, Apiv2Schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+// `ilike` is Postgres-only.
+define_sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+#[derive(Deserialize, Serialize, Apiv2Schema)]
+pub struct NamespaceLink {
+    name: String,
+    url: String,
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct NamespaceRequest {
+    #[serde(default)]
+    company: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    avatar_url: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    resources: String,
+    #[serde(default)]
+    links: Vec<NamespaceLink>,
+}
+
+/// The v3 representation, also embedded in collection responses.
+pub fn namespace_json(urls: &UrlBuilder, namespace: &Namespace) -> Value {
+    json!({
+        "id": namespace.id,
+        "href": urls.namespace(&namespace.name),
+        "name": namespace.name,
+        "company": namespace.company,
+        "email": namespace.email,
+        "avatar_url": namespace.avatar_url,
+        "description": namespace.description,
+        "resources": namespace.resources,
+        "links": namespace.links,
+    })
+}
+
+/// Looks up `name` for embedding; content synced before namespaces were
+/// tracked may still lack a row, so fall back to just the name.
+pub fn namespace_summary(conn: &mut DbConnection, urls: &UrlBuilder, name: &str) -> Value {
+    use crate::schema::namespaces;
+    namespaces::table
+        .filter(namespaces::name.eq(name))
+        .select(Namespace::as_select())
+        .first::<Namespace>(conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+        .map(|namespace| namespace_json(urls, &namespace))
+        .unwrap_or_else(|| json!({ "name": name }))
+}
+
+fn v1_namespace_json(urls: &UrlBuilder, namespace: &Namespace) -> Value {
+    let html_url = namespace.links[0]["url"].as_str().unwrap_or("");
+    json!({
+        "id": namespace.id,
+        "url": urls.absolute(&format!("/api/v1/namespaces/{}/", namespace.id)),
+        "name": namespace.name,
+        "company": namespace.company,
+        "email": namespace.email,
+        "avatar_url": namespace.avatar_url,
+        "description": namespace.description,
+        "html_url": html_url,
+        "related": {
+            "content": urls.absolute(&format!("/api/v1/search/roles/?namespace={}", namespace.name)),
+        },
+        "summary_fields": {"links": namespace.links},
+    })
+}
+
+fn load_page(
+    conn: &mut DbConnection,
+    query: &HashMap<String, String>,
+    pagination: &Pagination,
+) -> QueryResult<(i64, Vec<Namespace>)> {
+    use crate::schema::namespaces;
+    let filtered = || {
+        let mut q = namespaces::table.into_boxed();
+        if let Some(name) = query.get("name") {
+            q = q.filter(namespaces::name.eq(name.clone()));
+        }
+        if let Some(company) = query.get("company") {
+            q = q.filter(namespaces::company.eq(company.clone()));
+        }
+        if let Some(keywords) = query.get("keywords").or_else(|| query.get("q")) {
+            let pattern = format!("%{}%", keywords.to_lowercase());
+            q = q.filter(
+                lower(namespaces::name)
+                    .like(pattern.clone())
+                    .or(lower(namespaces::company).like(pattern.clone()))
+                    .or(lower(namespaces::description).like(pattern)),
+            );
+        }
+        q
+    };
+    let count = filtered().count().get_result(conn)?;
+    let results = filtered()
+        .select(Namespace::as_select())
+        .order(namespaces::name.asc())
+        .limit(pagination.limit)
+        .offset(pagination.offset)
+        .load::<Namespace>(conn)?;
+    Ok((count, results))
+}
+
+#[api_v2_operation]
+#[get("/api/v3/namespaces/")]
+async fn namespace_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    let pagination = match Pagination::from_limit_offset(&query) {
+        Ok(pagination) => pagination,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let (count, results) = load_page(&mut conn, &query, &pagination)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let urls = UrlBuilder::from_request(&req);
+    let data: Vec<Value> = results
+        .iter()
+        .map(|namespace| namespace_json(&urls, namespace))
+        .collect();
+    let resp = pagination.v3_page(
+        &urls.relative(req.path()),
+        &query,
+        count,
+        Value::Array(data),
+    );
+    HttpResponse::Ok().json(resp)
+}
+
+#[api_v2_operation]
+#[get("/api/v3/namespaces/{name}/")]
+async fn namespace_retrieve(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+) -> impl Responder {
+    use crate::schema::namespaces;
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let namespace = namespaces::table
+        .filter(namespaces::name.eq(path.into_inner()))
+        .select(Namespace::as_select())
+        .first::<Namespace>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(namespace) = namespace else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let urls = UrlBuilder::from_request(&req);
+    HttpResponse::Ok().json(namespace_json(&urls, &namespace))
+}
+
+#[api_v2_operation]
+#[put("/api/v3/namespaces/{name}/")]
+async fn namespace_update(
+    req: HttpRequest,
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    body: web::Json<NamespaceRequest>,
+) -> impl Responder {
+    use crate::schema::namespaces;
+    let name = path.into_inner();
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    if !can_publish(&mut conn, &identity, &name)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    {
+        return HttpResponse::Forbidden().json(
+            json!({"detail": format!("You are not allowed to edit the '{name}' namespace.")}),
+        );
+    }
+    let body = body.into_inner();
+    let namespace = diesel::update(namespaces::table.filter(namespaces::name.eq(&name)))
+        .set((
+            namespaces::company.eq(body.company),
+            namespaces::email.eq(body.email),
+            namespaces::avatar_url.eq(body.avatar_url),
+            namespaces::description.eq(body.description),
+            namespaces::resources.eq(body.resources),
+            namespaces::links.eq(json!(body.links)),
+        ))
+        .returning(Namespace::as_returning())
+        .get_result::<Namespace>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(namespace) = namespace else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let urls = UrlBuilder::from_request(&req);
+    HttpResponse::Ok().json(namespace_json(&urls, &namespace))
+}
+
+#[api_v2_operation]
+#[get("/api/v1/namespaces/")]
+async fn v1_namespace_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    let pagination = match Pagination::from_query(&query) {
+        Ok(pagination) => pagination,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let (count, results) = load_page(&mut conn, &query, &pagination)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let urls = UrlBuilder::from_request(&req);
+    let data: Vec<Value> = results
+        .iter()
+        .map(|namespace| v1_namespace_json(&urls, namespace))
+        .collect();
+    let resp = pagination.v2_page(
+        &urls.absolute(req.path()),
+        &query,
+        count,
+        Value::Array(data),
+    );
+    HttpResponse::Ok().json(resp)
+}
+
+#[api_v2_operation]
+#[get("/api/v1/namespaces/{id}/")]
+async fn v1_namespace_retrieve(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> impl Responder {
+    use crate::schema::namespaces;
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let namespace = namespaces::table
+        .find(path.into_inner())
+        .select(Namespace::as_select())
+        .first::<Namespace>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(namespace) = namespace else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let urls = UrlBuilder::from_request(&req);
+    HttpResponse::Ok().json(v1_namespace_json(&urls, &namespace))
+}
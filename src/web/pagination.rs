@@ -0,0 +1,139 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    Page,
+    Offset,
+}
+
+/// Galaxy-style pagination parsed from `limit`/`offset` or `page`/`page_size`.
+#[derive(Debug, Clone, Copy)]
+pub struct Pagination {
+    pub limit: i64,
+    pub offset: i64,
+    style: Style,
+}
+
+fn parse_param(query: &HashMap<String, String>, key: &str) -> Result<Option<i64>, Value> {
+    match query.get(key) {
+        None => Ok(None),
+        Some(raw) => match raw.parse::<i64>() {
+            Ok(value) if value >= 0 => Ok(Some(value)),
+            _ => Err(json!({ key: "A non-negative integer is required." })),
+        },
+    }
+}
+
+impl Pagination {
+    /// Parses v3 pagination, which only knows about `limit`/`offset`.
+    pub fn from_limit_offset(query: &HashMap<String, String>) -> Result<Self, Value> {
+        Ok(Pagination {
+            limit: parse_param(query, "limit")?
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE),
+            offset: parse_param(query, "offset")?.unwrap_or(0),
+            style: Style::Offset,
+        })
+    }
+
+    pub fn from_query(query: &HashMap<String, String>) -> Result<Self, Value> {
+        if query.contains_key("limit") || query.contains_key("offset") {
+            return Self::from_limit_offset(query);
+        }
+        let page_size = parse_param(query, "page_size")?
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let page = parse_param(query, "page")?.unwrap_or(1).max(1);
+        let offset = (page - 1)
+            .checked_mul(page_size)
+            .ok_or_else(|| json!({"page": "Invalid page."}))?;
+        Ok(Pagination {
+            limit: page_size,
+            offset,
+            style: Style::Page,
+        })
+    }
+
+    fn link(&self, base: &str, query: &HashMap<String, String>, offset: i64) -> String {
+        let mut params: Vec<(String, String)> = query
+            .iter()
+            .filter(|(k, _)| !["limit", "offset", "page", "page_size"].contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        match self.style {
+            Style::Offset => {
+                params.push(("limit".to_string(), self.limit.to_string()));
+                params.push(("offset".to_string(), offset.to_string()));
+            }
+            Style::Page => {
+                params.push(("page".to_string(), (offset / self.limit + 1).to_string()));
+                params.push(("page_size".to_string(), self.limit.to_string()));
+            }
+        }
+        params.sort();
+        let encoded = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params)
+            .finish();
+        format!("{base}?{encoded}")
+    }
+
+    pub fn next(&self, base: &str, query: &HashMap<String, String>, count: i64) -> Option<String> {
+        if self.offset.saturating_add(self.limit) >= count {
+            return None;
+        }
+        Some(self.link(base, query, self.offset + self.limit))
+    }
+
+    pub fn previous(&self, base: &str, query: &HashMap<String, String>) -> Option<String> {
+        if self.offset == 0 {
+            return None;
+        }
+        Some(self.link(base, query, (self.offset - self.limit).max(0)))
+    }
+
+    /// Renders a v2 page: `count`, `next`, `previous` and `results`.
+    pub fn v2_page(
+        &self,
+        base: &str,
+        query: &HashMap<String, String>,
+        count: i64,
+        results: Value,
+    ) -> Value {
+        json!({
+            "count": count,
+            "next": self.next(base, query, count),
+            "previous": self.previous(base, query),
+            "results": results,
+        })
+    }
+
+    /// Renders a v3 page: `meta`, `links` and `data`. Links are relative to
+    /// the server root, as Galaxy NG returns them.
+    pub fn v3_page(
+        &self,
+        base: &str,
+        query: &HashMap<String, String>,
+        count: i64,
+        data: Value,
+    ) -> Value {
+        let last = if count > 0 {
+            (count - 1) / self.limit * self.limit
+        } else {
+            0
+        };
+        json!({
+            "meta": {"count": count},
+            "links": {
+                "first": self.link(base, query, 0),
+                "previous": self.previous(base, query),
+                "next": self.next(base, query, count),
+                "last": self.link(base, query, last),
+            },
+            "data": data,
+        })
+    }
+}
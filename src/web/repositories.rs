@@ -0,0 +1,392 @@
+use super::audit;
+use super::auth::Identity;
+use super::routes::{
+    list_collection_versions, list_collections, retrieve_collection, retrieve_collection_version,
+    Scope,
+};
+use crate::db_utils::{write_transaction, DbConnection};
+use crate::models::RepositoryVersion;
+use crate::repository;
+use actix_web::{error, HttpRequest, HttpResponse, Responder};
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::{Integer, Nullable};
+use paperclip::actix::{api_v2_operation, get, post, web, Apiv2Schema};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+#[api_v2_operation]
+#[get("/api/v3/repositories/")]
+async fn repository_list(pool: web::Data<DbPool>) -> impl Responder {
+    use crate::schema::{repositories, repository_content};
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let results = repositories::table
+        .left_join(repository_content::table)
+        .group_by((
+            repositories::id,
+            repositories::name,
+            repositories::description,
+        ))
+        .select((
+            repositories::name,
+            repositories::description,
+            diesel::dsl::count(repository_content::collection_version_id.nullable()),
+            sql::<Nullable<Integer>>(
+                "(SELECT MAX(number) FROM repository_versions \
+                 WHERE repository_id = repositories.id)",
+            ),
+        ))
+        .order(repositories::id.asc())
+        .load::<(String, String, i64, Option<i32>)>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let data: Vec<_> = results
+        .iter()
+        .map(|(name, description, count, latest)| {
+            json!({
+                "name": name,
+                "description": description,
+                "collection_version_count": count,
+                "latest_version": latest,
+                "base_path": format!("/api/v3/content/{name}/"),
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({ "count": data.len(), "results": data }))
+}
+
+/// API root of a repository, so `ansible-galaxy -s <url>/api/v3/content/<repo>/`
+/// talks to that repository only.
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/")]
+async fn repository_api_root(pool: web::Data<DbPool>, path: web::Path<String>) -> impl Responder {
+    api_root(&pool, &path.into_inner(), None)
+}
+
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/versions/{number}/")]
+async fn repository_version_api_root(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32)>,
+) -> impl Responder {
+    let (repository, number) = path.into_inner();
+    api_root(&pool, &repository, Some(number))
+}
+
+fn api_root(pool: &DbPool, repository: &str, number: Option<i32>) -> HttpResponse {
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let mut found = repository::find(&mut conn, repository)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    if let (Some(repository_id), Some(number)) = (found, number) {
+        found = repository::find_version(&mut conn, repository_id, number)
+            .map_err(error::ErrorInternalServerError)
+            .unwrap();
+    }
+    if found.is_none() {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    }
+    HttpResponse::Ok().json(json!({"current_version": "v2", "available_versions": {"v2": "v2/"}}))
+}
+
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/v2/collections/")]
+async fn repository_collection_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    list_collections(&req, &pool, &query, Scope::Repository(&path.into_inner()))
+}
+
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/v2/collections/{namespace}/{name}/")]
+async fn repository_collection_retrieve(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String, String)>,
+) -> impl Responder {
+    let (repository, namespace, name) = path.into_inner();
+    retrieve_collection(
+        &req,
+        &pool,
+        &namespace,
+        &name,
+        Scope::Repository(&repository),
+    )
+}
+
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/v2/collections/{namespace}/{name}/versions/")]
+async fn repository_collection_version_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String, String)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    let (repository, namespace, name) = path.into_inner();
+    list_collection_versions(
+        &req,
+        &pool,
+        &query,
+        &namespace,
+        &name,
+        Scope::Repository(&repository),
+    )
+}
+
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/v2/collections/{namespace}/{name}/versions/{version}/")]
+async fn repository_collection_version_retrieve(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String, String, String)>,
+) -> impl Responder {
+    let (repository, namespace, name, version) = path.into_inner();
+    retrieve_collection_version(
+        &req,
+        &pool,
+        &namespace,
+        &name,
+        &version,
+        Scope::Repository(&repository),
+    )
+}
+
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/versions/{number}/v2/collections/")]
+async fn repository_version_collection_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    let (repository, number) = path.into_inner();
+    list_collections(&req, &pool, &query, Scope::Snapshot(&repository, number))
+}
+
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/versions/{number}/v2/collections/{namespace}/{name}/")]
+async fn repository_version_collection_retrieve(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32, String, String)>,
+) -> impl Responder {
+    let (repository, number, namespace, name) = path.into_inner();
+    let scope = Scope::Snapshot(&repository, number);
+    retrieve_collection(&req, &pool, &namespace, &name, scope)
+}
+
+#[api_v2_operation]
+#[get("/api/v3/content/{repository}/versions/{number}/v2/collections/{namespace}/{name}/versions/")]
+async fn repository_version_collection_version_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32, String, String)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    let (repository, number, namespace, name) = path.into_inner();
+    let scope = Scope::Snapshot(&repository, number);
+    list_collection_versions(&req, &pool, &query, &namespace, &name, scope)
+}
+
+#[api_v2_operation]
+#[get(
+    "/api/v3/content/{repository}/versions/{number}/v2/collections/{namespace}/{name}/versions/{version}/"
+)]
+async fn repository_version_collection_version_retrieve(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i32, String, String, String)>,
+) -> impl Responder {
+    let (repository, number, namespace, name, version) = path.into_inner();
+    let scope = Scope::Snapshot(&repository, number);
+    retrieve_collection_version(&req, &pool, &namespace, &name, &version, scope)
+}
+
+#[api_v2_operation]
+#[get("/api/v3/repositories/{repository}/versions/")]
+async fn repository_version_list(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+) -> impl Responder {
+    use crate::schema::{repository_version_content, repository_versions};
+    let name = path.into_inner();
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let Some(repository_id) = repository::find(&mut conn, &name)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let results = repository_versions::table
+        .left_join(repository_version_content::table)
+        .filter(repository_versions::repository_id.eq(repository_id))
+        .group_by(repository_versions::id)
+        .select((
+            RepositoryVersion::as_select(),
+            diesel::dsl::count(repository_version_content::collection_version_id.nullable()),
+        ))
+        .order(repository_versions::number.desc())
+        .load::<(RepositoryVersion, i64)>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let data: Vec<_> = results
+        .iter()
+        .map(|(version, count)| {
+            json!({
+                "number": version.number,
+                "created_at": version.created_at,
+                "collection_version_count": count,
+                "base_path": format!("/api/v3/content/{name}/versions/{}/", version.number),
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({ "count": data.len(), "results": data }))
+}
+
+#[derive(Deserialize, Apiv2Schema)]
+pub struct RollbackRequest {
+    number: i32,
+}
+
+/// Restores the content of a repository version, recorded as a new version.
+#[api_v2_operation]
+#[post("/api/v3/repositories/{repository}/rollback/")]
+async fn repository_rollback(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    body: web::Json<RollbackRequest>,
+) -> impl Responder {
+    if !identity.is_superuser {
+        return HttpResponse::Forbidden()
+            .json(json!({"detail": "You do not have permission to perform this action."}));
+    }
+    let name = path.into_inner();
+    let number = body.number;
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let Some(repository_id) = repository::find(&mut conn, &name)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let Some(version_id) = repository::find_version(&mut conn, repository_id, number)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    else {
+        return HttpResponse::BadRequest()
+            .json(json!({"number": format!("'{name}' has no version {number}.")}));
+    };
+    let latest = write_transaction(&mut conn, |conn| {
+        repository::rollback(conn, repository_id, version_id)?;
+        repository::snapshot(conn, &name)?;
+        let latest = repository::latest_version(conn, repository_id)?.map(|(_, n)| n);
+        audit::record(
+            conn,
+            &identity,
+            "rollback",
+            &format!("repository:{name}"),
+            &json!({ "number": number, "latest_version": latest }),
+        )?;
+        QueryResult::Ok(latest)
+    })
+    .map_err(error::ErrorInternalServerError)
+    .unwrap();
+    HttpResponse::Ok().json(json!({
+        "name": name,
+        "restored_version": number,
+        "latest_version": latest,
+    }))
+}
+
+/// Promotes (or rejects) a version by moving it between repositories.
+#[api_v2_operation]
+#[post("/api/v3/collections/{namespace}/{name}/versions/{version}/move/{source}/{destination}/")]
+async fn collection_version_move(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String, String, String, String)>,
+) -> impl Responder {
+    use crate::schema::{collection_versions, collections};
+    if !identity.is_superuser {
+        return HttpResponse::Forbidden()
+            .json(json!({"detail": "You do not have permission to perform this action."}));
+    }
+    let (namespace, name, version, source, destination) = path.into_inner();
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let source_id = repository::find(&mut conn, &source)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let destination_id = repository::find(&mut conn, &destination)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let version_id = collection_versions::table
+        .inner_join(collections::table)
+        .filter(collections::namespace.eq(&namespace))
+        .filter(collections::name.eq(&name))
+        .filter(collection_versions::version.eq(&version))
+        .select(collection_versions::id)
+        .first::<i32>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let (Some(source_id), Some(destination_id), Some(version_id)) =
+        (source_id, destination_id, version_id)
+    else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    if source_id == destination_id {
+        return HttpResponse::BadRequest()
+            .json(json!({"destination": "Must differ from the source repository."}));
+    }
+    let moved = write_transaction(&mut conn, |conn| {
+        let moved = repository::move_version(conn, version_id, source_id, destination_id)?;
+        if moved {
+            repository::snapshot(conn, &source)?;
+            repository::snapshot(conn, &destination)?;
+            audit::record(
+                conn,
+                &identity,
+                "move",
+                &format!("collection_version:{namespace}.{name}:{version}"),
+                &json!({ "source": source, "destination": destination }),
+            )?;
+        }
+        QueryResult::Ok(moved)
+    })
+    .map_err(error::ErrorInternalServerError)
+    .unwrap();
+    if !moved {
+        return HttpResponse::NotFound().json(json!({
+            "detail": format!("{namespace}.{name}:{version} is not in the '{source}' repository.")
+        }));
+    }
+    HttpResponse::Ok().json(json!({
+        "namespace": namespace,
+        "name": name,
+        "version": version,
+        "repository": destination,
+    }))
+}
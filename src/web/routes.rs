@@ -1,24 +1,36 @@
+use super::audit;
+use super::auth::{can_publish, Identity};
+use super::filters::bool_param;
+use super::namespaces::namespace_summary;
+use super::pagination::Pagination;
+use super::signatures::signatures_json;
+use super::urls::UrlBuilder;
+use crate::config::Config;
+use crate::db_utils::DbConnection;
 use crate::models::{self, Collection};
-use crate::sync::{import_task, mirror_content, process_requirements};
+use crate::repository;
+use crate::sync::{
+    check_git_source, import_directory_task, import_task, mirror_content, parse_filename,
+    process_requirements, role_import_task, RoleImport, RoleSource,
+};
+use crate::tasks::{Tasks, TASK_KINDS};
+use crate::versions;
 use actix_multipart::Multipart;
-use actix_web::{error, HttpResponse, Responder};
+use actix_web::{error, HttpRequest, HttpResponse, Responder};
+use diesel::dsl::{sql, AsExprOf};
+use diesel::expression::{SqlLiteral, UncheckedBind};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::{Bool, Integer, Text};
 use diesel::{prelude::*, ExpressionMethods};
-use diesel::{
-    r2d2::{ConnectionManager, Pool},
-    PgConnection,
-};
 use futures::TryStreamExt;
-use paperclip::actix::{api_v2_operation, get, post, web};
-use r2d2_redis::redis::{Commands, FromRedisValue};
-use r2d2_redis::RedisConnectionManager;
-use semver::Version;
+use paperclip::actix::{api_v2_operation, get, patch, post, web, Apiv2Schema};
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::collections::HashMap;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
 use url::Url;
-use uuid::Uuid;
 
-type DbPool = Pool<ConnectionManager<PgConnection>>;
+type DbPool = Pool<ConnectionManager<DbConnection>>;
 
 #[api_v2_operation]
 #[get("/api/")]
@@ -29,28 +41,21 @@ async fn api_metadata() -> impl Responder {
 
 #[api_v2_operation]
 #[get("/api/status/")]
-async fn api_status(
-    pool: web::Data<DbPool>,
-    redis_pool: web::Data<Pool<RedisConnectionManager>>,
-) -> impl Responder {
+async fn api_status(pool: web::Data<DbPool>, tasks: web::Data<Tasks>) -> impl Responder {
     pool.get()
         .map_err(error::ErrorServiceUnavailable)
         .expect("couldn't get db connection from pool");
-    redis_pool
-        .get()
+    let tasks_status = tasks
+        .status()
         .map_err(error::ErrorServiceUnavailable)
-        .expect("couldn't get redis connection from pool");
+        .expect("couldn't reach the task store");
     let state = pool.state();
-    let redis_state = redis_pool.state();
     let resp = json!({
         "db": {
             "connections": state.connections,
             "idle_connections": state.idle_connections
         },
-        "redis": {
-            "connections": redis_state.connections,
-            "idle_connections": redis_state.idle_connections
-        },
+        "tasks": tasks_status,
         "status": "ok"
     });
     HttpResponse::Ok().json(resp)
@@ -58,58 +63,31 @@ async fn api_status(
 
 #[api_v2_operation]
 #[get("/api/v2/tasks/{task_id}/")]
-async fn task_retrieve(
-    pool: web::Data<Pool<RedisConnectionManager>>,
-    path: web::Path<String>,
-) -> impl Responder {
-    let mut conn = pool
-        .get_timeout(Duration::from_secs(1))
-        .map_err(error::ErrorInternalServerError)
-        .expect("couldn't get redis connection from pool");
+async fn task_retrieve(tasks: web::Data<Tasks>, path: web::Path<String>) -> impl Responder {
     let task_id = path.into_inner();
-    let value = conn
-        .get(task_id.as_str())
+    let state = tasks
+        .state(&task_id)
         .map_err(error::ErrorInternalServerError)
-        .expect("Error getting key");
-    let state: String = FromRedisValue::from_redis_value(&value)
-        .map_err(error::ErrorInternalServerError)
-        .expect("Redis: Error getting value");
-    let resp = json!({"state": state});
-    HttpResponse::Ok().json(resp)
+        .expect("Error getting task state");
+    match state {
+        Some(state) => HttpResponse::Ok().json(json!({"state": state})),
+        None => HttpResponse::NotFound().json(json!({"detail": "Not found."})),
+    }
 }
 
 #[api_v2_operation]
 #[get("/api/v2/tasks/")]
-async fn task_list(pool: web::Data<Pool<RedisConnectionManager>>) -> impl Responder {
-    let mut conn = pool
-        .get_timeout(Duration::from_secs(1))
-        .map_err(error::ErrorInternalServerError)
-        .expect("couldn't get redis connection from pool");
+async fn task_list(tasks: web::Data<Tasks>) -> impl Responder {
     let mut resp = json!({"tasks": {}});
-    let patterns = vec!["upload:*", "mirror:*", "requirements:*"];
-    for pattern in patterns {
-        let values = conn
-            .keys(pattern)
+    for kind in TASK_KINDS {
+        let data: Vec<Value> = tasks
+            .list(kind)
             .map_err(error::ErrorInternalServerError)
-            .expect("Error getting keys");
-        let ids: Vec<String> = FromRedisValue::from_redis_value(&values)
-            .map_err(error::ErrorInternalServerError)
-            .expect("Redis: Error getting value");
-        let data: Vec<Value> = ids
-            .iter()
-            .map(|id| {
-                let value: String = FromRedisValue::from_redis_value(
-                    &conn
-                        .get(id)
-                        .map_err(error::ErrorInternalServerError)
-                        .expect("Error getting key"),
-                )
-                .map_err(error::ErrorInternalServerError)
-                .expect("Redis: Error getting value");
-                json!({"uuid": id, "state": value})
-            })
+            .expect("Error listing tasks")
+            .into_iter()
+            .map(|(id, state)| json!({"uuid": id, "state": state}))
             .collect();
-        resp["tasks"][pattern.replace(":*", "")] = Value::Array(data)
+        resp["tasks"][kind] = Value::Array(data)
     }
     HttpResponse::Ok().json(resp)
 }
@@ -119,22 +97,15 @@ async fn task_list(pool: web::Data<Pool<RedisConnectionManager>>) -> impl Respon
 async fn start_sync(
     path: web::Path<String>,
     db_pool: web::Data<DbPool>,
-    redis_pool: web::Data<Pool<RedisConnectionManager>>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
 ) -> impl Responder {
     let content_type = path.into_inner();
-    let galaxy_url = dotenv::var("GALAXY_URL").unwrap_or("https://galaxy.ansible.com/".to_string());
-    let root = Url::parse(galaxy_url.as_str()).unwrap();
-    let mut conn = redis_pool
-        .get_timeout(Duration::from_secs(1))
-        .map_err(error::ErrorInternalServerError)
-        .expect("couldn't get redis connection from pool");
-    let task_uuid = format!("mirror:{}", Uuid::new_v4());
-    conn.set::<&str, &str, bool>(task_uuid.as_str(), "waiting")
+    let root = Url::parse(&config.sync.galaxy_url).unwrap();
+    let task_uuid = tasks
+        .create("mirror", 172800)
         .map_err(error::ErrorInternalServerError)
-        .expect("Redis: Error setting key");
-    conn.expire::<&str, usize>(task_uuid.as_str(), 172800)
-        .map_err(error::ErrorInternalServerError)
-        .expect("Redis: Error setting TTL");
+        .expect("Error creating task");
     let resp = json!({ "syncing": content_type, "task": task_uuid });
     actix_web::rt::spawn(async move {
         mirror_content(
@@ -142,7 +113,8 @@ async fn start_sync(
             root,
             content_type.as_str(),
             db_pool,
-            redis_pool,
+            tasks,
+            config,
         )
         .await
     });
@@ -153,7 +125,8 @@ async fn start_sync(
 async fn start_req_sync(
     mut payload: Multipart,
     db_pool: web::Data<DbPool>,
-    redis_pool: web::Data<Pool<RedisConnectionManager>>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
 ) -> impl Responder {
     let mut field = payload
         .try_next()
@@ -173,22 +146,14 @@ async fn start_req_sync(
     while let Ok(Some(chunk)) = field.try_next().await {
         data.extend_from_slice(&chunk);
     }
-    let galaxy_url = dotenv::var("GALAXY_URL").unwrap_or("https://galaxy.ansible.com/".to_string());
-    let root = Url::parse(galaxy_url.as_str()).unwrap();
-    let mut conn = redis_pool
-        .get_timeout(Duration::from_secs(1))
-        .map_err(error::ErrorInternalServerError)
-        .expect("couldn't get redis connection from pool");
-    let task_uuid = format!("requirements:{}", Uuid::new_v4());
-    conn.set::<&str, &str, bool>(task_uuid.as_str(), "waiting")
+    let root = Url::parse(&config.sync.galaxy_url).unwrap();
+    let task_uuid = tasks
+        .create("requirements", 172800)
         .map_err(error::ErrorInternalServerError)
-        .expect("Redis: Error setting key");
-    conn.expire::<&str, usize>(task_uuid.as_str(), 172800)
-        .map_err(error::ErrorInternalServerError)
-        .expect("Redis: Error setting TTL");
+        .expect("Error creating task");
     let resp = json!({ "syncing": "requirements file", "task": task_uuid });
     actix_web::rt::spawn(async move {
-        process_requirements(task_uuid.as_str(), root, data, db_pool, redis_pool).await
+        process_requirements(task_uuid.as_str(), root, data, db_pool, tasks, config).await
     });
 
     HttpResponse::Ok().json(resp)
@@ -197,13 +162,17 @@ async fn start_req_sync(
 #[api_v2_operation]
 #[get("/api/v1/")]
 async fn list_v1() -> impl Responder {
-    let resp = json!({ "roles": "/api/v1/roles/" });
+    let resp = json!({ "namespaces": "/api/v1/namespaces/", "roles": "/api/v1/roles/" });
     HttpResponse::Ok().json(resp)
 }
 
 #[api_v2_operation]
 #[get("/api/v1/roles/")]
-async fn role_retrieve(query: web::Query<HashMap<String, String>>) -> impl Responder {
+async fn role_retrieve(
+    pool: web::Data<DbPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    use crate::schema::roles;
     let empty_string = String::from("");
     let namespace = query.get("owner__username").unwrap_or(&empty_string);
     let name = query.get("name").unwrap_or(&empty_string);
@@ -211,37 +180,101 @@ async fn role_retrieve(query: web::Query<HashMap<String, String>>) -> impl Respo
         let msg = json!({"Please specify the following query params": ["owner__username", "name"]});
         return HttpResponse::BadRequest().json(msg);
     }
-    let resp = json!({ "id": format!("{namespace}/{name}") });
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let (deprecated, metadata) = roles::table
+        .select((roles::deprecated, roles::metadata))
+        .filter(roles::namespace.eq(namespace))
+        .filter(roles::name.eq(name))
+        .first::<(bool, Value)>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+        .unwrap_or((false, Value::Null));
+    // Clients install the branch when a role has no released versions.
+    let resp = json!({
+        "id": format!("{namespace}/{name}"),
+        "deprecated": deprecated,
+        "github_branch": metadata["github_branch"],
+    });
     let results = json!({ "results": [resp] });
     HttpResponse::Ok().json(results)
 }
 
 #[api_v2_operation]
 #[get("/api/v1/roles/{namespace}/{name}/versions/")]
-async fn role_version_list(path: web::Path<(String, String)>) -> impl Responder {
+async fn role_version_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    use crate::schema::roles;
     let (namespace, name) = path.into_inner();
-    let config = crate::config::Config::from_env().unwrap();
+    let descending = match query.get("order_by").map(|v| v.as_str()) {
+        None | Some("-version") => true,
+        Some("version") => false,
+        Some(_) => {
+            let msg = json!({"order_by": ["version", "-version"]});
+            return HttpResponse::BadRequest().json(msg);
+        }
+    };
+    let urls = UrlBuilder::from_request(&req);
     let path = format!("roles/{namespace}/{name}/versions");
-    let mut refs = Vec::new();
-    for entry in std::fs::read_dir(format!("content/{path}"))
+    let Ok(entries) = std::fs::read_dir(format!("content/{path}")) else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let mut conn = pool
+        .get()
         .map_err(error::ErrorInternalServerError)
-        .unwrap()
-    {
-        let version_number = entry.unwrap().file_name().into_string().unwrap();
-        let current = json!({
-            "name": version_number,
-            "source": format!("http://{}:{}/{}/{}/{}.tar.gz", config.server.host, config.server.port, path, version_number,version_number)
-        });
-        let to_check = if version_number.starts_with('v') {
-            version_number[1..version_number.len()].to_string()
-        } else {
-            version_number
-        };
-        if Version::parse(&to_check).is_ok() {
-            refs.push(current)
-        }
+        .expect("couldn't get db connection from pool");
+    let metadata = roles::table
+        .select(roles::metadata)
+        .filter(roles::namespace.eq(&namespace))
+        .filter(roles::name.eq(&name))
+        .first::<Value>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let branch = metadata
+        .as_ref()
+        .and_then(|metadata| metadata["github_branch"].as_str());
+    let source = |version: &str| urls.content(&format!("{path}/{version}/{version}.tar.gz"));
+    // Directories being deleted are hidden behind a leading dot.
+    let mut tags: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|tag| !tag.starts_with('.') && Some(tag.as_str()) != branch)
+        .filter(|tag| versions::RoleVersion::parse(tag).is_some())
+        .collect();
+    tags.sort_by(|a, b| versions::compare_roles(a, b));
+    if descending {
+        tags.reverse();
     }
-    let data = json!({ "results": refs });
+    let latest = versions::latest_role(tags.iter().map(String::as_str)).map(str::to_string);
+
+    let refs: Vec<Value> = tags
+        .iter()
+        .map(|tag| {
+            let version = versions::RoleVersion::parse(tag).unwrap();
+            json!({
+                "name": tag,
+                "version": version.to_string(),
+                "source": source(tag),
+                "is_prerelease": version.is_prerelease(),
+                "is_highest": latest.as_deref() == Some(tag.as_str()),
+            })
+        })
+        .collect();
+    let github_branch = branch
+        .filter(|branch| Path::new(&format!("content/{path}/{branch}")).is_dir())
+        .map(|branch| json!({ "name": branch, "source": source(branch) }));
+    let data = json!({
+        "count": refs.len(),
+        "github_branch": github_branch,
+        "results": refs,
+    });
     HttpResponse::Ok().json(data)
 }
 
@@ -254,31 +287,176 @@ async fn list_v2() -> impl Responder {
 
 #[api_v2_operation]
 #[get("/api/v2/collections/")]
-async fn collection_list(pool: web::Data<DbPool>) -> impl Responder {
+async fn collection_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    list_collections(&req, &pool, &query, Scope::Published)
+}
+
+/// The content a collection endpoint serves.
+#[derive(Clone, Copy)]
+pub enum Scope<'a> {
+    /// The unscoped `/api/v2/collections/` endpoints serve `published`.
+    Published,
+    Repository(&'a str),
+    /// A numbered version of a repository.
+    Snapshot(&'a str, i32),
+}
+
+/// Filters collections to those with a version tagged with the value bound
+/// after this; the caller closes the parenthesis.
+#[cfg(not(feature = "sqlite"))]
+const HAS_TAG: &str = "EXISTS (SELECT 1 FROM collection_versions cv \
+    WHERE cv.collection_id = collections.id \
+    AND (cv.metadata::jsonb -> 'tags') ? ";
+/// `HAS_TAG` through SQLite's JSON functions.
+#[cfg(feature = "sqlite")]
+const HAS_TAG: &str = "EXISTS (SELECT 1 FROM collection_versions cv, \
+    json_each(cv.metadata, '$.tags') t \
+    WHERE cv.collection_id = collections.id AND t.value = ";
+
+/// Filter on `collection_versions.id`. Live content and snapshots live in
+/// different tables, so it's spelled out in SQL to share one type.
+type InScope = SqlLiteral<Bool, UncheckedBind<SqlLiteral<Bool>, AsExprOf<i32, Integer>>>;
+
+/// Resolves a scope to a filter and the links its responses hand out, or a
+/// 404 when the repository or version doesn't exist.
+fn resolve(
+    conn: &mut DbConnection,
+    req: &HttpRequest,
+    scope: Scope,
+) -> Result<(InScope, UrlBuilder), HttpResponse> {
+    let not_found = || HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    let urls = UrlBuilder::from_request(req);
+    let (name, urls) = match scope {
+        Scope::Published => (repository::PUBLISHED, urls),
+        Scope::Repository(name) => (name, urls.in_repository(name)),
+        Scope::Snapshot(name, number) => (name, urls.in_repository_version(name, number)),
+    };
+    let Some(repository_id) = repository::find(conn, name)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    else {
+        return Err(not_found());
+    };
+    let filter = match scope {
+        Scope::Snapshot(_, number) => {
+            let Some(version_id) = repository::find_version(conn, repository_id, number)
+                .map_err(error::ErrorInternalServerError)
+                .unwrap()
+            else {
+                return Err(not_found());
+            };
+            sql::<Bool>(
+                "collection_versions.id IN (SELECT collection_version_id \
+                 FROM repository_version_content WHERE repository_version_id = ",
+            )
+            .bind::<Integer, _>(version_id)
+            .sql(")")
+        }
+        _ => sql::<Bool>(
+            "collection_versions.id IN (SELECT collection_version_id \
+             FROM repository_content WHERE repository_id = ",
+        )
+        .bind::<Integer, _>(repository_id)
+        .sql(")"),
+    };
+    Ok((filter, urls))
+}
+
+pub fn list_collections(
+    req: &HttpRequest,
+    pool: &DbPool,
+    query: &HashMap<String, String>,
+    scope: Scope,
+) -> HttpResponse {
     use crate::schema::*;
+    let pagination = match Pagination::from_query(query) {
+        Ok(pagination) => pagination,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
+    let is_deprecated = match bool_param(query, "is_deprecated") {
+        Ok(value) => value,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
     let mut conn = pool
         .get()
         .map_err(error::ErrorInternalServerError)
         .expect("couldn't get db connection from pool");
-    let results = collections::table
+    let (in_scope, urls) = match resolve(&mut conn, req, scope) {
+        Ok(resolved) => resolved,
+        Err(resp) => return resp,
+    };
+    let filtered = || {
+        let mut q = collections::table
+            .filter(
+                collections::id.eq_any(
+                    collection_versions::table
+                        .filter(in_scope.clone())
+                        .select(collection_versions::collection_id),
+                ),
+            )
+            .into_boxed();
+        if let Some(namespace) = query.get("namespace") {
+            q = q.filter(collections::namespace.eq(namespace.clone()));
+        }
+        if let Some(name) = query.get("name") {
+            q = q.filter(collections::name.eq(name.clone()));
+        }
+        if let Some(deprecated) = is_deprecated {
+            q = q.filter(collections::deprecated.eq(deprecated));
+        }
+        if let Some(tag) = query.get("tag") {
+            q = q.filter(sql::<Bool>(HAS_TAG).bind::<Text, _>(tag.clone()).sql(")"));
+        }
+        q
+    };
+    let ordered = match query.get("order_by").map(|v| v.as_str()) {
+        None | Some("namespace") => {
+            filtered().order((collections::namespace.asc(), collections::name.asc()))
+        }
+        Some("-namespace") => {
+            filtered().order((collections::namespace.desc(), collections::name.desc()))
+        }
+        Some("name") => filtered().order((collections::name.asc(), collections::namespace.asc())),
+        Some("-name") => {
+            filtered().order((collections::name.desc(), collections::namespace.desc()))
+        }
+        Some(_) => {
+            let msg = json!({"order_by": ["namespace", "-namespace", "name", "-name"]});
+            return HttpResponse::BadRequest().json(msg);
+        }
+    };
+    let count: i64 = filtered()
+        .count()
+        .get_result(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let results = ordered
         .select(Collection::as_select())
+        .limit(pagination.limit)
+        .offset(pagination.offset)
         .load(&mut conn)
         .map_err(error::ErrorInternalServerError)
         .unwrap();
 
-    let resp = json!({
-        "count": results.len(),
-        "results": results,
-    });
+    let base = urls.absolute(req.path());
+    let resp = pagination.v2_page(&base, query, count, json!(results));
     HttpResponse::Ok().json(resp)
 }
 
 #[actix_web::post("/api/v2/collections/")]
 async fn collection_post(
+    req: HttpRequest,
+    identity: Identity,
     mut payload: Multipart,
     db_pool: web::Data<DbPool>,
-    redis_pool: web::Data<Pool<RedisConnectionManager>>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
 ) -> impl Responder {
+    use crate::schema::{collection_versions, collections};
     let mut field = payload
         .try_next()
         .await
@@ -294,28 +472,74 @@ async fn collection_post(
             .unwrap();
     }
     let filename = field.content_disposition().unwrap().get_filename().unwrap();
-    let parts = filename.split('-').collect::<Vec<&str>>();
-    if parts.len() != 3 {
+    let Some((namespace, name, version)) = parse_filename(filename) else {
         return HttpResponse::BadRequest().json(
             json!({"Collection name should follow the pattern": "<namespace>-<name>-<version>.tar.gz"})
         );
-    }
-    let mut conn = redis_pool
-        .get_timeout(Duration::from_secs(1))
-        .map_err(error::ErrorInternalServerError)
-        .expect("couldn't get redis connection from pool");
-    let task_uuid = format!("upload:{}", Uuid::new_v4());
-    conn.set::<&str, &str, bool>(task_uuid.as_str(), "waiting")
+    };
+    let mut dbconn = db_pool
+        .get()
         .map_err(error::ErrorInternalServerError)
-        .expect("Redis: Error setting key");
-    conn.expire::<&str, usize>(task_uuid.as_str(), 3600)
+        .expect("couldn't get db connection from pool");
+    if !can_publish(&mut dbconn, &identity, namespace)
         .map_err(error::ErrorInternalServerError)
-        .expect("Redis: Error setting TTL");
-    let resp = json!({ "task": task_uuid });
+        .unwrap()
+    {
+        let msg = format!("You are not allowed to publish to the '{namespace}' namespace.");
+        return HttpResponse::Forbidden().json(json!({ "detail": msg }));
+    }
+    let exists = diesel::select(diesel::dsl::exists(
+        collections::table
+            .inner_join(collection_versions::table)
+            .filter(collections::namespace.eq(namespace))
+            .filter(collections::name.eq(name))
+            .filter(collection_versions::version.eq(version)),
+    ))
+    .get_result::<bool>(&mut dbconn)
+    .map_err(error::ErrorInternalServerError)
+    .unwrap();
+    if exists {
+        let msg = format!("{namespace}.{name}:{version} already exists.");
+        return HttpResponse::Conflict().json(json!({ "detail": msg }));
+    }
+    // The limit applies while the upload streams in, so an oversized one is
+    // never buffered; base64 bodies are a third larger than the tarball.
+    let limit = config.content.max_upload_size;
+    let limit = match field.headers().get("content-transfer-encoding") {
+        Some(encoded) if encoded == "base64" => limit.saturating_add(limit.div_ceil(3) + 4),
+        _ => limit,
+    };
     let mut data = Vec::new();
-    while let Ok(Some(chunk)) = field.try_next().await {
-        data.extend_from_slice(&chunk);
+    loop {
+        match field.try_next().await {
+            Ok(Some(chunk)) => {
+                if (data.len() + chunk.len()) as u64 > limit {
+                    let msg = format!(
+                        "The archive is over the {} byte limit.",
+                        config.content.max_upload_size
+                    );
+                    return HttpResponse::PayloadTooLarge().json(json!({ "detail": msg }));
+                }
+                data.extend_from_slice(&chunk);
+            }
+            Ok(None) => break,
+            Err(err) => {
+                let msg = format!("Failed to read the upload: {err}.");
+                return HttpResponse::BadRequest().json(json!({ "detail": msg }));
+            }
+        }
     }
+    let task_uuid = tasks
+        .create("upload", 3600)
+        .map_err(error::ErrorInternalServerError)
+        .expect("Error creating task");
+    let resp = json!({ "task": task_uuid });
+    let urls = UrlBuilder::from_request(&req);
+    let repository = if config.content.require_approval {
+        repository::STAGING
+    } else {
+        repository::PUBLISHED
+    };
     actix_web::rt::spawn(async move {
         import_task(
             task_uuid.as_str(),
@@ -323,7 +547,11 @@ async fn collection_post(
             field.headers(),
             data,
             db_pool,
-            redis_pool,
+            tasks,
+            config,
+            urls,
+            identity.username,
+            repository,
         )
         .await
     });
@@ -331,142 +559,565 @@ async fn collection_post(
     HttpResponse::Ok().json(resp)
 }
 
+#[derive(Deserialize, Apiv2Schema)]
+pub struct DirectoryImportRequest {
+    /// Directory on the server holding `<namespace>-<name>-<version>.tar.gz` files.
+    path: String,
+}
+
+/// Imports every collection tarball in a directory on the server, like
+/// uploads; follow it at `/api/v2/collection-imports/{task}/`.
 #[api_v2_operation]
-#[get("/api/v2/collection-imports/{task_id}/")]
-async fn collection_import(
-    pool: web::Data<Pool<RedisConnectionManager>>,
-    path: web::Path<String>,
+#[post("/api/v3/imports/collections/")]
+async fn collection_directory_import(
+    req: HttpRequest,
+    identity: Identity,
+    db_pool: web::Data<DbPool>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
+    body: web::Json<DirectoryImportRequest>,
 ) -> impl Responder {
-    let mut conn = pool
-        .get_timeout(Duration::from_secs(1))
+    if !identity.is_superuser {
+        return HttpResponse::Forbidden()
+            .json(json!({"detail": "You do not have permission to perform this action."}));
+    }
+    let dir = PathBuf::from(&body.path);
+    if !dir.is_dir() {
+        return HttpResponse::BadRequest()
+            .json(json!({"detail": format!("{} is not a directory.", body.path)}));
+    }
+    let task_uuid = tasks
+        .create("upload", 172800)
         .map_err(error::ErrorInternalServerError)
-        .expect("couldn't get redis connection from pool");
-    let task_id = path.into_inner();
-    let value = conn
-        .get(task_id.as_str())
+        .expect("Error creating task");
+    let resp = json!({ "task": task_uuid });
+    let urls = UrlBuilder::from_request(&req);
+    let repository = if config.content.require_approval {
+        repository::STAGING
+    } else {
+        repository::PUBLISHED
+    };
+    actix_web::rt::spawn(async move {
+        import_directory_task(
+            task_uuid.as_str(),
+            dir,
+            db_pool,
+            tasks,
+            config,
+            urls,
+            identity.username,
+            repository,
+        )
+        .await
+    });
+    HttpResponse::Ok().json(resp)
+}
+
+/// Imports a role from a tarball sent as `file` with its `version`, or from
+/// the git `repository` path or URL given, tagging versions from its tags.
+#[actix_web::post("/api/v1/imports/")]
+async fn role_import(
+    identity: Identity,
+    mut payload: Multipart,
+    db_pool: web::Data<DbPool>,
+    tasks: web::Data<Tasks>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let mut fields = HashMap::new();
+    let mut file = None;
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let Some(name) = field.name().map(str::to_string) else {
+            continue;
+        };
+        let mut data = Vec::new();
+        while let Ok(Some(chunk)) = field.try_next().await {
+            data.extend_from_slice(&chunk);
+        }
+        if name == "file" {
+            file = Some(data);
+        } else {
+            fields.insert(name, String::from_utf8_lossy(&data).trim().to_string());
+        }
+    }
+    let Some(namespace) = fields.remove("namespace").filter(|ns| !ns.is_empty()) else {
+        return HttpResponse::BadRequest().json(json!({"namespace": "This field is required."}));
+    };
+    let source = match (file, fields.remove("repository"), fields.remove("version")) {
+        (Some(data), None, Some(version)) => RoleSource::Tarball { data, version },
+        (Some(_), None, None) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"version": "This field is required with a file."}))
+        }
+        (None, Some(repository), _) => {
+            let hosts = &config.content.role_import_hosts;
+            if let Err(msg) = check_git_source(&repository, hosts, identity.is_superuser) {
+                return HttpResponse::BadRequest().json(json!({ "repository": msg }));
+            }
+            RoleSource::Git(repository)
+        }
+        _ => {
+            return HttpResponse::BadRequest()
+                .json(json!({"detail": "Send either a role tarball as file or a git repository."}))
+        }
+    };
+    let mut dbconn = db_pool
+        .get()
         .map_err(error::ErrorInternalServerError)
-        .expect("Error getting key");
-    let state: String = FromRedisValue::from_redis_value(&value)
+        .expect("couldn't get db connection from pool");
+    if !can_publish(&mut dbconn, &identity, &namespace)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    {
+        let msg = format!("You are not allowed to publish to the '{namespace}' namespace.");
+        return HttpResponse::Forbidden().json(json!({ "detail": msg }));
+    }
+    let task_uuid = tasks
+        .create("role-import", 3600)
+        .map_err(error::ErrorInternalServerError)
+        .expect("Error creating task");
+    let resp = json!({ "task": task_uuid });
+    let import = RoleImport {
+        namespace,
+        name: fields.remove("name").filter(|name| !name.is_empty()),
+        source,
+        uploaded_by: identity.username,
+    };
+    actix_web::rt::spawn(async move {
+        role_import_task(task_uuid.as_str(), import, db_pool, tasks, config).await
+    });
+
+    HttpResponse::Ok().json(resp)
+}
+
+#[api_v2_operation]
+#[get("/api/v1/imports/{task_id}/")]
+async fn role_import_status(tasks: web::Data<Tasks>, path: web::Path<String>) -> impl Responder {
+    import_status(&tasks, path.into_inner())
+}
+
+#[api_v2_operation]
+#[get("/api/v2/collection-imports/{task_id}/")]
+async fn collection_import(tasks: web::Data<Tasks>, path: web::Path<String>) -> impl Responder {
+    import_status(&tasks, path.into_inner())
+}
+
+/// State of an upload or import task, with its messages when it failed and
+/// the per-file report of directory imports.
+pub(super) fn import_status(tasks: &Tasks, task_id: String) -> HttpResponse {
+    let state = tasks
+        .state(&task_id)
         .map_err(error::ErrorInternalServerError)
-        .expect("Redis: Error getting value");
+        .expect("Error getting task state");
+    let Some(state) = state else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
     let mut resp = json!({"state": state});
     if state == "completed" {
         resp = json!({"state": state, "finished_at": "now"});
     }
+    if state == "failed" {
+        let messages = tasks
+            .messages(&task_id)
+            .map_err(error::ErrorInternalServerError)
+            .expect("Error getting task messages");
+        resp = json!({
+            "state": state,
+            "finished_at": "now",
+            "error": {"code": "import_failed", "description": messages.join(" ")},
+            "messages": messages
+                .iter()
+                .map(|message| json!({"level": "ERROR", "message": message, "time": "now"}))
+                .collect::<Vec<_>>(),
+        });
+    }
+    let report = tasks
+        .report(&task_id)
+        .map_err(error::ErrorInternalServerError)
+        .expect("Error getting task report");
+    if let Some(report) = report {
+        resp["report"] = report;
+    }
     HttpResponse::Ok().json(resp)
 }
 
 #[api_v2_operation]
 #[get("/api/v2/collections/{namespace}/{name}/")]
 async fn collection_retrieve(
+    req: HttpRequest,
     pool: web::Data<DbPool>,
     path: web::Path<(String, String)>,
 ) -> impl Responder {
+    let (namespace, name) = path.into_inner();
+    retrieve_collection(&req, &pool, &namespace, &name, Scope::Published)
+}
+
+/// A collection is only visible in a repository holding one of its versions.
+pub fn retrieve_collection(
+    req: &HttpRequest,
+    pool: &DbPool,
+    namespace: &str,
+    name: &str,
+    scope: Scope,
+) -> HttpResponse {
     use crate::schema::*;
     let mut conn = pool
         .get()
         .map_err(error::ErrorInternalServerError)
         .expect("couldn't get db connection from pool");
-    let config = crate::config::Config::from_env().unwrap();
-    let (namespace, name) = path.into_inner();
-    let results = collections::table
-        .inner_join(collection_versions::table)
-        .select((collections::id, (collection_versions::version)))
+    let (in_scope, urls) = match resolve(&mut conn, req, scope) {
+        Ok(resolved) => resolved,
+        Err(resp) => return resp,
+    };
+    let collection = collections::table
+        .select(Collection::as_select())
         .filter(
             collections::namespace
-                .eq(&namespace)
-                .and(collections::name.eq(&name)),
+                .eq(namespace)
+                .and(collections::name.eq(name)),
         )
-        .load::<(i32, String)>(&mut conn)
-        .map_err(error::ErrorNotFound)
+        .first::<Collection>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
         .unwrap();
-    let (collection_id, latest_version) = results
-        .iter()
-        .max_by(|x, y| {
-            Version::parse(&x.1)
-                .unwrap()
-                .cmp(&Version::parse(&y.1).unwrap())
-        })
+    let Some(collection) = collection else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let versions = collection_versions::table
+        .select(collection_versions::version)
+        .filter(collection_versions::collection_id.eq(collection.id))
+        .filter(in_scope)
+        .load::<String>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
         .unwrap();
+    if versions.is_empty() {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    }
+    let latest_version = versions::latest(versions.iter().map(String::as_str)).map(|version| {
+        json!({
+            "version": version,
+            "href": urls.collection_version(namespace, name, version)
+        })
+    });
 
-    let href = format!(
-        "http://{}:{}/api/v2/collections/{}/{}/",
-        config.server.host, config.server.port, namespace, name
-    );
+    let href = urls.collection(namespace, name);
     let versions_url = format!("{href}versions/");
-    let latest_href = format!("{versions_url}{latest_version}/");
     let resp = json!({
         "href": href,
-        "id": collection_id,
+        "id": collection.id,
         "name": name,
-        "namespace": {"name": namespace},
+        "namespace": namespace_summary(&mut conn, &urls, namespace),
+        "deprecated": collection.deprecated,
         "versions_url": versions_url,
-        "latest_version":{"version": latest_version, "href": latest_href}
+        "latest_version": latest_version
     });
     HttpResponse::Ok().json(resp)
 }
 
+#[derive(Deserialize, Apiv2Schema)]
+pub struct DeprecationRequest {
+    deprecated: bool,
+}
+
+fn deprecation_denied(
+    conn: &mut DbConnection,
+    identity: &Identity,
+    namespace: &str,
+) -> Option<HttpResponse> {
+    let allowed = can_publish(conn, identity, namespace)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    (!allowed).then(|| {
+        HttpResponse::Forbidden().json(json!({
+            "detail": format!("You are not allowed to change content in the '{namespace}' namespace.")
+        }))
+    })
+}
+
+#[api_v2_operation]
+#[patch("/api/v2/collections/{namespace}/{name}/")]
+async fn collection_update(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    body: web::Json<DeprecationRequest>,
+) -> impl Responder {
+    use crate::schema::collections;
+    let (namespace, name) = path.into_inner();
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    if let Some(resp) = deprecation_denied(&mut conn, &identity, &namespace) {
+        return resp;
+    }
+    let collection = conn
+        .transaction(|conn| {
+            let collection = diesel::update(
+                collections::table
+                    .filter(collections::namespace.eq(&namespace))
+                    .filter(collections::name.eq(&name)),
+            )
+            .set(collections::deprecated.eq(body.deprecated))
+            .returning(Collection::as_returning())
+            .get_result::<Collection>(conn)
+            .optional()?;
+            if collection.is_some() {
+                audit::record(
+                    conn,
+                    &identity,
+                    if body.deprecated {
+                        "deprecate"
+                    } else {
+                        "undeprecate"
+                    },
+                    &format!("collection:{namespace}.{name}"),
+                    &json!({}),
+                )?;
+            }
+            QueryResult::Ok(collection)
+        })
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(collection) = collection else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    HttpResponse::Ok().json(collection)
+}
+
+#[api_v2_operation]
+#[patch("/api/v1/roles/{namespace}/{name}/")]
+async fn role_update(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    body: web::Json<DeprecationRequest>,
+) -> impl Responder {
+    use crate::schema::roles;
+    let (namespace, name) = path.into_inner();
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    if let Some(resp) = deprecation_denied(&mut conn, &identity, &namespace) {
+        return resp;
+    }
+    let role = conn
+        .transaction(|conn| {
+            let role = diesel::update(
+                roles::table
+                    .filter(roles::namespace.eq(&namespace))
+                    .filter(roles::name.eq(&name)),
+            )
+            .set(roles::deprecated.eq(body.deprecated))
+            .returning((roles::id, roles::deprecated))
+            .get_result::<(i32, bool)>(conn)
+            .optional()?;
+            if role.is_some() {
+                audit::record(
+                    conn,
+                    &identity,
+                    if body.deprecated {
+                        "deprecate"
+                    } else {
+                        "undeprecate"
+                    },
+                    &format!("role:{namespace}.{name}"),
+                    &json!({}),
+                )?;
+            }
+            QueryResult::Ok(role)
+        })
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some((id, deprecated)) = role else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    HttpResponse::Ok().json(json!({
+        "id": id,
+        "namespace": namespace,
+        "name": name,
+        "deprecated": deprecated,
+    }))
+}
+
 #[api_v2_operation]
 #[get("/api/v2/collections/{namespace}/{name}/versions/")]
-async fn collection_version_list(path: web::Path<(String, String)>) -> impl Responder {
-    let config = crate::config::Config::from_env().unwrap();
+async fn collection_version_list(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
     let (namespace, name) = path.into_inner();
-    let path = format!("collections/{namespace}/{name}/versions");
-    let mut refs = Vec::new();
-    for entry in std::fs::read_dir(format!("content/{path}"))
+    list_collection_versions(&req, &pool, &query, &namespace, &name, Scope::Published)
+}
+
+pub fn list_collection_versions(
+    req: &HttpRequest,
+    pool: &DbPool,
+    query: &HashMap<String, String>,
+    namespace: &str,
+    name: &str,
+    scope: Scope,
+) -> HttpResponse {
+    use crate::schema::*;
+    let pagination = match Pagination::from_query(query) {
+        Ok(pagination) => pagination,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
+    let descending = match query.get("order_by").map(|v| v.as_str()) {
+        None | Some("-version") => true,
+        Some("version") => false,
+        Some(_) => {
+            let msg = json!({"order_by": ["version", "-version"]});
+            return HttpResponse::BadRequest().json(msg);
+        }
+    };
+    let mut conn = pool
+        .get()
         .map_err(error::ErrorInternalServerError)
-        .unwrap()
-    {
-        let version_number = entry.unwrap().file_name().into_string().unwrap();
-        refs.push(json!({
-            "version": version_number,
-            "href": format!("http://{}:{}/api/v2/{}/{}/", config.server.host, config.server.port, path, version_number)
-        }))
+        .expect("couldn't get db connection from pool");
+    let (in_scope, urls) = match resolve(&mut conn, req, scope) {
+        Ok(resolved) => resolved,
+        Err(resp) => return resp,
+    };
+    let collection_id = collections::table
+        .select(collections::id)
+        .filter(
+            collections::namespace
+                .eq(namespace)
+                .and(collections::name.eq(name)),
+        )
+        .first::<i32>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(collection_id) = collection_id else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let in_collection = || {
+        collection_versions::table
+            .filter(collection_versions::collection_id.eq(collection_id))
+            .filter(in_scope.clone())
+    };
+    let count: i64 = in_collection()
+        .count()
+        .get_result(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    // A release sorts above its prereleases, which have a non-empty key.
+    let numbers = in_collection().select(collection_versions::version);
+    let page = if descending {
+        numbers
+            .order((
+                collection_versions::version_major.desc(),
+                collection_versions::version_minor.desc(),
+                collection_versions::version_patch.desc(),
+                collection_versions::version_prerelease_key.eq("").desc(),
+                collection_versions::version_prerelease_key.desc(),
+            ))
+            .limit(pagination.limit)
+            .offset(pagination.offset)
+            .load::<String>(&mut conn)
+    } else {
+        numbers
+            .order((
+                collection_versions::version_major.asc(),
+                collection_versions::version_minor.asc(),
+                collection_versions::version_patch.asc(),
+                collection_versions::version_prerelease_key.eq("").asc(),
+                collection_versions::version_prerelease_key.asc(),
+            ))
+            .limit(pagination.limit)
+            .offset(pagination.offset)
+            .load::<String>(&mut conn)
     }
-    let data = json!({ "results": refs });
+    .map_err(error::ErrorInternalServerError)
+    .unwrap();
+    // Stable releases win over prereleases, matching `versions::latest`.
+    let latest = in_collection()
+        .select(collection_versions::version)
+        .order((
+            collection_versions::version_prerelease_key.eq("").desc(),
+            collection_versions::version_major.desc(),
+            collection_versions::version_minor.desc(),
+            collection_versions::version_patch.desc(),
+            collection_versions::version_prerelease_key.desc(),
+        ))
+        .first::<String>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+
+    let refs: Vec<Value> = page
+        .iter()
+        .map(|version_number| {
+            json!({
+                "version": version_number,
+                "href": urls.collection_version(namespace, name, version_number),
+                "is_prerelease": versions::is_prerelease(version_number),
+                "is_highest": latest.as_deref() == Some(version_number.as_str()),
+            })
+        })
+        .collect();
+    let base = urls.absolute(req.path());
+    let data = pagination.v2_page(&base, query, count, Value::Array(refs));
     HttpResponse::Ok().json(data)
 }
 
 #[api_v2_operation]
 #[get("/api/v2/collections/{namespace}/{name}/versions/{version}/")]
 async fn collection_version_retrieve(
+    req: HttpRequest,
     pool: web::Data<DbPool>,
     path: web::Path<(String, String, String)>,
 ) -> impl Responder {
+    let (namespace, name, version) = path.into_inner();
+    retrieve_collection_version(&req, &pool, &namespace, &name, &version, Scope::Published)
+}
+
+pub fn retrieve_collection_version(
+    req: &HttpRequest,
+    pool: &DbPool,
+    namespace: &str,
+    name: &str,
+    version: &str,
+    scope: Scope,
+) -> HttpResponse {
     use crate::schema::*;
     let mut conn = pool
         .get()
         .map_err(error::ErrorInternalServerError)
         .expect("couldn't get db connection from pool");
-    let config = crate::config::Config::from_env().unwrap();
-    let (namespace, name, version) = path.into_inner();
+    let (in_scope, urls) = match resolve(&mut conn, req, scope) {
+        Ok(resolved) => resolved,
+        Err(resp) => return resp,
+    };
     let result = collections::table
         .inner_join(collection_versions::table)
-        .select(collection_versions::all_columns)
+        .select(models::CollectionVersion::as_select())
         .filter(
             collections::namespace
-                .eq(&namespace)
-                .and(collections::name.eq(&name))
-                .and(collection_versions::version.eq(&version)),
+                .eq(namespace)
+                .and(collections::name.eq(name))
+                .and(collection_versions::version.eq(version)),
         )
+        .filter(in_scope)
         .load::<models::CollectionVersion>(&mut conn)
         .map_err(error::ErrorInternalServerError)
         .unwrap();
-    let current_version = result.first().unwrap();
-    let collection_href = format!(
-        "http://{}:{}/api/v2/collections/{}/{}/",
-        config.server.host, config.server.port, namespace, name
-    );
-    let version_url = format!("{collection_href}versions/{version}/");
-    let download_url = format!(
-        "http://{}:{}/content/collections/{}/{}/versions/{}/{}",
-        config.server.host,
-        config.server.port,
+    let Some(current_version) = result.first() else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let collection_href = urls.collection(namespace, name);
+    let version_url = urls.collection_version(namespace, name, version);
+    let download_url = urls.content(&format!(
+        "collections/{}/{}/versions/{}/{}",
         namespace,
         name,
         version,
         current_version.artifact["filename"].as_str().unwrap()
-    );
+    ));
     let resp = json!({
         "artifact": current_version.artifact,
         "collection": {"name": name, "id": current_version.collection_id, "href": collection_href},
@@ -474,8 +1125,11 @@ async fn collection_version_retrieve(
         "href": version_url,
         "id": current_version.id,
         "metadata": current_version.metadata,
-        "namespace": {"name": namespace},
+        "namespace": namespace_summary(&mut conn, &urls, namespace),
         "version": version,
+        "uploaded_by": current_version.uploaded_by,
+        "created_at": current_version.created_at,
+        "signatures": signatures_json(&mut conn, current_version.id),
     });
     HttpResponse::Ok().json(resp)
 }
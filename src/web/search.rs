@@ -0,0 +1,360 @@
+use super::filters::{bool_param, keywords_query, list_param};
+use super::pagination::Pagination;
+use super::urls::UrlBuilder;
+use crate::db_utils::DbConnection;
+use crate::repository;
+use actix_web::{error, HttpRequest, HttpResponse, Responder};
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::{Bool, Float4, Text};
+use paperclip::actix::{api_v2_operation, get, web};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+// Highest version of its collection within the repository: stable releases
+// win over prereleases, matching `versions::latest`.
+const IS_HIGHEST: &str = "NOT EXISTS (SELECT 1 FROM collection_versions o \
+    JOIN repository_content orc ON orc.collection_version_id = o.id \
+    WHERE o.collection_id = collection_versions.collection_id \
+    AND orc.repository_id = repository_content.repository_id \
+    AND (o.version_prerelease_key = '', o.version_major, o.version_minor, o.version_patch, \
+    o.version_prerelease_key) \
+    > (collection_versions.version_prerelease_key = '', collection_versions.version_major, \
+    collection_versions.version_minor, collection_versions.version_patch, \
+    collection_versions.version_prerelease_key))";
+
+const IS_SIGNED: &str = "EXISTS (SELECT 1 FROM collection_signatures \
+    WHERE collection_signatures.collection_version_id = collection_versions.id)";
+
+// Filters and ranks as the SQL before and after their bound value: a JSON
+// array of tags, or what `keywords_query` returns.
+#[cfg(not(feature = "sqlite"))]
+mod dialect {
+    pub const COLLECTION_HAS_TAGS: (&str, &str) = (
+        "((collection_versions.metadata::jsonb -> 'tags') @> ",
+        "::jsonb)",
+    );
+    pub const COLLECTION_MATCHES: (&str, &str) = (
+        "collection_versions.search_vector @@ to_tsquery('simple', ",
+        ")",
+    );
+    pub const COLLECTION_RANK: (&str, &str) = (
+        "ts_rank(collection_versions.search_vector, to_tsquery('simple', ",
+        "))",
+    );
+    pub const ROLE_HAS_TAGS: (&str, &str) = ("((roles.tags::jsonb) @> ", "::jsonb)");
+    pub const ROLE_MATCHES: (&str, &str) = ("roles.search_vector @@ to_tsquery('simple', ", ")");
+    pub const ROLE_RANK: (&str, &str) =
+        ("ts_rank(roles.search_vector, to_tsquery('simple', ", "))");
+}
+
+// SQLite has no full text search without an extension, so every term must
+// appear somewhere in the fields Postgres indexes, and results rank by how
+// many terms the namespace and name hold.
+#[cfg(feature = "sqlite")]
+mod dialect {
+    pub const COLLECTION_HAS_TAGS: (&str, &str) = (
+        "NOT EXISTS (SELECT 1 FROM json_each(",
+        ") t WHERE t.value NOT IN \
+         (SELECT value FROM json_each(collection_versions.metadata, '$.tags')))",
+    );
+    pub const COLLECTION_MATCHES: (&str, &str) = (
+        "NOT EXISTS (SELECT 1 FROM json_each(",
+        ") t WHERE instr(lower(\
+         coalesce(json_extract(collection_versions.metadata, '$.namespace'), '') || ' ' || \
+         coalesce(json_extract(collection_versions.metadata, '$.name'), '') || ' ' || \
+         coalesce(json_extract(collection_versions.metadata, '$.tags'), '') || ' ' || \
+         coalesce(json_extract(collection_versions.metadata, '$.description'), '') || ' ' || \
+         collection_versions.readme), t.value) = 0)",
+    );
+    pub const COLLECTION_RANK: (&str, &str) = (
+        "(SELECT count(*) FROM json_each(",
+        ") t WHERE instr(lower(\
+         coalesce(json_extract(collection_versions.metadata, '$.namespace'), '') || ' ' || \
+         coalesce(json_extract(collection_versions.metadata, '$.name'), '')), t.value) > 0)",
+    );
+    pub const ROLE_HAS_TAGS: (&str, &str) = (
+        "NOT EXISTS (SELECT 1 FROM json_each(",
+        ") t WHERE t.value NOT IN (SELECT value FROM json_each(roles.tags)))",
+    );
+    pub const ROLE_MATCHES: (&str, &str) = (
+        "NOT EXISTS (SELECT 1 FROM json_each(",
+        ") t WHERE instr(lower(roles.namespace || ' ' || roles.name || ' ' || \
+         roles.tags || ' ' || roles.description || ' ' || roles.readme), t.value) = 0)",
+    );
+    pub const ROLE_RANK: (&str, &str) = (
+        "(SELECT count(*) FROM json_each(",
+        ") t WHERE instr(lower(roles.namespace || ' ' || roles.name), t.value) > 0)",
+    );
+}
+
+#[api_v2_operation]
+#[get("/api/v3/plugin/ansible/search/collection-versions/")]
+async fn collection_version_search(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    use crate::schema::*;
+    let pagination = match Pagination::from_limit_offset(&query) {
+        Ok(pagination) => pagination,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
+    let (is_deprecated, is_highest, is_signed) = match (
+        bool_param(&query, "is_deprecated"),
+        bool_param(&query, "is_highest"),
+        bool_param(&query, "is_signed"),
+    ) {
+        (Ok(deprecated), Ok(highest), Ok(signed)) => (deprecated, highest, signed),
+        (Err(msg), _, _) | (_, Err(msg), _) | (_, _, Err(msg)) => {
+            return HttpResponse::BadRequest().json(msg)
+        }
+    };
+    let keywords = query
+        .get("keywords")
+        .or_else(|| query.get("q"))
+        .and_then(|k| keywords_query(k));
+    let tags = list_param(&query, "tags");
+    let repository_names = list_param(&query, "repository_name");
+    // Galaxy NG hides staging and rejected content from mirrors this way.
+    let hide_unapproved =
+        query.get("repository_label").map(String::as_str) == Some("!hide_from_search");
+    let filtered = || {
+        let mut q = collection_versions::table
+            .inner_join(collections::table)
+            .inner_join(repository_content::table.inner_join(repositories::table))
+            .into_boxed();
+        if !repository_names.is_empty() {
+            q = q.filter(repositories::name.eq_any(repository_names.clone()));
+        }
+        if hide_unapproved {
+            q = q.filter(repositories::name.eq(repository::PUBLISHED));
+        }
+        if let Some(namespace) = query.get("namespace") {
+            q = q.filter(collections::namespace.eq(namespace.clone()));
+        }
+        if let Some(name) = query.get("name") {
+            q = q.filter(collections::name.eq(name.clone()));
+        }
+        if let Some(version) = query.get("version") {
+            q = q.filter(collection_versions::version.eq(version.clone()));
+        }
+        if let Some(deprecated) = is_deprecated {
+            q = q.filter(collections::deprecated.eq(deprecated));
+        }
+        if let Some(highest) = is_highest {
+            q = q.filter(sql::<Bool>(IS_HIGHEST).eq(highest));
+        }
+        if let Some(signed) = is_signed {
+            q = q.filter(sql::<Bool>(IS_SIGNED).eq(signed));
+        }
+        if !tags.is_empty() {
+            let (before, after) = dialect::COLLECTION_HAS_TAGS;
+            q = q.filter(
+                sql::<Bool>(before)
+                    .bind::<Text, _>(json!(tags).to_string())
+                    .sql(after),
+            );
+        }
+        if let Some(keywords) = &keywords {
+            let (before, after) = dialect::COLLECTION_MATCHES;
+            q = q.filter(
+                sql::<Bool>(before)
+                    .bind::<Text, _>(keywords.clone())
+                    .sql(after),
+            );
+        }
+        q
+    };
+    let mut ordered = filtered();
+    if let Some(keywords) = &keywords {
+        let (before, after) = dialect::COLLECTION_RANK;
+        ordered = ordered.order(
+            sql::<Float4>(before)
+                .bind::<Text, _>(keywords.clone())
+                .sql(after)
+                .desc(),
+        );
+    }
+    let ordered = ordered.then_order_by((
+        collections::namespace.asc(),
+        collections::name.asc(),
+        collection_versions::version_major.desc(),
+        collection_versions::version_minor.desc(),
+        collection_versions::version_patch.desc(),
+        collection_versions::version_prerelease_key.eq("").desc(),
+        collection_versions::version_prerelease_key.desc(),
+        repositories::name.asc(),
+    ));
+
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let count: i64 = filtered()
+        .count()
+        .get_result(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let results = ordered
+        .select((
+            repositories::name,
+            collections::namespace,
+            collections::name,
+            collections::deprecated,
+            collection_versions::version,
+            collection_versions::metadata,
+            sql::<Bool>(IS_HIGHEST),
+            sql::<Bool>(IS_SIGNED),
+        ))
+        .limit(pagination.limit)
+        .offset(pagination.offset)
+        .load::<(String, String, String, bool, String, Value, bool, bool)>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+
+    let urls = UrlBuilder::from_request(&req);
+    let data: Vec<Value> = results
+        .iter()
+        .map(
+            |(repository, namespace, name, deprecated, version, metadata, highest, signed)| {
+                let urls = urls.clone().in_repository(repository);
+                json!({
+                    "repository": {"name": repository},
+                    "collection_version": {
+                        "namespace": namespace,
+                        "name": name,
+                        "version": version,
+                        "description": metadata["description"].as_str().unwrap_or(""),
+                        "tags": metadata.get("tags").cloned().unwrap_or(json!([])),
+                        "dependencies": metadata.get("dependencies").cloned().unwrap_or(json!({})),
+                        "href": urls.collection_version(namespace, name, version),
+                    },
+                    "is_highest": highest,
+                    "is_deprecated": deprecated,
+                    "is_signed": signed,
+                })
+            },
+        )
+        .collect();
+    let resp = pagination.v3_page(
+        &urls.relative(req.path()),
+        &query,
+        count,
+        Value::Array(data),
+    );
+    HttpResponse::Ok().json(resp)
+}
+
+#[api_v2_operation]
+#[get("/api/v1/search/roles/")]
+async fn role_search(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    use crate::schema::*;
+    let pagination = match Pagination::from_query(&query) {
+        Ok(pagination) => pagination,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
+    // `ansible-galaxy role search` sends the `*_autocomplete` variants.
+    let keywords = query
+        .get("keywords")
+        .or_else(|| query.get("autocomplete"))
+        .and_then(|k| keywords_query(k));
+    let mut tags = list_param(&query, "tags");
+    tags.extend(list_param(&query, "tags_autocomplete"));
+    let namespace = query
+        .get("namespace")
+        .or_else(|| query.get("owner__username"))
+        .or_else(|| query.get("username_autocomplete"));
+    let is_deprecated = match bool_param(&query, "is_deprecated") {
+        Ok(value) => value,
+        Err(msg) => return HttpResponse::BadRequest().json(msg),
+    };
+    let filtered = || {
+        let mut q = roles::table.into_boxed();
+        if let Some(deprecated) = is_deprecated {
+            q = q.filter(roles::deprecated.eq(deprecated));
+        }
+        if let Some(namespace) = namespace {
+            q = q.filter(roles::namespace.eq(namespace.clone()));
+        }
+        if let Some(name) = query.get("name") {
+            q = q.filter(roles::name.eq(name.clone()));
+        }
+        if !tags.is_empty() {
+            let (before, after) = dialect::ROLE_HAS_TAGS;
+            q = q.filter(
+                sql::<Bool>(before)
+                    .bind::<Text, _>(json!(tags).to_string())
+                    .sql(after),
+            );
+        }
+        if let Some(keywords) = &keywords {
+            let (before, after) = dialect::ROLE_MATCHES;
+            q = q.filter(
+                sql::<Bool>(before)
+                    .bind::<Text, _>(keywords.clone())
+                    .sql(after),
+            );
+        }
+        q
+    };
+    let mut ordered = filtered();
+    if let Some(keywords) = &keywords {
+        let (before, after) = dialect::ROLE_RANK;
+        ordered = ordered.order(
+            sql::<Float4>(before)
+                .bind::<Text, _>(keywords.clone())
+                .sql(after)
+                .desc(),
+        );
+    }
+    let ordered = ordered.then_order_by((roles::namespace.asc(), roles::name.asc()));
+
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    let count: i64 = filtered()
+        .count()
+        .get_result(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let results = ordered
+        .select((
+            roles::id,
+            roles::namespace,
+            roles::name,
+            roles::description,
+            roles::tags,
+            roles::deprecated,
+        ))
+        .limit(pagination.limit)
+        .offset(pagination.offset)
+        .load::<(i32, String, String, String, Value, bool)>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+
+    let data: Vec<Value> = results
+        .iter()
+        .map(|(id, namespace, name, description, tags, deprecated)| {
+            json!({
+                "id": id,
+                "name": name,
+                "username": namespace,
+                "description": description,
+                "deprecated": deprecated,
+                "summary_fields": {"namespace": {"name": namespace}, "tags": tags},
+            })
+        })
+        .collect();
+    let base = UrlBuilder::from_request(&req).absolute(req.path());
+    let resp = pagination.v2_page(&base, &query, count, Value::Array(data));
+    HttpResponse::Ok().json(resp)
+}
@@ -1,81 +1,216 @@
+use super::admin::*;
+use super::auth::authenticate;
+use super::content::*;
+use super::deletion::*;
+use super::exports::*;
+use super::namespaces::*;
+use super::repositories::*;
 use super::routes::*;
+use super::search::*;
+use super::signatures::*;
+use super::tls;
+use crate::config::Config;
+use crate::db_utils::{pool_builder, DbConnection};
+use crate::tasks::Tasks;
 use actix_web::{
-    middleware::{Logger, NormalizePath, TrailingSlash},
-    web::Data,
-    App, HttpServer,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, Uri},
+    middleware::{from_fn, Logger, Next, NormalizePath, TrailingSlash},
+    web::{self, Data},
+    App, Error, HttpRequest, HttpResponse, HttpServer,
 };
-use diesel::{
-    r2d2::{ConnectionManager, Pool},
-    PgConnection,
-};
-use dotenv::dotenv;
+use diesel::r2d2::ConnectionManager;
 use log::info;
 use paperclip::actix::OpenApiExt;
-use r2d2_redis::RedisConnectionManager;
-use std::time::Duration;
 
-const CACHE_POOL_MAX_OPEN: u32 = 16;
-const CACHE_POOL_MIN_IDLE: u32 = 8;
-const CACHE_POOL_EXPIRE_SECONDS: u64 = 60;
+/// Lets groot be mounted under `SERVER.PATH_PREFIX` by routing `/galaxy/api/...`
+/// as if it were `/api/...`.
+async fn strip_path_prefix(
+    prefix: String,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let stripped = req
+        .path()
+        .strip_prefix(prefix.as_str())
+        .filter(|rest| !prefix.is_empty() && (rest.is_empty() || rest.starts_with('/')))
+        .map(|rest| if rest.is_empty() { "/" } else { rest }.to_string());
+    if let Some(path) = stripped {
+        let path_and_query = match req.query_string() {
+            "" => path,
+            query => format!("{path}?{query}"),
+        };
+        let mut parts = req.head().uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse().unwrap());
+        let uri = Uri::from_parts(parts).unwrap();
+        req.match_info_mut().get_mut().update(&uri);
+        req.head_mut().uri = uri;
+    }
+    next.call(req).await
+}
 
-pub async fn start_actix_server() {
+/// Sends plain HTTP requests to the same path on the HTTPS listener, keeping
+/// the method (308) so uploads are not silently turned into GETs.
+async fn redirect_to_https(req: HttpRequest, https_port: Data<i32>) -> HttpResponse {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost");
+    let hostname = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    let port = match **https_port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, format!("https://{hostname}{port}{path}")))
+        .finish()
+}
+
+pub async fn start_actix_server(config: Config) {
     std::env::set_var("RUST_LOG", "actix_web=info,groot=info");
     pretty_env_logger::init();
-    let db_url = dotenv::var("DATABASE_URL").expect("DATABASE_URL");
-    let manager = ConnectionManager::<PgConnection>::new(db_url);
-    let db_pool = Pool::builder()
+    let manager = ConnectionManager::<DbConnection>::new(config.database.url.as_str());
+    let db_pool = pool_builder()
+        .max_size(config.database.pool_size)
         .build(manager)
         .expect("Error building a db connection pool");
-    let redis_url = dotenv::var("REDIS_URL").expect("REDIS_URL");
-    let manager =
-        RedisConnectionManager::new(redis_url).expect("Error with redis connection manager");
-    let redis_pool = Pool::builder()
-        .max_size(CACHE_POOL_MAX_OPEN)
-        .max_lifetime(Some(Duration::from_secs(CACHE_POOL_EXPIRE_SECONDS)))
-        .min_idle(Some(CACHE_POOL_MIN_IDLE))
-        .build(manager)
-        .expect("Error building a redis connection pool");
+    let tasks = Tasks::from_config(&config, &db_pool).expect("Error setting up the task store");
 
     std::fs::create_dir_all("content/collections").unwrap();
     std::fs::create_dir_all("content/roles").unwrap();
 
-    dotenv().ok();
-    let config = crate::config::Config::from_env().unwrap();
+    let config = Data::new(config);
+    let scheme = if config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
     info!(
-        "Starting server at http://{}:{}",
-        config.server.host, config.server.port
+        "Starting server at {}://{}:{}",
+        scheme, config.server.host, config.server.port
     );
-    HttpServer::new(move || {
+    let prefix = config.server.path_prefix();
+    let auth = config.auth.clone();
+    let app_config = config.clone();
+    let server = HttpServer::new(move || {
+        let prefix = prefix.clone();
+        let auth = auth.clone();
         App::new()
             .app_data(Data::new(db_pool.clone()))
-            .app_data(Data::new(redis_pool.clone()))
+            .app_data(Data::new(tasks.clone()))
+            .app_data(app_config.clone())
             .wrap_api()
+            .wrap(from_fn(move |req, next| {
+                authenticate(auth.clone(), req, next)
+            }))
             .wrap(NormalizePath::new(TrailingSlash::Always))
+            .wrap(from_fn(move |req, next| {
+                strip_path_prefix(prefix.clone(), req, next)
+            }))
             .wrap(Logger::default())
             .service(list_v1)
             .service(role_retrieve)
+            .service(role_update)
             .service(role_version_list)
             .service(list_v2)
             .service(task_list)
             .service(task_retrieve)
             .service(collection_list)
             .service(collection_retrieve)
+            .service(collection_update)
             .service(collection_version_retrieve)
             .service(collection_version_list)
+            .service(collection_version_search)
+            .service(role_search)
+            .service(namespace_list)
+            .service(namespace_retrieve)
+            .service(namespace_update)
+            .service(v1_namespace_list)
+            .service(v1_namespace_retrieve)
+            .service(repository_list)
+            .service(repository_api_root)
+            .service(repository_collection_list)
+            .service(repository_collection_retrieve)
+            .service(repository_collection_version_list)
+            .service(repository_collection_version_retrieve)
+            .service(repository_version_api_root)
+            .service(repository_version_collection_list)
+            .service(repository_version_collection_retrieve)
+            .service(repository_version_collection_version_list)
+            .service(repository_version_collection_version_retrieve)
+            .service(repository_version_list)
+            .service(repository_rollback)
+            .service(collection_version_move)
+            .service(collection_delete)
+            .service(collection_version_delete)
+            .service(role_delete)
+            .service(role_version_delete)
             .service(api_metadata)
             .service(api_status)
             .service(start_sync)
             .service(collection_import)
+            .service(collection_directory_import)
+            .service(role_import_status)
+            .service(user_list)
+            .service(user_create)
+            .service(user_token_create)
+            .service(user_token_revoke)
+            .service(token_rotate)
+            .service(group_list)
+            .service(group_create)
+            .service(group_member_add)
+            .service(group_member_remove)
+            .service(namespace_owner_list)
+            .service(namespace_owner_create)
+            .service(namespace_owner_update)
+            .service(audit_log_list)
+            .service(export_create)
+            .service(export_list)
+            .service(bundle_import)
+            .service(bundle_import_status)
             .with_json_spec_at("/api/spec/v2/")
             .with_swagger_ui_at("/openapi")
             .build()
             .service(start_req_sync)
             .service(collection_post)
-            .service(actix_files::Files::new("/content", "content").show_files_listing())
+            .service(role_import)
+            .service(collection_signature_upload)
+            .service(content_file)
+    });
+    let address = format!("{}:{}", config.server.host, config.server.port);
+    let Some(tls_config) = &config.tls else {
+        return server.bind(address).unwrap().run().await.unwrap();
+    };
+    let rustls_config = tls::server_config(tls_config).expect("Error loading TLS certificate");
+    let server = server
+        .bind_rustls_0_23(address, rustls_config)
+        .unwrap()
+        .run();
+    let Some(redirect_port) = tls_config.redirect_port else {
+        return server.await.unwrap();
+    };
+    info!(
+        "Redirecting http://{}:{} to HTTPS",
+        config.server.host, redirect_port
+    );
+    let https_port = config.server.port;
+    let redirect = HttpServer::new(move || {
+        App::new()
+            .app_data(Data::new(https_port))
+            .default_service(web::to(redirect_to_https))
     })
-    .bind(format!("{}:{}", config.server.host, config.server.port))
-    .unwrap()
-    .run()
-    .await
+    .bind(format!("{}:{}", config.server.host, redirect_port))
     .unwrap()
+    .run();
+    futures::future::try_join(server, redirect).await.unwrap();
 }
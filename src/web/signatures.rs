@@ -0,0 +1,150 @@
+use super::audit;
+use super::auth::{can_publish, Identity};
+use crate::db_utils::DbConnection;
+use crate::models::{CollectionSignature, CollectionSignatureNew};
+use crate::signatures;
+use actix_multipart::Multipart;
+use actix_web::{error, HttpResponse, Responder};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use futures::TryStreamExt;
+use paperclip::actix::web;
+use serde_json::{json, Value};
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+/// Armored detached signatures are well under a KiB; anything past this isn't one.
+const MAX_SIGNATURE_SIZE: usize = 8 * 1024;
+
+fn signature_json(signature: &CollectionSignature) -> Value {
+    json!({
+        "signature": signature.signature,
+        "pubkey_fingerprint": signature.pubkey_fingerprint,
+        "signing_service": signature.signing_service,
+        "pulp_created": signature.created_at,
+    })
+}
+
+/// The `signatures` of a version detail, as `ansible-galaxy --keyring` reads them.
+pub fn signatures_json(conn: &mut DbConnection, version_id: i32) -> Value {
+    use crate::schema::collection_signatures;
+    let results = collection_signatures::table
+        .filter(collection_signatures::collection_version_id.eq(version_id))
+        .select(CollectionSignature::as_select())
+        .order(collection_signatures::id.asc())
+        .load::<CollectionSignature>(conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    Value::Array(results.iter().map(signature_json).collect())
+}
+
+/// Attaches a detached, ASCII-armored signature of the version's
+/// `MANIFEST.json`, sent as the `file` field.
+#[actix_web::post("/api/v2/collections/{namespace}/{name}/versions/{version}/signatures/")]
+async fn collection_signature_upload(
+    identity: Identity,
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String, String)>,
+    mut payload: Multipart,
+) -> impl Responder {
+    use crate::schema::{collection_signatures, collection_versions, collections};
+    let (namespace, name, version) = path.into_inner();
+    let mut data = Vec::new();
+    loop {
+        let mut field = match payload.try_next().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => {
+                let msg = format!("Failed to read the upload: {err}.");
+                return HttpResponse::BadRequest().json(json!({ "detail": msg }));
+            }
+        };
+        if field.name() != Some("file") {
+            continue;
+        }
+        loop {
+            match field.try_next().await {
+                Ok(Some(chunk)) => {
+                    if data.len() + chunk.len() > MAX_SIGNATURE_SIZE {
+                        let msg =
+                            format!("The signature is over the {MAX_SIGNATURE_SIZE} byte limit.");
+                        return HttpResponse::PayloadTooLarge().json(json!({ "detail": msg }));
+                    }
+                    data.extend_from_slice(&chunk);
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    let msg = format!("Failed to read the upload: {err}.");
+                    return HttpResponse::BadRequest().json(json!({ "detail": msg }));
+                }
+            }
+        }
+    }
+    let mut conn = pool
+        .get()
+        .map_err(error::ErrorInternalServerError)
+        .expect("couldn't get db connection from pool");
+    if !can_publish(&mut conn, &identity, &namespace)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap()
+    {
+        let msg = format!("You are not allowed to sign content of the '{namespace}' namespace.");
+        return HttpResponse::Forbidden().json(json!({ "detail": msg }));
+    }
+    let Ok(signature) = String::from_utf8(data) else {
+        return HttpResponse::BadRequest().json(json!({"file": "Not an ASCII-armored signature."}));
+    };
+    let Some(fingerprint) = signatures::issuer(&signature) else {
+        return HttpResponse::BadRequest().json(json!({"file": "Not an ASCII-armored signature."}));
+    };
+    let version_id = collection_versions::table
+        .inner_join(collections::table)
+        .filter(collections::namespace.eq(&namespace))
+        .filter(collections::name.eq(&name))
+        .filter(collection_versions::version.eq(&version))
+        .select(collection_versions::id)
+        .first::<i32>(&mut conn)
+        .optional()
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    let Some(version_id) = version_id else {
+        return HttpResponse::NotFound().json(json!({"detail": "Not found."}));
+    };
+    let saved = conn
+        .transaction(|conn| {
+            let saved = signatures::save(
+                conn,
+                &[CollectionSignatureNew {
+                    collection_version_id: version_id,
+                    signature: &signature,
+                    pubkey_fingerprint: &fingerprint,
+                    signing_service: None,
+                }],
+            )?;
+            if saved > 0 {
+                audit::record(
+                    conn,
+                    &identity,
+                    "sign",
+                    &format!("collection_version:{namespace}.{name}:{version}"),
+                    &json!({ "pubkey_fingerprint": fingerprint }),
+                )?;
+            }
+            QueryResult::Ok(saved)
+        })
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    if saved == 0 {
+        return HttpResponse::Conflict().json(json!({
+            "detail": format!("{namespace}.{name}:{version} is already signed by {fingerprint}.")
+        }));
+    }
+    let created = collection_signatures::table
+        .filter(collection_signatures::collection_version_id.eq(version_id))
+        .filter(collection_signatures::pubkey_fingerprint.eq(&fingerprint))
+        .select(CollectionSignature::as_select())
+        .first::<CollectionSignature>(&mut conn)
+        .map_err(error::ErrorInternalServerError)
+        .unwrap();
+    HttpResponse::Created().json(signature_json(&created))
+}
@@ -0,0 +1,88 @@
+use crate::config::TlsConfig;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .with_context(|| format!("Failed to open certificate {cert_path}"))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse certificate {cert_path}"))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to parse private key {key_path}"))?;
+    let signing_key =
+        any_supported_type(&key).with_context(|| format!("Unsupported private key {key_path}"))?;
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Serves the certificate currently on disk, so renewed certificates are picked
+/// up without restarting groot.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCertResolver {
+    pub fn new(tls: &TlsConfig) -> Result<Self> {
+        let key = load_certified_key(&tls.cert_path, &tls.key_path)?;
+        Ok(ReloadingCertResolver {
+            cert_path: tls.cert_path.clone(),
+            key_path: tls.key_path.clone(),
+            current: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    /// Polls the certificate and key files and swaps in the new pair when either
+    /// changes. A pair that fails to load is logged and the previous one kept.
+    pub fn watch(self: Arc<Self>, interval: Duration) {
+        std::thread::spawn(move || {
+            let mut last = (modified(&self.cert_path), modified(&self.key_path));
+            loop {
+                std::thread::sleep(interval);
+                let current = (modified(&self.cert_path), modified(&self.key_path));
+                if current == last {
+                    continue;
+                }
+                match load_certified_key(&self.cert_path, &self.key_path) {
+                    Ok(key) => {
+                        *self.current.write().unwrap() = Arc::new(key);
+                        last = current;
+                        info!("Reloaded TLS certificate from {}", self.cert_path);
+                    }
+                    Err(e) => warn!("Keeping previous TLS certificate: {e:#}"),
+                }
+            }
+        });
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+pub fn server_config(tls: &TlsConfig) -> Result<ServerConfig> {
+    let resolver = Arc::new(ReloadingCertResolver::new(tls)?);
+    resolver
+        .clone()
+        .watch(Duration::from_secs(tls.reload_interval));
+    let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS protocol versions")?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    Ok(config)
+}
@@ -0,0 +1,109 @@
+use crate::config::{normalize_prefix, Config};
+use actix_web::{http::header, web, HttpRequest};
+
+/// Builds every link groot hands out, so they point at the address clients
+/// actually use rather than the address the server is bound to.
+#[derive(Debug, Clone)]
+pub struct UrlBuilder {
+    root: String,
+    prefix: String,
+    collections: String,
+}
+
+impl UrlBuilder {
+    pub fn new(root: &str, prefix: &str) -> Self {
+        UrlBuilder {
+            root: root.trim_end_matches('/').to_string(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+            collections: "/api/v2/collections".to_string(),
+        }
+    }
+
+    /// Points collection links at `/api/v3/content/<repository>/` instead of
+    /// the unscoped endpoints, which serve the `published` repository.
+    pub fn in_repository(mut self, repository: &str) -> Self {
+        self.collections = format!("/api/v3/content/{repository}/v2/collections");
+        self
+    }
+
+    /// Like `in_repository`, for a numbered version of the repository.
+    pub fn in_repository_version(mut self, repository: &str, number: i32) -> Self {
+        self.collections = format!("/api/v3/content/{repository}/versions/{number}/v2/collections");
+        self
+    }
+
+    /// Resolves the base URL in order of precedence: `SERVER.EXTERNAL_URL`,
+    /// proxy headers when `SERVER.TRUST_FORWARDED_HEADERS` is set, then the
+    /// request's `Host` header (or HTTP/2 authority), falling back to `SERVER.HOST`/`SERVER.PORT`.
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let config = req
+            .app_data::<web::Data<Config>>()
+            .expect("the server shares its configuration");
+        let mut prefix = config.server.path_prefix();
+        if let Some(external_url) = &config.server.external_url {
+            return UrlBuilder::new(external_url, &prefix);
+        }
+        if config.server.trust_forwarded_headers {
+            if let Some(forwarded) = req
+                .headers()
+                .get("x-forwarded-prefix")
+                .and_then(|h| h.to_str().ok())
+            {
+                prefix = normalize_prefix(forwarded);
+            }
+            let info = req.connection_info();
+            return UrlBuilder::new(&format!("{}://{}", info.scheme(), info.host()), &prefix);
+        }
+        let scheme = if req.app_config().secure() {
+            "https"
+        } else {
+            "http"
+        };
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| req.uri().authority().map(|a| a.to_string()))
+            .unwrap_or_else(|| format!("{}:{}", config.server.host, config.server.port));
+        UrlBuilder::new(&format!("{scheme}://{host}"), &prefix)
+    }
+
+    /// Links for work done outside a request, such as command line imports:
+    /// `SERVER.EXTERNAL_URL`, else `SERVER.HOST`/`SERVER.PORT`.
+    pub fn from_config(config: &Config) -> Self {
+        let prefix = config.server.path_prefix();
+        match &config.server.external_url {
+            Some(external_url) => UrlBuilder::new(external_url, &prefix),
+            None => UrlBuilder::new(
+                &format!("http://{}:{}", config.server.host, config.server.port),
+                &prefix,
+            ),
+        }
+    }
+
+    /// Server-relative link including the path prefix, as used in v3 `links`.
+    pub fn relative(&self, path: &str) -> String {
+        format!("{}{}", self.prefix, path)
+    }
+
+    pub fn absolute(&self, path: &str) -> String {
+        format!("{}{}{}", self.root, self.prefix, path)
+    }
+
+    pub fn collection(&self, namespace: &str, name: &str) -> String {
+        self.absolute(&format!("{}/{namespace}/{name}/", self.collections))
+    }
+
+    pub fn collection_version(&self, namespace: &str, name: &str, version: &str) -> String {
+        format!("{}versions/{}/", self.collection(namespace, name), version)
+    }
+
+    pub fn namespace(&self, name: &str) -> String {
+        self.absolute(&format!("/api/v3/namespaces/{name}/"))
+    }
+
+    pub fn content(&self, path: &str) -> String {
+        self.absolute(&format!("/content/{path}"))
+    }
+}